@@ -13,6 +13,11 @@ export default function StatusBadge({ status, className }: StatusBadgeProps) {
 			className: "bg-amber-500/20 text-amber-400 border-amber-500/50",
 			dotClassName: "bg-amber-400 animate-pulse",
 		},
+		Queued: {
+			label: "QUEUED",
+			className: "bg-sky-500/20 text-sky-400 border-sky-500/50",
+			dotClassName: "bg-sky-400 animate-pulse",
+		},
 		Running: {
 			label: "RUNNING",
 			className: "bg-emerald-500/20 text-emerald-400 border-emerald-500/50",
@@ -23,6 +28,11 @@ export default function StatusBadge({ status, className }: StatusBadgeProps) {
 			className: "bg-red-500/20 text-red-400 border-red-500/50",
 			dotClassName: "bg-red-400",
 		},
+		Stopped: {
+			label: "STOPPED",
+			className: "bg-slate-500/20 text-slate-300 border-slate-500/50",
+			dotClassName: "bg-slate-300",
+		},
 		Unknown: {
 			label: "UNKNOWN",
 			className: "bg-gray-500/20 text-gray-400 border-gray-500/50",
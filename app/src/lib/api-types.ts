@@ -1,11 +1,21 @@
 // API types matching backend src/api/types.rs
 
-export type PreviewStatus = "Building" | "Running" | "Failed" | "Unknown";
+export type PreviewStatus = "Building" | "Queued" | "Running" | "Failed" | "Stopped" | "Unknown";
+
+export interface ContainerStats {
+	cpuPercent: number;
+	memoryUsageBytes: number;
+	memoryLimitBytes: number;
+}
 
 export interface ContainerSummary {
 	name: string;
 	service: string;
 	state: string;
+	/** Only present from the containers endpoint, for running containers with a healthcheck */
+	health?: "healthy" | "unhealthy" | "starting";
+	/** Only present on the detail view and from the containers endpoint, for running containers */
+	stats?: ContainerStats;
 }
 
 export interface PreviewSummary {
@@ -13,6 +23,7 @@ export interface PreviewSummary {
 	composeId: string;
 	prId: string | null;
 	prTitle: string | null;
+	prAuthor: string | null;
 	branch: string;
 	status: PreviewStatus;
 	createdAt: string | null;
@@ -35,8 +46,13 @@ export interface DeploymentInfo {
 
 export interface PreviewListResponse {
 	previews: PreviewSummary[];
+	total: number;
+	/** False when spinploy has no Docker access, so `containers` are unknown rather than empty */
+	dockerAvailable: boolean;
 }
 
 export interface PreviewDetailResponse extends PreviewSummary {
 	deployments: DeploymentInfo[];
+	/** False when spinploy has no Docker access, so `containers` are unknown rather than empty */
+	dockerAvailable: boolean;
 }
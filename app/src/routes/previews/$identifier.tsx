@@ -363,7 +363,9 @@ function PreviewDetailPage() {
 							<div className="p-4">
 								{data.containers.length === 0 ? (
 									<div className="text-gray-500 font-mono text-sm text-center py-8">
-										No containers found
+										{data.dockerAvailable
+											? "No containers found"
+											: "Container info unavailable (no Docker access)"}
 									</div>
 								) : (
 									<div className="space-y-2">
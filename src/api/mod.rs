@@ -1,9 +1,11 @@
+#[cfg(feature = "openapi")]
+pub mod openapi;
 pub mod previews;
 pub mod types;
 
 use axum::{
-    routing::get,
     Router,
+    routing::{get, post},
 };
 
 use crate::AppState;
@@ -13,10 +15,40 @@ pub fn preview_routes() -> Router<AppState> {
     Router::new()
         .route("/previews", get(previews::list_previews))
         .route("/previews/{identifier}", get(previews::get_preview_detail))
+        .route(
+            "/previews/{identifier}/status",
+            get(previews::get_preview_status),
+        )
+        .route("/previews/{identifier}/env", get(previews::get_preview_env))
+        .route("/previews/{identifier}/raw", get(previews::get_preview_raw))
+        .route(
+            "/previews/{identifier}/env/preview",
+            get(previews::get_preview_env_preview),
+        )
+        .route(
+            "/previews/{identifier}/redeploy",
+            post(previews::redeploy_preview),
+        )
+        .route(
+            "/previews/{identifier}/containers",
+            get(previews::get_preview_containers),
+        )
         .route(
             "/previews/{identifier}/containers/{service}/logs",
             get(previews::stream_preview_container_logs),
         )
+        .route(
+            "/previews/{identifier}/containers/{service}/logs/ws",
+            get(previews::stream_preview_container_logs_ws),
+        )
+        .route(
+            "/previews/{identifier}/containers/{service}/logs/tail",
+            get(previews::get_preview_container_log_tail),
+        )
+        .route(
+            "/previews/{identifier}/containers/{service}/restart",
+            post(previews::restart_preview_container),
+        )
         .route(
             "/previews/{identifier}/deployments/{deployment_id}/logs",
             get(previews::stream_deployment_logs),
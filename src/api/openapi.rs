@@ -0,0 +1,73 @@
+use axum::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// OpenAPI document for the preview management API.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Spinploy", description = "Preview environment management API"),
+    paths(
+        crate::create_or_update_preview,
+        crate::delete_preview,
+        super::previews::list_previews,
+        super::previews::get_preview_detail,
+        super::previews::get_preview_status,
+        super::previews::get_preview_env,
+        super::previews::get_preview_env_preview,
+        super::previews::get_preview_raw,
+        super::previews::get_preview_containers,
+        super::previews::get_preview_container_log_tail,
+        super::previews::redeploy_preview,
+        super::previews::restart_preview_container,
+    ),
+    modifiers(&ApiKeyAuth),
+    security(("api_key" = []))
+)]
+pub struct ApiDoc;
+
+/// Declares the `x-api-key` header every endpoint requires.
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+    }
+}
+
+/// GET /openapi.json - The OpenAPI document
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_preview_endpoints_and_types() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        for path in [
+            "/api/previews",
+            "/api/previews/{identifier}",
+            "/api/previews/{identifier}/redeploy",
+        ] {
+            assert!(doc["paths"][path].is_object(), "missing {path}");
+        }
+        for schema in [
+            "PreviewSummary",
+            "PreviewDetailResponse",
+            "DeploymentInfo",
+            "ComposeCreateUpdateRequest",
+        ] {
+            assert!(
+                doc["components"]["schemas"][schema].is_object(),
+                "missing {schema}"
+            );
+        }
+    }
+}
@@ -1,18 +1,28 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
+use spinploy::docker_client::{ContainerHealth, ContainerStats};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewListResponse {
     pub previews: Vec<PreviewSummary>,
+    /// Number of previews matching the filters, before pagination
+    pub total: usize,
+    /// False when spinploy has no Docker access, so `containers` are unknown rather than empty
+    pub docker_available: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewSummary {
     pub identifier: String,
     pub compose_id: String,
     pub pr_id: Option<String>,
     pub pr_title: Option<String>,
+    pub pr_author: Option<String>,
     pub branch: String,
     pub status: PreviewStatus,
     pub created_at: Option<String>,
@@ -23,31 +33,106 @@ pub struct PreviewSummary {
     pub containers: Vec<ContainerSummary>,
 }
 
+/// Just a preview's status, for cheap polling (e.g. status badges).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewStatusResponse {
+    pub status: PreviewStatus,
+    pub last_deployed_at: Option<String>,
+}
+
+/// Env vars spinploy would generate for a preview. Only the derived values are
+/// included; project secrets stay as unresolved `${{project.*}}` references.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewEnvResponse {
+    pub identifier: String,
+    pub env: String,
+}
+
+/// Env vars currently set on a preview's compose, with secret-looking values masked.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewEnvVarsResponse {
+    pub identifier: String,
+    pub compose_id: String,
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct PreviewDetailResponse {
     #[serde(flatten)]
     pub summary: PreviewSummary,
     pub deployments: Vec<DeploymentInfo>,
+    /// False when spinploy has no Docker access, so `containers` are unknown rather than empty
+    pub docker_available: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub enum PreviewStatus {
+    #[serde(alias = "building")]
     Building,
+    /// Waiting for a deploy slot before it's triggered on Dokploy
+    #[serde(alias = "queued")]
+    Queued,
+    #[serde(alias = "running")]
     Running,
+    #[serde(alias = "failed")]
     Failed,
+    /// Deployed successfully, but its containers have since been stopped
+    #[serde(alias = "stopped")]
+    Stopped,
+    #[serde(alias = "unknown")]
     Unknown,
 }
 
+/// How container log lines are sent over SSE.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Raw Docker output, timestamp glued to the message
+    #[default]
+    Text,
+    /// JSON `{ timestamp, stream, message }` per line
+    Json,
+}
+
+/// How a preview is redeployed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RedeployMode {
+    /// Pull the source and rebuild images, like a push does
+    #[default]
+    Rebuild,
+    /// Restart the existing containers without rebuilding
+    Reload,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct ContainerSummary {
     pub name: String,
     pub service: String,
     pub state: String,
+    /// Healthcheck result; only filled in by the containers endpoint, for running
+    /// containers that have a healthcheck
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<ContainerHealth>,
+    /// Only filled in on the detail view and by the containers endpoint, for running containers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ContainerStats>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct DeploymentInfo {
     pub deployment_id: String,
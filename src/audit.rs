@@ -0,0 +1,194 @@
+use std::fmt::Display;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// What was done to a preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    /// Created, or redeployed if it already existed
+    Deploy,
+    Delete,
+    /// Deleted to stay under the preview limit
+    Prune,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    /// Who triggered it, e.g. `api`, `azure:jane@example.com` or `reconcile`
+    pub actor: String,
+    pub identifier: String,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEvent {
+    /// Event for an action that just finished with `result`.
+    pub fn new<T, E: Display>(
+        actor: &str,
+        identifier: &str,
+        action: AuditAction,
+        result: &Result<T, E>,
+    ) -> Self {
+        let (outcome, error) = match result {
+            Ok(_) => (AuditOutcome::Success, None),
+            Err(e) => (AuditOutcome::Failure, Some(e.to_string())),
+        };
+        Self {
+            timestamp: Utc::now(),
+            actor: actor.to_string(),
+            identifier: identifier.to_string(),
+            action,
+            outcome,
+            error,
+        }
+    }
+}
+
+/// Where audit events are appended: the `AUDIT_LOG_PATH` file, or nowhere. Clones
+/// share the file.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl AuditLog {
+    /// Opens `path` for appending. Without a path, events are dropped.
+    pub async fn open(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(file))),
+        })
+    }
+
+    /// This log, recording events on behalf of `actor`.
+    pub fn actor(&self, actor: impl Into<String>) -> Auditor {
+        Auditor {
+            log: self.clone(),
+            actor: actor.into(),
+        }
+    }
+
+    /// Appends `event` as one JSON line. Write failures are logged, not returned, so
+    /// auditing never fails the action itself.
+    pub async fn record(&self, event: AuditEvent) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut line = serde_json::to_string(&event).expect("audit events serialize");
+        line.push('\n');
+
+        let mut file = file.lock().await;
+        let written = match file.write_all(line.as_bytes()).await {
+            Ok(()) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            tracing::warn!(error = %e, ?event, "Failed to write audit log");
+        }
+    }
+}
+
+/// An [`AuditLog`] recording events on behalf of one actor.
+#[derive(Debug, Clone)]
+pub struct Auditor {
+    log: AuditLog,
+    actor: String,
+}
+
+impl Auditor {
+    /// Who the events are recorded for, e.g. `api` or `github:octocat`
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// Records that `action` on `identifier` just finished with `result`.
+    pub async fn record<T, E: Display>(
+        &self,
+        identifier: &str,
+        action: AuditAction,
+        result: &Result<T, E>,
+    ) {
+        let event = AuditEvent::new(&self.actor, identifier, action, result);
+        self.log.record(event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_round_trip_as_json_lines() {
+        let failed = AuditEvent::new::<(), _>(
+            "azure:jane@example.com",
+            "pr-42",
+            AuditAction::Deploy,
+            &Err("dokploy unreachable"),
+        );
+        let line = serde_json::to_string(&failed).unwrap();
+
+        assert!(!line.contains('\n'));
+        assert!(line.contains(r#""action":"deploy","outcome":"failure""#));
+        assert_eq!(serde_json::from_str::<AuditEvent>(&line).unwrap(), failed);
+
+        let deleted = AuditEvent::new::<_, String>("api", "pr-42", AuditAction::Delete, &Ok(()));
+        let line = serde_json::to_string(&deleted).unwrap();
+
+        assert!(!line.contains("error"));
+        assert_eq!(serde_json::from_str::<AuditEvent>(&line).unwrap(), deleted);
+    }
+
+    #[tokio::test]
+    async fn records_are_appended_as_json_lines() {
+        let path = std::env::temp_dir().join(format!("spinploy-audit-test-{}", std::process::id()));
+        let log = AuditLog::open(Some(&path)).await.unwrap();
+
+        let audit = log.actor("api");
+        audit
+            .record::<_, String>("pr-1", AuditAction::Deploy, &Ok(()))
+            .await;
+        audit
+            .record::<(), _>("pr-2", AuditAction::Delete, &Err("gone"))
+            .await;
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let events: Vec<AuditEvent> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            (events[0].actor.as_str(), events[0].identifier.as_str()),
+            ("api", "pr-1")
+        );
+        assert_eq!(events[1].error.as_deref(), Some("gone"));
+    }
+}
@@ -1,14 +1,28 @@
-use std::time::Duration;
-
+use crate::config::HttpTimeouts;
 use crate::models::azure::{
-    AzureBuildDetail, AzureBuildListItem, AzureBuildListResponse, AzureBuildTimeline, AzureCommit,
-    AzurePullRequestDetail,
+    AzureBuildDetail, AzureBuildListItem, AzureBuildListResponse, AzureBuildTimeline,
+    AzureCommentRef, AzureCommit, AzurePrStatusContext, AzurePrStatusRequest, AzurePrStatusState,
+    AzurePullRequestDetail, AzurePullRequestListItem, AzurePullRequestListResponse, AzureThread,
+    AzureThreadComment,
 };
-use anyhow::Result;
+use crate::retry::SendWithRetry;
+use anyhow::{Context, Result};
+
+/// Page size when listing pull requests
+const PULL_REQUEST_PAGE_SIZE: usize = 100;
+
+/// Genre/name of the PR status check reflecting the preview deploy
+pub const PR_STATUS_GENRE: &str = "spinploy";
+pub const PR_STATUS_NAME: &str = "preview";
 
-/// Minimal Azure DevOps REST client for posting PR thread comments
+/// Azure DevOps Services; on-prem Azure DevOps Server uses its own host
+pub const DEFAULT_BASE_URL: &str = "https://dev.azure.com";
+
+/// Minimal Azure DevOps REST client for posting PR thread comments. Requests Azure
+/// answers with 429 or 5xx are retried (see [`crate::retry`]).
 #[derive(Clone, Debug)]
 pub struct AzureDevOpsClient {
+    base_url: String,
     pub org: String,
     pub project: String,
     pat: String,
@@ -16,13 +30,20 @@ pub struct AzureDevOpsClient {
 }
 
 impl AzureDevOpsClient {
-    pub fn new(org: impl AsRef<str>, project: impl AsRef<str>, pat: impl AsRef<str>) -> Self {
+    pub fn new(
+        base_url: impl AsRef<str>,
+        org: impl AsRef<str>,
+        project: impl AsRef<str>,
+        pat: impl AsRef<str>,
+        timeouts: HttpTimeouts,
+    ) -> Self {
         let reqw_client = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(15))
-            .timeout(Duration::from_secs(30))
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.total)
             .build()
             .expect("failed to build http client");
         Self {
+            base_url: base_url.as_ref().to_string(),
             org: org.as_ref().to_string(),
             project: project.as_ref().to_string(),
             pat: pat.as_ref().to_string(),
@@ -30,17 +51,25 @@ impl AzureDevOpsClient {
         }
     }
 
-    /// Post a text reply inside an existing PR comment thread
+    fn project_url(&self) -> String {
+        project_url(&self.base_url, &self.org, &self.project)
+    }
+
+    /// Post a text reply inside an existing PR comment thread. Returns where the reply
+    /// is, to edit it later with [`update_comment`](Self::update_comment).
     pub async fn reply_in_thread(
         &self,
         repo_id: &str,
         pr_id: u64,
         thread_id: u64,
         content: &str,
-    ) -> Result<()> {
+    ) -> Result<AzureCommentRef> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/git/repositories/{}/pullRequests/{}/threads/{}/comments?api-version=7.1-preview.1",
-            self.org, self.project, repo_id, pr_id, thread_id
+            "{}/_apis/git/repositories/{}/pullRequests/{}/threads/{}/comments?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            pr_id,
+            thread_id
         );
 
         let body = serde_json::json!({
@@ -48,30 +77,96 @@ impl AzureDevOpsClient {
             "commentType": "text",
         });
 
-        self.client
+        let response = self
+            .client
             .post(url)
             // PAT as Basic password; username can be empty
             .basic_auth("", Some(&self.pat))
             .json(&body)
-            .send()
+            .send_with_retry()
             .await?
-            .error_for_status()?;
+            .error_for_status()?
+            .text()
+            .await?;
 
-        Ok(())
+        parse_reply(thread_id, &response)
+    }
+
+    /// Replace the text of a comment in a PR thread, e.g. a reply whose deploy has
+    /// since finished. Returns the updated comment.
+    pub async fn update_comment(
+        &self,
+        repo_id: &str,
+        pr_id: u64,
+        thread_id: u64,
+        comment_id: u64,
+        content: &str,
+    ) -> Result<AzureThreadComment> {
+        let url = format!(
+            "{}/_apis/git/repositories/{}/pullRequests/{}/threads/{}/comments/{}?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            pr_id,
+            thread_id,
+            comment_id
+        );
+
+        let comment = self
+            .client
+            .patch(url)
+            .basic_auth("", Some(&self.pat))
+            .json(&update_comment_request(content))
+            .send_with_retry()
+            .await?
+            .error_for_status()?
+            .json::<AzureThreadComment>()
+            .await?;
+
+        Ok(comment)
+    }
+
+    /// Start a new top-level comment thread on a PR, for notifications that
+    /// aren't a reply to anything. Returns where its comment is, to edit it later.
+    pub async fn create_thread(
+        &self,
+        repo_id: &str,
+        pr_id: u64,
+        content: &str,
+    ) -> Result<AzureCommentRef> {
+        let url = format!(
+            "{}/_apis/git/repositories/{}/pullRequests/{}/threads?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            pr_id
+        );
+
+        let response = self
+            .client
+            .post(url)
+            .basic_auth("", Some(&self.pat))
+            .json(&new_thread_request(content))
+            .send_with_retry()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        parse_thread(&response)
     }
 
     /// Fetch build details to obtain sourceVersion, repository id, build number and result.
     pub async fn get_build(&self, build_id: u64) -> Result<AzureBuildDetail> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/build/builds/{}?api-version=7.1-preview.7",
-            self.org, self.project, build_id
+            "{}/_apis/build/builds/{}?api-version=7.1-preview.7",
+            self.project_url(),
+            build_id
         );
 
         let resp = self
             .client
             .get(url)
             .basic_auth("", Some(&self.pat))
-            .send()
+            .send_with_retry()
             .await?
             .error_for_status()?
             .json::<AzureBuildDetail>()
@@ -83,15 +178,16 @@ impl AzureDevOpsClient {
     /// Fetch build timeline to inspect stage/job results.
     pub async fn get_build_timeline(&self, build_id: u64) -> Result<AzureBuildTimeline> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/build/builds/{}/timeline?api-version=7.1-preview.2",
-            self.org, self.project, build_id
+            "{}/_apis/build/builds/{}/timeline?api-version=7.1-preview.2",
+            self.project_url(),
+            build_id
         );
 
         let resp = self
             .client
             .get(url)
             .basic_auth("", Some(&self.pat))
-            .send()
+            .send_with_retry()
             .await?
             .error_for_status()?
             .json::<AzureBuildTimeline>()
@@ -103,15 +199,17 @@ impl AzureDevOpsClient {
     /// Fetch commit details to get commit author information.
     pub async fn get_commit(&self, repo_id: &str, commit_sha: &str) -> Result<AzureCommit> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/git/repositories/{}/commits/{}?api-version=7.1-preview.1",
-            self.org, self.project, repo_id, commit_sha
+            "{}/_apis/git/repositories/{}/commits/{}?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            commit_sha
         );
 
         let resp = self
             .client
             .get(url)
             .basic_auth("", Some(&self.pat))
-            .send()
+            .send_with_retry()
             .await?
             .error_for_status()?
             .json::<AzureCommit>()
@@ -128,8 +226,8 @@ impl AzureDevOpsClient {
         top: u64,
     ) -> Result<Vec<AzureBuildListItem>> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/build/builds?api-version=7.1-preview.7",
-            self.org, self.project
+            "{}/_apis/build/builds?api-version=7.1-preview.7",
+            self.project_url()
         );
 
         let resp = self
@@ -143,7 +241,7 @@ impl AzureDevOpsClient {
                 ("queryOrder", "finishTimeDescending".to_string()),
                 ("$top", top.to_string()),
             ])
-            .send()
+            .send_with_retry()
             .await?
             .error_for_status()?
             .json::<AzureBuildListResponse>()
@@ -152,22 +250,75 @@ impl AzureDevOpsClient {
         Ok(resp.value)
     }
 
-    /// Fetch pull request details to get title.
+    /// List all of a repository's pull requests with the given status (`active`,
+    /// `completed`, `abandoned` or `all`), following pages until the last one.
+    pub async fn list_pull_requests(
+        &self,
+        repo_id: &str,
+        status: &str,
+    ) -> Result<Vec<AzurePullRequestListItem>> {
+        let url = format!(
+            "{}/_apis/git/repositories/{}/pullrequests?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id
+        );
+
+        let mut pull_requests = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("searchCriteria.status", status.to_string()),
+                ("$top", PULL_REQUEST_PAGE_SIZE.to_string()),
+            ];
+            match &continuation_token {
+                Some(token) => query.push(("continuationToken", token.clone())),
+                None => query.push(("$skip", pull_requests.len().to_string())),
+            }
+
+            let resp = self
+                .client
+                .get(&url)
+                .basic_auth("", Some(&self.pat))
+                .query(&query)
+                .send_with_retry()
+                .await?
+                .error_for_status()?;
+            // Some Azure DevOps versions page with a continuation token header,
+            // others only by `$skip`; a short page means there's nothing after it
+            continuation_token = resp
+                .headers()
+                .get("x-ms-continuationtoken")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let page = resp.json::<AzurePullRequestListResponse>().await?.value;
+
+            let last_page = page.len() < PULL_REQUEST_PAGE_SIZE && continuation_token.is_none();
+            let empty = page.is_empty();
+            pull_requests.extend(page);
+            if last_page || empty {
+                return Ok(pull_requests);
+            }
+        }
+    }
+
+    /// Fetch pull request details to get its title and author.
     pub async fn get_pull_request(
         &self,
         repo_id: &str,
         pr_id: u64,
     ) -> Result<AzurePullRequestDetail> {
         let url = format!(
-            "https://dev.azure.com/{}/{}/_apis/git/repositories/{}/pullRequests/{}?api-version=7.1-preview.1",
-            self.org, self.project, repo_id, pr_id
+            "{}/_apis/git/repositories/{}/pullRequests/{}?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            pr_id
         );
 
         let resp = self
             .client
             .get(url)
             .basic_auth("", Some(&self.pat))
-            .send()
+            .send_with_retry()
             .await?
             .error_for_status()?
             .json::<AzurePullRequestDetail>()
@@ -175,4 +326,422 @@ impl AzureDevOpsClient {
 
         Ok(resp)
     }
+
+    /// Set the preview status check on a PR. Reuses the same genre/name so
+    /// each call replaces the previous state.
+    pub async fn set_pr_status(
+        &self,
+        repo_id: &str,
+        pr_id: u64,
+        state: AzurePrStatusState,
+        description: &str,
+        target_url: Option<&str>,
+    ) -> Result<()> {
+        let url = format!(
+            "{}/_apis/git/repositories/{}/pullRequests/{}/statuses?api-version=7.1-preview.1",
+            self.project_url(),
+            repo_id,
+            pr_id
+        );
+
+        self.client
+            .post(url)
+            .basic_auth("", Some(&self.pat))
+            .json(&pr_status_request(state, description, target_url))
+            .send_with_retry()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// `{base_url}/{org}/{project}`, tolerating a trailing slash on the base. On Azure
+/// DevOps Server the org is the collection (e.g. `https://tfs.example.com/tfs` + `DefaultCollection`).
+pub fn project_url(base_url: &str, org: &str, project: &str) -> String {
+    format!("{}/{}/{}", base_url.trim_end_matches('/'), org, project)
+}
+
+/// A thread holding a single text comment, left open
+fn new_thread_request(content: &str) -> serde_json::Value {
+    serde_json::json!({
+        "comments": [{
+            "parentCommentId": 0,
+            "content": content,
+            "commentType": "text",
+        }],
+        "status": "active",
+    })
+}
+
+/// Where the comment created in `thread_id` is, from the create comment response
+fn parse_reply(thread_id: u64, response: &str) -> Result<AzureCommentRef> {
+    let comment: AzureThreadComment =
+        serde_json::from_str(response).context("failed to parse created comment")?;
+    Ok(AzureCommentRef {
+        thread_id,
+        comment_id: comment.id,
+    })
+}
+
+/// Where the root comment of a created thread is, from the create thread response
+fn parse_thread(response: &str) -> Result<AzureCommentRef> {
+    let thread: AzureThread =
+        serde_json::from_str(response).context("failed to parse created thread")?;
+    let comment = thread
+        .comments
+        .first()
+        .context("created thread has no comment")?;
+    Ok(AzureCommentRef {
+        thread_id: thread.id,
+        comment_id: comment.id,
+    })
+}
+
+/// Only `content` can be changed on an existing comment
+fn update_comment_request(content: &str) -> serde_json::Value {
+    serde_json::json!({ "content": content })
+}
+
+fn pr_status_request(
+    state: AzurePrStatusState,
+    description: &str,
+    target_url: Option<&str>,
+) -> AzurePrStatusRequest {
+    AzurePrStatusRequest {
+        state,
+        description: description.to_string(),
+        target_url: target_url.map(str::to_string),
+        context: AzurePrStatusContext {
+            genre: PR_STATUS_GENRE.to_string(),
+            name: PR_STATUS_NAME.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pr_status_body_has_stable_context() {
+        let body = serde_json::to_value(pr_status_request(
+            AzurePrStatusState::Succeeded,
+            "Preview deployed",
+            Some("https://pr-1.preview.example.com"),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "state": "succeeded",
+                "description": "Preview deployed",
+                "targetUrl": "https://pr-1.preview.example.com",
+                "context": { "genre": "spinploy", "name": "preview" },
+            })
+        );
+    }
+
+    #[test]
+    fn parses_the_created_reply() {
+        let response = r#"{
+            "id": 3,
+            "parentCommentId": 0,
+            "author": { "displayName": "Spinploy", "id": "a1b2" },
+            "content": "👷 Preview building",
+            "publishedDate": "2024-05-01T10:00:00Z",
+            "commentType": "text",
+            "_links": { "threads": { "href": "https://dev.azure.com/org/project/_apis/git/repositories/repo/pullRequests/7/threads/12" } }
+        }"#;
+
+        assert_eq!(
+            parse_reply(12, response).unwrap(),
+            AzureCommentRef {
+                thread_id: 12,
+                comment_id: 3
+            }
+        );
+        assert!(parse_reply(12, r#"{ "content": "no id" }"#).is_err());
+    }
+
+    #[test]
+    fn parses_the_created_thread() {
+        let response = r#"{
+            "id": 12,
+            "comments": [{ "id": 1, "parentCommentId": 0, "content": "🔄 Preview redeploying" }],
+            "status": "active"
+        }"#;
+
+        assert_eq!(
+            parse_thread(response).unwrap(),
+            AzureCommentRef {
+                thread_id: 12,
+                comment_id: 1
+            }
+        );
+        assert!(parse_thread(r#"{ "id": 12, "comments": [] }"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn replies_are_retried_when_rate_limited() {
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let requests = std::sync::Arc::new(AtomicU32::new(0));
+        let app = axum::Router::new().route(
+            "/org/project/_apis/git/repositories/repo/pullRequests/7/threads/12/comments",
+            axum::routing::post({
+                let requests = requests.clone();
+                move || async move {
+                    if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")])
+                            .into_response();
+                    }
+                    axum::Json(serde_json::json!({ "id": 3, "content": "hi" })).into_response()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = AzureDevOpsClient::new(
+            format!("http://{addr}"),
+            "org",
+            "project",
+            "pat",
+            HttpTimeouts::default(),
+        );
+
+        let reply = client.reply_in_thread("repo", 7, 12, "hi").await.unwrap();
+
+        assert_eq!(reply.comment_id, 3);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn update_comment_body_only_has_the_content() {
+        assert_eq!(
+            update_comment_request("✅ Preview deployed"),
+            serde_json::json!({ "content": "✅ Preview deployed" })
+        );
+    }
+
+    #[tokio::test]
+    async fn list_pull_requests_follows_pages() {
+        use axum::extract::Query;
+        use std::collections::HashMap;
+
+        // 250 PRs served in pages of `$top`, starting at `$skip`
+        async fn pull_requests(
+            Query(query): Query<HashMap<String, String>>,
+        ) -> axum::Json<serde_json::Value> {
+            let skip: u64 = query["$skip"].parse().unwrap();
+            let top: u64 = query["$top"].parse().unwrap();
+            let value: Vec<_> = (skip..250.min(skip + top))
+                .map(|id| {
+                    serde_json::json!({
+                        "pullRequestId": id,
+                        "sourceRefName": format!("refs/heads/feature/{id}"),
+                        "targetRefName": "refs/heads/main",
+                        "title": format!("PR {id}"),
+                        "status": "active",
+                    })
+                })
+                .collect();
+            axum::Json(serde_json::json!({ "value": value, "count": value.len() }))
+        }
+
+        let app = axum::Router::new().route(
+            "/org/project/_apis/git/repositories/repo/pullrequests",
+            axum::routing::get(pull_requests),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = AzureDevOpsClient::new(
+            format!("http://{addr}"),
+            "org",
+            "project",
+            "pat",
+            HttpTimeouts::default(),
+        );
+
+        let prs = client.list_pull_requests("repo", "active").await.unwrap();
+
+        assert_eq!(prs.len(), 250);
+        assert_eq!(prs[249].pull_request_id, 249);
+        assert_eq!(prs[0].title.as_deref(), Some("PR 0"));
+        assert_eq!(prs[0].target_ref_name.as_deref(), Some("refs/heads/main"));
+    }
+
+    #[test]
+    fn project_url_for_cloud_and_server() {
+        assert_eq!(
+            project_url(DEFAULT_BASE_URL, "acme", "web"),
+            "https://dev.azure.com/acme/web"
+        );
+        assert_eq!(
+            project_url("https://tfs.example.com/tfs/", "DefaultCollection", "web"),
+            "https://tfs.example.com/tfs/DefaultCollection/web"
+        );
+    }
+
+    #[test]
+    fn new_thread_body_has_one_root_comment() {
+        let body = new_thread_request("Preview redeployed");
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "comments": [{
+                    "parentCommentId": 0,
+                    "content": "Preview redeployed",
+                    "commentType": "text",
+                }],
+                "status": "active",
+            })
+        );
+    }
+
+    #[test]
+    fn pr_status_body_omits_missing_target_url() {
+        let body = serde_json::to_value(pr_status_request(
+            AzurePrStatusState::Pending,
+            "Deploying",
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(body["state"], "pending");
+        assert!(body.get("targetUrl").is_none());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires AZDO_* environment variables and an open PR
+    async fn test_set_pr_status() {
+        crate::test_init_env();
+        let var = |name: &str| std::env::var(name).unwrap();
+        let client = AzureDevOpsClient::new(
+            std::env::var("AZDO_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            var("AZDO_ORG"),
+            var("AZDO_PROJECT"),
+            var("AZDO_PAT"),
+            HttpTimeouts::default(),
+        );
+        let pr_id = var("AZDO_TEST_PR_ID").parse().unwrap();
+
+        let res = client
+            .set_pr_status(
+                &var("AZDO_REPOSITORY_ID"),
+                pr_id,
+                AzurePrStatusState::Pending,
+                "Integration test",
+                None,
+            )
+            .await;
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires AZDO_* environment variables and an open PR
+    async fn test_get_pull_request() {
+        crate::test_init_env();
+        let var = |name: &str| std::env::var(name).unwrap();
+        let client = AzureDevOpsClient::new(
+            std::env::var("AZDO_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            var("AZDO_ORG"),
+            var("AZDO_PROJECT"),
+            var("AZDO_PAT"),
+            HttpTimeouts::default(),
+        );
+        let pr_id = var("AZDO_TEST_PR_ID").parse().unwrap();
+
+        let pr = client
+            .get_pull_request(&var("AZDO_REPOSITORY_ID"), pr_id)
+            .await
+            .unwrap();
+        assert!(!pr.title.is_empty());
+        assert!(
+            pr.created_by
+                .is_some_and(|author| !author.display_name.is_empty())
+        );
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires AZDO_* environment variables and an open PR; posts a comment
+    async fn test_create_thread() {
+        crate::test_init_env();
+        let var = |name: &str| std::env::var(name).unwrap();
+        let client = AzureDevOpsClient::new(
+            std::env::var("AZDO_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            var("AZDO_ORG"),
+            var("AZDO_PROJECT"),
+            var("AZDO_PAT"),
+            HttpTimeouts::default(),
+        );
+        let pr_id = var("AZDO_TEST_PR_ID").parse().unwrap();
+
+        let res = client
+            .create_thread(&var("AZDO_REPOSITORY_ID"), pr_id, "Integration test")
+            .await;
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires AZDO_* environment variables and an open PR thread; posts and edits a comment
+    async fn test_reply_in_thread() {
+        crate::test_init_env();
+        let var = |name: &str| std::env::var(name).unwrap();
+        let client = AzureDevOpsClient::new(
+            std::env::var("AZDO_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            var("AZDO_ORG"),
+            var("AZDO_PROJECT"),
+            var("AZDO_PAT"),
+            HttpTimeouts::default(),
+        );
+        let repo_id = var("AZDO_REPOSITORY_ID");
+        let pr_id = var("AZDO_TEST_PR_ID").parse().unwrap();
+        let thread_id = var("AZDO_TEST_THREAD_ID").parse().unwrap();
+
+        let reply = client
+            .reply_in_thread(&repo_id, pr_id, thread_id, "Integration test")
+            .await
+            .unwrap();
+        assert_eq!(reply.thread_id, thread_id);
+
+        let updated = client
+            .update_comment(
+                &repo_id,
+                pr_id,
+                thread_id,
+                reply.comment_id,
+                "Integration test, edited",
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.id, reply.comment_id);
+        assert_eq!(updated.content.as_deref(), Some("Integration test, edited"));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires AZDO_* environment variables
+    async fn test_list_pull_requests() {
+        crate::test_init_env();
+        let var = |name: &str| std::env::var(name).unwrap();
+        let client = AzureDevOpsClient::new(
+            std::env::var("AZDO_BASE_URL").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            var("AZDO_ORG"),
+            var("AZDO_PROJECT"),
+            var("AZDO_PAT"),
+            HttpTimeouts::default(),
+        );
+
+        let prs = client
+            .list_pull_requests(&var("AZDO_REPOSITORY_ID"), "all")
+            .await
+            .unwrap();
+        assert!(prs.iter().all(|pr| pr.source_ref_name.starts_with("refs/")));
+    }
 }
@@ -1,25 +1,144 @@
-use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
 use config::{Config as ConfigBuilder, Environment};
-use serde::Deserialize;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::azure_client::project_url;
+use crate::dokploy_client::ComposeScope;
+use crate::models::dokploy::{ComposeSource, ComposeType, SourceType};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub dokploy_url: String,
+    // Optional server-side Dokploy key, used by the readiness probe and for requests
+    // authenticated with a management key
+    #[serde(default)]
+    pub dokploy_api_key: Option<String>,
+    // Keys accepted on management routes. Comma-separated in the environment; when empty,
+    // incoming keys are Dokploy keys, validated against and passed through to Dokploy
+    #[serde(default, deserialize_with = "deserialize_comma_list")]
+    pub api_keys: Vec<String>,
+    // With `api_keys` set, still accept Dokploy keys that aren't management keys
+    #[serde(default)]
+    pub api_key_passthrough: bool,
     pub project_id: String,
     pub environment_id: String,
     pub custom_git_url: String,
     pub custom_git_ssh_key_id: String,
     pub compose_path: String,
+    // Dokploy compose type: "docker-compose" (default) or "stack" for Swarm
+    #[serde(default)]
+    pub compose_type: ComposeType,
+    // Where previews' compose file comes from: "git" (default) checks out the PR branch
+    // of `custom_git_url`, "raw" stores `raw_compose_file` itself in Dokploy
+    #[serde(default)]
+    pub source_type: SourceType,
+    #[serde(default)]
+    pub raw_compose_file: Option<String>,
     pub base_domain: String,
     pub frontend_service_name: String,
     pub frontend_port: u16,
     pub backend_service_name: String,
     pub backend_port: u16,
-    // Azure DevOps configuration for posting PR comments
+    // Docker daemon to read containers from (`unix://`, `tcp://`, `http://` or `https://`);
+    // the local socket when unset. With DOCKER_CERT_PATH, TCP connections use TLS with the
+    // client certificate `key.pem`/`cert.pem` and CA `ca.pem` from that directory
+    pub docker_host: Option<String>,
+    pub docker_cert_path: Option<String>,
+    // Start of the Dokploy app name of every compose spinploy creates; listing, pruning,
+    // reconciling and lookups by name only consider composes with it, so bots sharing a
+    // Dokploy need distinct ones
+    #[serde(default = "default_app_name_prefix")]
+    pub app_name_prefix: String,
+    // Docker container name of a compose service; `{app_name}` and `{service}` are substituted
+    #[serde(default = "default_container_name_template")]
+    pub container_name_template: String,
+    // Log lines buffered per log stream before reading from Docker waits on the client
+    #[serde(default = "default_log_buffer_size")]
+    pub log_buffer_size: usize,
+    // Per-repository overrides of the settings above, keyed by repository id or name
+    // (Azure repo id/name, GitHub `owner/repo`, GitLab `group/project`). Set either as
+    // nested env vars (`REPOSITORIES__<KEY>__BASE_DOMAIN`) or as a JSON object in `REPOSITORIES`
+    #[serde(default, deserialize_with = "deserialize_repository_overrides")]
+    pub repositories: HashMap<String, RepositoryOverrides>,
+    // When set, pruning only logs the previews it would delete
+    #[serde(default)]
+    pub prune_dry_run: bool,
+    // Comment on the Azure PR (in `azdo_repository_id`) of a pruned preview that it was
+    // removed and how to get it back
+    #[serde(default)]
+    pub prune_pr_comments: bool,
+    // Preview identifiers never pruned automatically; `*` matches any run of characters
+    // (e.g. `pr-42,br-release-*`). Comma-separated in the environment
+    #[serde(default, deserialize_with = "deserialize_comma_list")]
+    pub prune_protect: Vec<String>,
+    // Users allowed to run PR comment slash commands, by unique name (usually the email)
+    // or identity id, case-insensitive. Comma-separated in the environment; empty allows everyone
+    #[serde(default, deserialize_with = "deserialize_comma_list")]
+    pub allowed_command_users: Vec<String>,
+    // Hosts of the frontend and backend, with `{identifier}` and `{base_domain}` placeholders
+    #[serde(default = "default_frontend_subdomain_template")]
+    pub frontend_subdomain_template: String,
+    #[serde(default = "default_backend_subdomain_template")]
+    pub backend_subdomain_template: String,
+    // Services beyond the frontend and backend that also get a preview domain. A JSON
+    // array in the environment, e.g. `[{"name": "worker", "port": 9000}]`
+    #[serde(default, deserialize_with = "deserialize_services")]
+    pub services: Vec<ServiceConfig>,
+    // Certificate Dokploy provisions for each preview domain: `none` (default) relies on
+    // an existing wildcard cert, `letsencrypt` gets one per host
+    #[serde(default)]
+    pub certificate_type: CertificateType,
+    // Guard against misconfigured service lists minting many domains per preview
+    #[serde(default = "default_max_domains_per_preview")]
+    pub max_domains_per_preview: usize,
+    // Env file written to new previews; see `DEFAULT_ENV_TEMPLATE` for the placeholders
+    #[serde(default = "default_env_template")]
+    pub env_template: String,
+    // Fixed `KEY=VALUE` vars appended to every new preview's env, in the order given.
+    // Newline-separated in the environment, like an env file
+    #[serde(default, deserialize_with = "deserialize_env_pairs")]
+    pub extra_env: Vec<(String, String)>,
+    // Traefik basic-auth users (`user:hash`, comma-separated, as htpasswd writes them)
+    // written to new previews' env as `PREVIEW_BASIC_AUTH`, for the compose file's
+    // Traefik labels to put in front of the preview. Unset leaves previews public
+    #[serde(default)]
+    pub preview_basic_auth: Option<String>,
+    // Azure DevOps configuration for posting PR comments. The base URL only
+    // changes for on-prem Azure DevOps Server, where the org is the collection
+    #[serde(default = "default_azdo_base_url")]
+    pub azdo_base_url: String,
     pub azdo_org: String,
     pub azdo_project: String,
     pub azdo_repository_id: String,
     pub azdo_pat: String,
+    // Optional shared secret; when set, Azure webhooks must carry a valid HMAC-SHA256 signature
+    #[serde(default)]
+    pub azure_webhook_secret: Option<String>,
+    // Target branches whose completed (merged) PRs get their preview deleted;
+    // comma-separated in the environment
+    #[serde(
+        default = "default_merge_delete_branches",
+        deserialize_with = "deserialize_comma_list"
+    )]
+    pub merge_delete_branches: Vec<String>,
+    // Don't redeploy previews of draft PRs on push; their preview is created once the
+    // PR is marked ready for review
+    #[serde(default)]
+    pub skip_draft_previews: bool,
+    // Optional GitHub token for resolving PR branches on private repos
+    #[serde(default)]
+    pub github_token: Option<String>,
+    // Optional shared token; when set, GitLab webhooks must send it in `X-Gitlab-Token`
+    #[serde(default)]
+    pub gitlab_webhook_token: Option<String>,
+    // Optional secret; when set, Bitbucket webhooks must be signed with it (`X-Hub-Signature`)
+    #[serde(default)]
+    pub bitbucket_webhook_secret: Option<String>,
     // Slack Incoming Webhook URL for alerts
     pub slack_webhook_url: String,
     // Authentication cache settings
@@ -27,6 +146,75 @@ pub struct Config {
     pub auth_cache_ttl_secs: u64,
     #[serde(default = "default_auth_cache_negative_ttl")]
     pub auth_cache_negative_ttl_secs: u64,
+    // Webhook coordination: how long delivered event ids are remembered for dedupe,
+    // how long identical payloads resent under a new id are dropped,
+    // and how long a push redeploy waits for a newer push to coalesce into
+    #[serde(default = "default_webhook_dedupe_ttl")]
+    pub webhook_dedupe_ttl_secs: u64,
+    #[serde(default = "default_webhook_duplicate_window")]
+    pub webhook_duplicate_window_secs: u64,
+    #[serde(default = "default_redeploy_coalesce")]
+    pub redeploy_coalesce_secs: u64,
+    // Webhook deliveries accepted per minute from one source address; off by default (0),
+    // since behind a reverse proxy every delivery comes from the proxy's address
+    #[serde(default)]
+    pub webhook_rate_limit_per_minute: u32,
+    // Largest request body accepted by the webhook and API routes; bigger ones get 413
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    // Outbound HTTP timeouts for the Dokploy, Azure DevOps and GitHub clients
+    #[serde(default = "default_http_connect_timeout")]
+    pub http_connect_timeout_secs: u64,
+    #[serde(default = "default_http_timeout")]
+    pub http_timeout_secs: u64,
+    // Most Dokploy requests made at once when looking up many previews, e.g. to list
+    // or prune them
+    #[serde(default = "default_dokploy_concurrency")]
+    pub dokploy_concurrency: usize,
+    // Most deploys running at once; further deploys wait for one to finish (0 disables)
+    #[serde(default)]
+    pub max_concurrent_deploys: usize,
+    // How often the index of composes by name is refetched from Dokploy (0 disables the
+    // index, so every lookup fetches all projects)
+    #[serde(default = "default_compose_index_refresh")]
+    pub compose_index_refresh_secs: u64,
+    // Longest a `?wait=true` create or redeploy waits for the deploy to finish
+    #[serde(default = "default_deploy_wait_timeout")]
+    pub deploy_wait_timeout_secs: u64,
+    // Post to Slack how `/preview` deploys ended, once they have
+    #[serde(default)]
+    pub slack_deploy_notifications: bool,
+    // Longest a deploy is followed up on in the background before giving up on it
+    #[serde(default = "default_deploy_watch_timeout")]
+    pub deploy_watch_timeout_secs: u64,
+    // `serve` (default) runs the HTTP server; `reconcile` runs one reconcile pass and exits
+    #[serde(default)]
+    pub run_mode: RunMode,
+    // Environment `/promote` deploys reviewed branches to, and the base domain of their
+    // hosts there (defaults to `base_domain`); promotion is disabled without an environment
+    #[serde(default)]
+    pub staging_environment_id: Option<String>,
+    #[serde(default)]
+    pub staging_base_domain: Option<String>,
+    // File deploys, deletes and prunes are appended to as JSON lines, with who triggered
+    // them; no audit log when unset
+    #[serde(default)]
+    pub audit_log_path: Option<String>,
+    // `compact` (default) human-readable logs, or `json` for log aggregators
+    #[serde(default)]
+    pub log_format: LogFormat,
+    // Path prefix all routes are served under, e.g. `/spinploy` behind a reverse proxy
+    #[serde(default)]
+    pub base_path: Option<String>,
+    // Check at startup and in `/readyz` that hosts under `base_domain` resolve; only warns
+    // unless `strict_dns_check` is set, which implies the check and fails on a miss
+    #[serde(default)]
+    pub dns_check: bool,
+    #[serde(default)]
+    pub strict_dns_check: bool,
+    // How long shutdown waits for in-flight requests before exiting anyway
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_secs: u64,
     // Optional protected storage settings
     pub storage: Option<StorageConfig>,
     // Deployed Preview API path
@@ -41,6 +229,454 @@ fn default_auth_cache_negative_ttl() -> u64 {
     10
 }
 
+fn default_merge_delete_branches() -> Vec<String> {
+    vec!["main".to_string()]
+}
+
+/// Accepts either a list or a comma-separated string, since env vars can only be strings.
+fn deserialize_comma_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrString {
+        List(Vec<String>),
+        String(String),
+    }
+
+    Ok(match ListOrString::deserialize(deserializer)? {
+        ListOrString::List(items) => items,
+        ListOrString::String(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// Accepts either a list or a JSON array string, since env vars can't nest lists.
+fn deserialize_services<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ServiceConfig>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrString {
+        List(Vec<ServiceConfig>),
+        String(String),
+    }
+
+    match ListOrString::deserialize(deserializer)? {
+        ListOrString::List(services) => Ok(services),
+        ListOrString::String(s) if s.trim().is_empty() => Ok(Vec::new()),
+        ListOrString::String(s) => serde_json::from_str(&s).map_err(de::Error::custom),
+    }
+}
+
+/// Accepts either a list of pairs or env-file text (`KEY=VALUE` lines, blank lines and
+/// `#` comments skipped). Only the first `=` splits, so values may contain `=`.
+fn deserialize_env_pairs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, String)>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PairsOrString {
+        Pairs(Vec<(String, String)>),
+        String(String),
+    }
+
+    match PairsOrString::deserialize(deserializer)? {
+        PairsOrString::Pairs(pairs) => Ok(pairs),
+        PairsOrString::String(s) => s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.split_once('=') {
+                Some((key, value)) if !key.trim().is_empty() => {
+                    Ok((key.trim().to_string(), value.to_string()))
+                }
+                _ => Err(de::Error::custom(format!(
+                    "expected KEY=VALUE, got {:?}",
+                    line
+                ))),
+            })
+            .collect(),
+    }
+}
+
+/// Accepts either a map or a JSON object string, since repository ids (GUIDs, `owner/repo`)
+/// can't be spelled as nested env var names.
+///
+/// A visitor rather than an untagged enum, so map values are deserialized straight from
+/// the env source, which is what turns env strings like `"9090"` into numbers.
+fn deserialize_repository_overrides<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, RepositoryOverrides>, D::Error> {
+    struct RepositoriesVisitor;
+
+    impl<'de> Visitor<'de> for RepositoriesVisitor {
+        type Value = HashMap<String, RepositoryOverrides>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of repository overrides or a JSON object string")
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+            if s.trim().is_empty() {
+                return Ok(HashMap::new());
+            }
+            serde_json::from_str(s).map_err(E::custom)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut repositories = HashMap::new();
+            while let Some((key, overrides)) = map.next_entry()? {
+                repositories.insert(key, overrides);
+            }
+            Ok(repositories)
+        }
+    }
+
+    deserializer.deserialize_any(RepositoriesVisitor)
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: the whole text must equal the pattern
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Dot-separated labels of ASCII letters, digits and inner hyphens, with at least two labels.
+fn is_plausible_hostname(host: &str) -> bool {
+    let labels: Vec<&str> = host.split('.').collect();
+    labels.len() >= 2
+        && host.len() <= 253
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// `user:hash` pairs as Traefik's basicauth middleware takes them. Written single-quoted
+/// to the env file, so hashes keep their `$`s, which rules out quotes and whitespace.
+fn is_basic_auth_users(users: &str) -> bool {
+    users.split(',').all(|entry| {
+        entry
+            .split_once(':')
+            .is_some_and(|(user, hash)| !user.is_empty() && !hash.is_empty())
+            && !entry
+                .chars()
+                .any(|c| c.is_whitespace() || c == '\'' || c == '"')
+    })
+}
+
+/// When the template ends in a `-N` replica index, accept any replica
+/// (e.g. `-2` for scaled services) rather than only the configured one.
+fn strip_replica_suffix<'a>(rest: &'a str, template_suffix: &str) -> Option<&'a str> {
+    let is_replica = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !template_suffix.strip_prefix('-').is_some_and(is_replica) {
+        return None;
+    }
+
+    rest.rsplit_once('-')
+        .filter(|(_, replica)| is_replica(replica))
+        .map(|(service, _)| service)
+}
+
+fn default_log_buffer_size() -> usize {
+    crate::docker_client::DEFAULT_LOG_BUFFER
+}
+
+fn default_app_name_prefix() -> String {
+    "preview-".to_string()
+}
+
+fn default_container_name_template() -> String {
+    // Dokploy's isolated deployments name containers {app_name}-{service}-{replica}
+    "{app_name}-{service}-1".to_string()
+}
+
+/// Env vars for new previews. `{frontend_domain}`, `{backend_domain}`, `{base_domain}`
+/// and `{identifier}` are substituted; anything else, like Dokploy's
+/// `${{project.*}}` references, is written as-is.
+pub const DEFAULT_ENV_TEMPLATE: &str = r#"APP_URL=https://{frontend_domain}
+BACKEND_API_URL=https://{backend_domain}
+EMAIL_ENVIRONMENT_PREFIX="[{identifier}] "
+
+COOKIE_DOMAIN=${{project.COOKIE_DOMAIN}}
+STORAGE_URL=${{project.STORAGE_URL}}
+STORAGE_TOKEN=${{project.STORAGE_TOKEN}}
+
+EMAIL_INVOICE_CREDENTIALS_PASSWORD=${{project.EMAIL_INVOICE_CREDENTIALS_PASSWORD}}
+EMAIL_DIRECT_REGULATION_CREDENTIALS_PASSWORD=${{project.EMAIL_DIRECT_REGULATION_CREDENTIALS_PASSWORD}}
+EMAIL_TEST_ANSWER_CREDENTIALS_PASSWORD=${{project.EMAIL_TEST_ANSWER_CREDENTIALS_PASSWORD}}
+EMAIL_REFERRAL_CREDENTIALS_PASSWORD=${{project.EMAIL_REFERRAL_CREDENTIALS_PASSWORD}}
+EMAIL_NO_REPLY_CREDENTIALS_PASSWORD=${{project.EMAIL_NO_REPLY_CREDENTIALS_PASSWORD}}
+
+FEATURE_MANAGEMENT_FREJA_POLLING_JOB=${{project.FEATURE_MANAGEMENT_FREJA_POLLING_JOB}}
+FEATURE_MANAGEMENT_VARA_IMPORT_JOB=${{project.FEATURE_MANAGEMENT_VARA_IMPORT_JOB}}
+FEATURE_MANAGEMENT_SMS_JOBS=${{project.FEATURE_MANAGEMENT_SMS_JOBS}}
+
+SMS_PASSWORD_BASIC_AUTH=${{project.SMS_PASSWORD_BASIC_AUTH}}
+SMS_PASSWORD_XML=${{project.SMS_PASSWORD_XML}}
+
+VARA_PASSWORD=${{project.VARA_PASSWORD}}
+IMAGE_ANALYSIS_API_KEY=${{project.IMAGE_ANALYSIS_API_KEY}}
+"#;
+
+fn default_env_template() -> String {
+    DEFAULT_ENV_TEMPLATE.to_string()
+}
+
+fn default_max_domains_per_preview() -> usize {
+    10
+}
+
+fn default_webhook_dedupe_ttl() -> u64 {
+    600
+}
+
+fn default_webhook_duplicate_window() -> u64 {
+    30
+}
+
+fn default_azdo_base_url() -> String {
+    crate::azure_client::DEFAULT_BASE_URL.to_string()
+}
+
+fn default_frontend_subdomain_template() -> String {
+    "{identifier}.{base_domain}".to_string()
+}
+
+fn default_backend_subdomain_template() -> String {
+    "api-{identifier}.{base_domain}".to_string()
+}
+
+fn default_http_connect_timeout() -> u64 {
+    15
+}
+
+fn default_http_timeout() -> u64 {
+    30
+}
+
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_dokploy_concurrency() -> usize {
+    8
+}
+
+fn default_compose_index_refresh() -> u64 {
+    60
+}
+
+fn default_deploy_wait_timeout() -> u64 {
+    300
+}
+
+fn default_deploy_watch_timeout() -> u64 {
+    1800
+}
+
+// Stays under Kubernetes' default 30s termination grace period
+fn default_shutdown_timeout() -> u64 {
+    25
+}
+
+fn default_redeploy_coalesce() -> u64 {
+    10
+}
+
+/// A compose service that gets its own preview domain.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct ServiceConfig {
+    pub name: String,
+    pub port: u16,
+    /// Put before the identifier in the host; defaults to `{name}-`
+    #[serde(default)]
+    pub subdomain_prefix: Option<String>,
+    /// Whole host with `{identifier}` and `{base_domain}` placeholders; wins over the prefix
+    #[serde(default)]
+    pub subdomain_template: Option<String>,
+}
+
+impl ServiceConfig {
+    /// Host of this service in the preview `identifier`.
+    pub fn host(&self, identifier: &str, base_domain: &str) -> String {
+        if let Some(template) = &self.subdomain_template {
+            return render_host(template, identifier, base_domain);
+        }
+        match &self.subdomain_prefix {
+            Some(prefix) => format!("{}{}.{}", prefix, identifier, base_domain),
+            None => format!("{}-{}.{}", self.name, identifier, base_domain),
+        }
+    }
+}
+
+fn render_host(template: &str, identifier: &str, base_domain: &str) -> String {
+    template
+        .replace("{identifier}", identifier)
+        .replace("{base_domain}", base_domain)
+}
+
+/// A Dokploy environment composes are deployed to, and how their services are exposed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewTarget {
+    pub environment_id: String,
+    pub base_domain: String,
+    pub frontend: ServiceConfig,
+    pub backend: ServiceConfig,
+    /// Further services that get a domain (`SERVICES`)
+    pub extra_services: Vec<ServiceConfig>,
+}
+
+impl PreviewTarget {
+    /// Every service that gets a domain: the frontend, the backend, then the extra ones.
+    pub fn services(&self) -> impl Iterator<Item = &ServiceConfig> {
+        [&self.frontend, &self.backend]
+            .into_iter()
+            .chain(&self.extra_services)
+    }
+
+    /// Frontend and backend hosts of `identifier` in this target.
+    pub fn hosts(&self, identifier: &str) -> (String, String) {
+        (
+            self.frontend.host(identifier, &self.base_domain),
+            self.backend.host(identifier, &self.base_domain),
+        )
+    }
+}
+
+/// The repository a preview's PR lives in. It's recorded as the description of the
+/// compose created for it, since `pr-<n>` alone doesn't tell an Azure PR from a GitHub
+/// PR or from a PR of another repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreviewSource {
+    Azure(String),
+    GitHub(String),
+    GitLab(String),
+    Bitbucket(String),
+}
+
+impl PreviewSource {
+    const DESCRIPTION_PREFIX: &str = "spinploy:";
+
+    /// Compose description recording this source, e.g. `spinploy:azure:<repository id>`
+    pub fn description(&self) -> String {
+        let (provider, repository) = match self {
+            PreviewSource::Azure(repository) => ("azure", repository),
+            PreviewSource::GitHub(repository) => ("github", repository),
+            PreviewSource::GitLab(repository) => ("gitlab", repository),
+            PreviewSource::Bitbucket(repository) => ("bitbucket", repository),
+        };
+        format!("{}{}:{}", Self::DESCRIPTION_PREFIX, provider, repository)
+    }
+
+    /// Source recorded in a compose description; `None` for composes without one, such
+    /// as previews created before sources were recorded.
+    pub fn from_description(description: &str) -> Option<Self> {
+        let (provider, repository) = description
+            .trim()
+            .strip_prefix(Self::DESCRIPTION_PREFIX)?
+            .split_once(':')?;
+        let repository = repository.to_string();
+        match provider {
+            "azure" => Some(PreviewSource::Azure(repository)),
+            "github" => Some(PreviewSource::GitHub(repository)),
+            "gitlab" => Some(PreviewSource::GitLab(repository)),
+            "bitbucket" => Some(PreviewSource::Bitbucket(repository)),
+            _ => None,
+        }
+    }
+}
+
+/// Settings a single repository can override; unset fields fall back to the flat defaults.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RepositoryOverrides {
+    pub environment_id: Option<String>,
+    pub custom_git_url: Option<String>,
+    pub custom_git_ssh_key_id: Option<String>,
+    pub compose_path: Option<String>,
+    pub base_domain: Option<String>,
+    pub frontend_service_name: Option<String>,
+    pub frontend_port: Option<u16>,
+    pub backend_service_name: Option<String>,
+    pub backend_port: Option<u16>,
+    pub azdo_repository_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    #[default]
+    Serve,
+    Reconcile,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Compact,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CertificateType {
+    #[default]
+    None,
+    Letsencrypt,
+}
+
+impl CertificateType {
+    /// Dokploy's `certificateType` value
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CertificateType::None => "none",
+            CertificateType::Letsencrypt => "letsencrypt",
+        }
+    }
+}
+
+/// Connect and total request timeouts for outbound API clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpTimeouts {
+    pub connect: Duration,
+    pub total: Duration,
+}
+
+impl Default for HttpTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(default_http_connect_timeout()),
+            total: Duration::from_secs(default_http_timeout()),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct StorageConfig {
     pub base_url: String,
@@ -62,4 +698,855 @@ impl Config {
             .try_deserialize()
             .context("Failed to deserialize configuration")
     }
+
+    pub fn http_timeouts(&self) -> HttpTimeouts {
+        HttpTimeouts {
+            connect: Duration::from_secs(self.http_connect_timeout_secs),
+            total: Duration::from_secs(self.http_timeout_secs),
+        }
+    }
+
+    /// Compose source of a preview of `git_branch`, per `SOURCE_TYPE`.
+    pub fn compose_source(&self, git_branch: &str) -> ComposeSource {
+        match self.source_type {
+            SourceType::Git => ComposeSource::Git {
+                custom_git_url: self.custom_git_url.clone(),
+                custom_git_branch: git_branch.to_string(),
+                custom_git_ssh_key_id: self.custom_git_ssh_key_id.clone(),
+                compose_path: self.compose_path.clone(),
+            },
+            SourceType::Raw => ComposeSource::Raw {
+                compose_file: self.raw_compose_file.clone().unwrap_or_default(),
+            },
+        }
+    }
+
+    /// Where previews are deployed: `ENVIRONMENT_ID`, under `BASE_DOMAIN`.
+    pub fn preview_target(&self) -> PreviewTarget {
+        PreviewTarget {
+            environment_id: self.environment_id.clone(),
+            base_domain: self.base_domain.clone(),
+            frontend: self.frontend_service(),
+            backend: self.backend_service(),
+            extra_services: self.services.clone(),
+        }
+    }
+
+    /// The composes of this configuration's previews: spinploy's (by `APP_NAME_PREFIX`)
+    /// in `ENVIRONMENT_ID`.
+    pub fn compose_scope(&self) -> ComposeScope {
+        self.compose_scope_in(&self.environment_id)
+    }
+
+    /// Spinploy's composes (by `APP_NAME_PREFIX`) in `environment_id`.
+    pub fn compose_scope_in(&self, environment_id: &str) -> ComposeScope {
+        ComposeScope {
+            app_name_prefix: self.app_name_prefix.clone(),
+            environment_ids: vec![environment_id.to_string()],
+        }
+    }
+
+    /// Spinploy's composes in `ENVIRONMENT_ID` or any `REPOSITORIES` environment, for
+    /// lookups that aren't tied to a repository, like the API's by identifier.
+    /// Promoted (staging) copies aren't included.
+    pub fn preview_scope(&self) -> ComposeScope {
+        let mut environment_ids = vec![self.environment_id.clone()];
+        for overrides in self.repositories.values() {
+            if let Some(environment_id) = &overrides.environment_id
+                && !environment_ids.contains(environment_id)
+            {
+                environment_ids.push(environment_id.clone());
+            }
+        }
+        ComposeScope {
+            app_name_prefix: self.app_name_prefix.clone(),
+            environment_ids,
+        }
+    }
+
+    /// Where `/promote` deploys: `STAGING_ENVIRONMENT_ID`, under `STAGING_BASE_DOMAIN`
+    /// (or `BASE_DOMAIN`), with the preview services. `None` if promotion isn't configured.
+    pub fn staging_target(&self) -> Option<PreviewTarget> {
+        let environment_id = self
+            .staging_environment_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|id| !id.is_empty())?;
+        Some(PreviewTarget {
+            environment_id: environment_id.to_string(),
+            base_domain: self
+                .staging_base_domain
+                .clone()
+                .unwrap_or_else(|| self.base_domain.clone()),
+            frontend: self.frontend_service(),
+            backend: self.backend_service(),
+            extra_services: self.services.clone(),
+        })
+    }
+
+    /// `BASE_PATH` without its trailing slash, or `None` if routes are served at the root.
+    pub fn base_path(&self) -> Option<&str> {
+        self.base_path
+            .as_deref()
+            .map(|path| path.trim().trim_end_matches('/'))
+            .filter(|path| !path.is_empty())
+    }
+
+    /// Web URL of an Azure DevOps pull request in the configured repository.
+    pub fn pr_url(&self, pr_id: &str) -> String {
+        format!(
+            "{}/_git/{}/pullrequest/{}",
+            project_url(&self.azdo_base_url, &self.azdo_org, &self.azdo_project),
+            self.azdo_repository_id,
+            pr_id
+        )
+    }
+
+    /// Checks values that deserialize fine but can't work, reporting every problem at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        let required = [
+            ("DOKPLOY_URL", &self.dokploy_url),
+            ("PROJECT_ID", &self.project_id),
+            ("ENVIRONMENT_ID", &self.environment_id),
+            ("CUSTOM_GIT_URL", &self.custom_git_url),
+            ("CUSTOM_GIT_SSH_KEY_ID", &self.custom_git_ssh_key_id),
+            ("COMPOSE_PATH", &self.compose_path),
+            ("BASE_DOMAIN", &self.base_domain),
+            ("FRONTEND_SERVICE_NAME", &self.frontend_service_name),
+            ("BACKEND_SERVICE_NAME", &self.backend_service_name),
+            ("AZDO_ORG", &self.azdo_org),
+            ("AZDO_PROJECT", &self.azdo_project),
+            ("AZDO_REPOSITORY_ID", &self.azdo_repository_id),
+            ("AZDO_PAT", &self.azdo_pat),
+            ("SLACK_WEBHOOK_URL", &self.slack_webhook_url),
+            ("DEPLOYED_PREVIEW_API_PATH", &self.deployed_preview_api_path),
+        ];
+        for (name, value) in required {
+            if value.trim().is_empty() {
+                problems.push(format!("{} must not be empty", name));
+            }
+        }
+
+        for (name, value) in [
+            ("DOKPLOY_URL", &self.dokploy_url),
+            ("AZDO_BASE_URL", &self.azdo_base_url),
+        ] {
+            if value.trim().is_empty() {
+                continue;
+            }
+            match url::Url::parse(value) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                Ok(url) => problems.push(format!(
+                    "{} must be an http(s) URL, got scheme {:?}",
+                    name,
+                    url.scheme()
+                )),
+                Err(e) => problems.push(format!("{} is not a valid URL: {}", name, e)),
+            }
+        }
+        if !self.base_domain.trim().is_empty() && !is_plausible_hostname(&self.base_domain) {
+            problems.push(format!(
+                "BASE_DOMAIN {:?} is not a valid hostname (e.g. preview.example.com)",
+                self.base_domain
+            ));
+        }
+        if self.source_type == SourceType::Raw
+            && self
+                .raw_compose_file
+                .as_deref()
+                .is_none_or(|file| file.trim().is_empty())
+        {
+            problems.push("RAW_COMPOSE_FILE must be set when SOURCE_TYPE is raw".to_string());
+        }
+        if let Some(users) = &self.preview_basic_auth
+            && !is_basic_auth_users(users)
+        {
+            problems.push(
+                "PREVIEW_BASIC_AUTH must be comma-separated user:hash pairs without quotes"
+                    .to_string(),
+            );
+        }
+        if let Some(domain) = &self.staging_base_domain
+            && !is_plausible_hostname(domain)
+        {
+            problems.push(format!(
+                "STAGING_BASE_DOMAIN {:?} is not a valid hostname",
+                domain
+            ));
+        }
+        for (name, port) in [
+            ("FRONTEND_PORT", self.frontend_port),
+            ("BACKEND_PORT", self.backend_port),
+        ] {
+            if port == 0 {
+                problems.push(format!("{} must not be 0", name));
+            }
+        }
+        for (name, template) in [
+            (
+                "FRONTEND_SUBDOMAIN_TEMPLATE",
+                &self.frontend_subdomain_template,
+            ),
+            (
+                "BACKEND_SUBDOMAIN_TEMPLATE",
+                &self.backend_subdomain_template,
+            ),
+        ] {
+            if !template.contains("{identifier}") {
+                problems.push(format!(
+                    "{} must contain {{identifier}}, or every preview would share one host",
+                    name
+                ));
+            }
+        }
+        for service in &self.services {
+            if service.name.trim().is_empty() {
+                problems.push("SERVICES entries must have a name".to_string());
+            } else if service.port == 0 {
+                problems.push(format!("SERVICES port of {:?} must not be 0", service.name));
+            }
+        }
+        let mut hosts = std::collections::HashSet::new();
+        for service in self.preview_services() {
+            if !hosts.insert(service.host("{identifier}", "")) {
+                problems.push(format!(
+                    "service {:?} would share its preview host with another service",
+                    service.name
+                ));
+            }
+        }
+        if let Some(path) = self.base_path()
+            && (!path.starts_with('/') || path.contains(['{', '}', '*']))
+        {
+            problems.push(format!(
+                "BASE_PATH {:?} must start with / and not contain route parameters",
+                path
+            ));
+        }
+        for (name, secs) in [
+            ("HTTP_CONNECT_TIMEOUT_SECS", self.http_connect_timeout_secs),
+            ("HTTP_TIMEOUT_SECS", self.http_timeout_secs),
+            ("DEPLOY_WAIT_TIMEOUT_SECS", self.deploy_wait_timeout_secs),
+            ("DEPLOY_WATCH_TIMEOUT_SECS", self.deploy_watch_timeout_secs),
+        ] {
+            if secs == 0 {
+                problems.push(format!("{} must not be 0", name));
+            }
+        }
+        if !self.api_keys.is_empty()
+            && self
+                .dokploy_api_key
+                .as_deref()
+                .is_none_or(|key| key.trim().is_empty())
+        {
+            problems.push("DOKPLOY_API_KEY must be set when API_KEYS is set".to_string());
+        }
+        // An empty prefix would have pruning consider every compose in the environment
+        if self.app_name_prefix.trim().is_empty() {
+            problems.push("APP_NAME_PREFIX must not be empty".to_string());
+        }
+        if self.log_buffer_size == 0 {
+            problems.push("LOG_BUFFER_SIZE must not be 0".to_string());
+        }
+        if self.dokploy_concurrency == 0 {
+            problems.push("DOKPLOY_CONCURRENCY must not be 0".to_string());
+        }
+        if self.max_body_bytes == 0 {
+            problems.push("MAX_BODY_BYTES must not be 0".to_string());
+        }
+        if !self.container_name_template.contains("{service}") {
+            problems.push("CONTAINER_NAME_TEMPLATE must contain {service}".to_string());
+        }
+        for (key, overrides) in &self.repositories {
+            if let Some(domain) = &overrides.base_domain
+                && !is_plausible_hostname(domain)
+            {
+                problems.push(format!(
+                    "base_domain {:?} of repository {:?} is not a valid hostname",
+                    domain, key
+                ));
+            }
+            if overrides.frontend_port == Some(0) || overrides.backend_port == Some(0) {
+                problems.push(format!("ports of repository {:?} must not be 0", key));
+            }
+        }
+        if let Some(storage) = &self.storage {
+            for (name, value) in [
+                ("STORAGE__BASE_URL", &storage.base_url),
+                ("STORAGE__DIR", &storage.dir),
+                ("STORAGE__TOKEN", &storage.token),
+            ] {
+                if value.trim().is_empty() {
+                    problems.push(format!("{} must not be empty", name));
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// Settings for a repository known under any of `keys` (e.g. its id and its name),
+    /// with its overrides applied on top of the defaults. Keys match case-insensitively,
+    /// as env var sources lowercase them. Unknown repositories get the defaults.
+    pub fn for_repository(&self, keys: &[&str]) -> Config {
+        let mut config = self.clone();
+        let Some(overrides) = keys.iter().find_map(|key| {
+            self.repositories
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, overrides)| overrides)
+        }) else {
+            return config;
+        };
+
+        let overrides = overrides.clone();
+        let set = |field: &mut String, value: Option<String>| {
+            if let Some(value) = value {
+                *field = value;
+            }
+        };
+        set(&mut config.environment_id, overrides.environment_id);
+        set(&mut config.custom_git_url, overrides.custom_git_url);
+        set(
+            &mut config.custom_git_ssh_key_id,
+            overrides.custom_git_ssh_key_id,
+        );
+        set(&mut config.compose_path, overrides.compose_path);
+        set(&mut config.base_domain, overrides.base_domain);
+        set(
+            &mut config.frontend_service_name,
+            overrides.frontend_service_name,
+        );
+        set(
+            &mut config.backend_service_name,
+            overrides.backend_service_name,
+        );
+        set(&mut config.azdo_repository_id, overrides.azdo_repository_id);
+        config.frontend_port = overrides.frontend_port.unwrap_or(config.frontend_port);
+        config.backend_port = overrides.backend_port.unwrap_or(config.backend_port);
+        config
+    }
+
+    /// These settings with previews deployed from `compose_path` if given, e.g. a path
+    /// from a create request or a `/preview --compose-path` comment. It takes precedence
+    /// over both `COMPOSE_PATH` and repository overrides.
+    pub fn with_compose_path(mut self, compose_path: Option<&str>) -> Config {
+        if let Some(compose_path) = compose_path {
+            self.compose_path = compose_path.to_string();
+        }
+        self
+    }
+
+    /// Whether a compose with this description was created for a PR of `AZDO_REPOSITORY_ID`.
+    /// Passes acting on that repository's PRs only touch such previews.
+    pub fn is_azure_repository_preview(&self, description: Option<&str>) -> bool {
+        match description.and_then(PreviewSource::from_description) {
+            Some(PreviewSource::Azure(repository)) => {
+                repository.eq_ignore_ascii_case(&self.azdo_repository_id)
+            }
+            _ => false,
+        }
+    }
+
+    /// Docker container name of `service` in the compose deployed as `app_name`.
+    pub fn container_name(&self, app_name: &str, service: &str) -> String {
+        self.container_name_template
+            .replace("{app_name}", app_name)
+            .replace("{service}", service)
+    }
+
+    /// Recovers the service name from a container name built by [`Self::container_name`].
+    /// Returns `None` for containers that don't follow the template.
+    pub fn service_from_container_name<'a>(
+        &self,
+        app_name: &str,
+        container_name: &'a str,
+    ) -> Option<&'a str> {
+        let (prefix, suffix) = self.container_name_template.split_once("{service}")?;
+        let prefix = prefix.replace("{app_name}", app_name);
+        let suffix = suffix.replace("{app_name}", app_name);
+
+        let rest = container_name
+            .trim_start_matches('/')
+            .strip_prefix(prefix.as_str())?;
+        rest.strip_suffix(suffix.as_str())
+            .or_else(|| strip_replica_suffix(rest, &suffix))
+            .filter(|service| !service.is_empty())
+    }
+
+    /// Frontend and backend hosts of the preview `identifier`.
+    pub fn preview_hosts(&self, identifier: &str) -> (String, String) {
+        self.preview_target().hosts(identifier)
+    }
+
+    /// Every service that gets a preview domain: the frontend and backend at their
+    /// subdomain templates, then any extra `SERVICES`.
+    pub fn preview_services(&self) -> Vec<ServiceConfig> {
+        let primary = [self.frontend_service(), self.backend_service()];
+        primary.into_iter().chain(self.services.clone()).collect()
+    }
+
+    fn frontend_service(&self) -> ServiceConfig {
+        ServiceConfig {
+            name: self.frontend_service_name.clone(),
+            port: self.frontend_port,
+            subdomain_prefix: None,
+            subdomain_template: Some(self.frontend_subdomain_template.clone()),
+        }
+    }
+
+    fn backend_service(&self) -> ServiceConfig {
+        ServiceConfig {
+            name: self.backend_service_name.clone(),
+            port: self.backend_port,
+            subdomain_prefix: None,
+            subdomain_template: Some(self.backend_subdomain_template.clone()),
+        }
+    }
+
+    /// Whether a commenter known by `names` (unique name, identity id) may run slash commands.
+    pub fn allows_command_user(&self, names: &[&str]) -> bool {
+        self.allowed_command_users.is_empty()
+            || self.allowed_command_users.iter().any(|allowed| {
+                names
+                    .iter()
+                    .any(|name| name.trim().eq_ignore_ascii_case(allowed.trim()))
+            })
+    }
+
+    /// Whether the preview with this identifier is exempt from automatic pruning.
+    pub fn is_prune_protected(&self, identifier: &str) -> bool {
+        self.prune_protect
+            .iter()
+            .any(|pattern| glob_matches(pattern, identifier))
+    }
+
+    /// Whether merging a PR into `target_branch` should delete its preview.
+    pub fn deletes_preview_on_merge_into(&self, target_branch: &str) -> bool {
+        self.merge_delete_branches
+            .iter()
+            .any(|branch| branch == target_branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config_json() -> serde_json::Value {
+        serde_json::json!({
+            "dokploy_url": "https://dokploy.example.com/api",
+            "project_id": "project",
+            "environment_id": "env",
+            "custom_git_url": "ssh://git@example.com/repo.git",
+            "custom_git_ssh_key_id": "ssh-key",
+            "compose_path": "./docker-compose.yml",
+            "base_domain": "preview.example.com",
+            "frontend_service_name": "web",
+            "frontend_port": 3000,
+            "backend_service_name": "api",
+            "backend_port": 8080,
+            "azdo_org": "org",
+            "azdo_project": "project",
+            "azdo_repository_id": "repo",
+            "azdo_pat": "pat",
+            "slack_webhook_url": "https://hooks.slack.com/services/x",
+            "deployed_preview_api_path": "https://spinploy.example.com",
+        })
+    }
+
+    fn test_config() -> Config {
+        serde_json::from_value(test_config_json()).unwrap()
+    }
+
+    #[test]
+    fn test_config_is_valid() {
+        assert!(test_config().validate().is_ok());
+    }
+
+    #[test]
+    fn validate_lists_every_problem() {
+        let config = Config {
+            project_id: String::new(),
+            azdo_pat: "  ".to_string(),
+            dokploy_url: "not a url".to_string(),
+            base_domain: "https://preview.example.com".to_string(),
+            backend_port: 0,
+            ..test_config()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("PROJECT_ID must not be empty"), "{err}");
+        assert!(err.contains("AZDO_PAT must not be empty"), "{err}");
+        assert!(err.contains("DOKPLOY_URL is not a valid URL"), "{err}");
+        assert!(
+            err.contains("BASE_DOMAIN \"https://preview.example.com\" is not a valid hostname"),
+            "{err}"
+        );
+        assert!(err.contains("BACKEND_PORT must not be 0"), "{err}");
+        assert!(!err.contains("FRONTEND_PORT"), "{err}");
+        assert!(!err.contains("DOKPLOY_CONCURRENCY"), "{err}");
+    }
+
+    #[test]
+    fn validate_rejects_non_http_dokploy_url_and_bare_hostname() {
+        let config = Config {
+            dokploy_url: "ftp://dokploy.example.com".to_string(),
+            base_domain: "localhost".to_string(),
+            ..test_config()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("DOKPLOY_URL must be an http(s) URL"), "{err}");
+        assert!(err.contains("BASE_DOMAIN \"localhost\""), "{err}");
+    }
+
+    #[test]
+    fn validate_requires_identifier_in_subdomain_templates() {
+        let config = Config {
+            backend_subdomain_template: "api.{base_domain}".to_string(),
+            ..test_config()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("BACKEND_SUBDOMAIN_TEMPLATE must contain {identifier}"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_services_sharing_a_host() {
+        let config = Config {
+            services: vec![ServiceConfig {
+                name: "admin".to_string(),
+                port: 4000,
+                subdomain_prefix: Some("api-".to_string()),
+                subdomain_template: None,
+            }],
+            ..test_config()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("\"admin\" would share its preview host"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn validate_checks_preview_basic_auth_users() {
+        let valid = Config {
+            preview_basic_auth: Some("qa:$apr1$abc$def,dev:$2y$05$xyz".to_string()),
+            ..test_config()
+        };
+        assert!(valid.validate().is_ok());
+
+        for users in ["qa", "qa:", "qa:$apr1$abc, dev:$2y$05$xyz", "qa:'hash'"] {
+            let config = Config {
+                preview_basic_auth: Some(users.to_string()),
+                ..test_config()
+            };
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.contains("PREVIEW_BASIC_AUTH"), "{users}: {err}");
+        }
+    }
+
+    #[test]
+    fn validate_requires_dokploy_key_with_management_keys() {
+        let with_api_keys = Config {
+            dokploy_api_key: Some("dokploy-key".to_string()),
+            api_keys: vec!["old-key".to_string(), "new-key".to_string()],
+            ..test_config()
+        };
+        let config = Config {
+            dokploy_api_key: None,
+            ..with_api_keys.clone()
+        };
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("DOKPLOY_API_KEY must be set when API_KEYS is set"),
+            "{err}"
+        );
+        assert!(with_api_keys.validate().is_ok());
+    }
+
+    #[test]
+    fn staging_requires_an_environment() {
+        assert!(test_config().staging_target().is_none());
+
+        let config = Config {
+            staging_environment_id: Some("staging".to_string()),
+            ..test_config()
+        };
+        let staging = config.staging_target().unwrap();
+        assert_eq!(staging.environment_id, "staging");
+        // Without STAGING_BASE_DOMAIN, hosts stay under the preview base domain
+        assert_eq!(staging.base_domain, config.base_domain);
+    }
+
+    #[test]
+    fn preview_target_hosts_come_from_the_frontend_and_backend() {
+        let config = Config {
+            services: vec![ServiceConfig {
+                name: "worker".to_string(),
+                port: 9000,
+                subdomain_prefix: None,
+                subdomain_template: None,
+            }],
+            ..test_config()
+        };
+        let target = config.preview_target();
+
+        assert_eq!(
+            target.hosts("pr-1"),
+            (
+                "pr-1.preview.example.com".to_string(),
+                "api-pr-1.preview.example.com".to_string()
+            )
+        );
+        let names: Vec<_> = target.services().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["web", "api", "worker"]);
+
+        // Without extra services there are still hosts for both
+        let target = PreviewTarget {
+            extra_services: vec![],
+            ..target
+        };
+        assert_eq!(target.hosts("pr-1").1, "api-pr-1.preview.example.com");
+        assert_eq!(target.services().count(), 2);
+    }
+
+    fn config_with_repositories(repositories: serde_json::Value) -> Config {
+        let mut json = test_config_json();
+        json["repositories"] = repositories;
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn repository_overrides_apply_on_top_of_defaults() {
+        let config = config_with_repositories(serde_json::json!({
+            "acme/webapp": {
+                "environment_id": "env-webapp",
+                "base_domain": "webapp.example.com",
+                "backend_port": 9090
+            }
+        }));
+
+        let resolved = config.for_repository(&["ACME/WebApp"]);
+        assert_eq!(resolved.environment_id, "env-webapp");
+        assert_eq!(resolved.base_domain, "webapp.example.com");
+        assert_eq!(resolved.backend_port, 9090);
+        // Not overridden: defaults
+        assert_eq!(resolved.frontend_service_name, "web");
+        assert_eq!(resolved.frontend_port, 3000);
+    }
+
+    #[test]
+    fn compose_path_override_beats_repository_and_default() {
+        let config = config_with_repositories(serde_json::json!({
+            "infra": { "compose_path": "infra/docker-compose.yml" }
+        }));
+
+        assert_eq!(
+            config.clone().with_compose_path(None).compose_path,
+            "./docker-compose.yml"
+        );
+        assert_eq!(
+            config
+                .for_repository(&["infra"])
+                .with_compose_path(None)
+                .compose_path,
+            "infra/docker-compose.yml"
+        );
+        for keys in [&[][..], &["infra"]] {
+            let resolved = config
+                .for_repository(keys)
+                .with_compose_path(Some("ops/compose.yml"));
+            assert_eq!(resolved.compose_path, "ops/compose.yml");
+        }
+    }
+
+    #[test]
+    fn unknown_repository_falls_back_to_defaults() {
+        let config = config_with_repositories(serde_json::json!({
+            "acme/webapp": { "environment_id": "env-webapp" }
+        }));
+
+        assert_eq!(config.for_repository(&["acme/other"]).environment_id, "env");
+        assert_eq!(config.for_repository(&[]).environment_id, "env");
+    }
+
+    #[test]
+    fn repository_matches_any_of_its_keys() {
+        let config = config_with_repositories(serde_json::json!({
+            "webapp": { "base_domain": "webapp.example.com" }
+        }));
+
+        let resolved = config.for_repository(&["5c7a9e1e-0000-4000-8000-000000000000", "webapp"]);
+        assert_eq!(resolved.base_domain, "webapp.example.com");
+    }
+
+    #[test]
+    fn repository_overrides_from_json_string() {
+        let config = config_with_repositories(serde_json::json!(
+            r#"{"acme/webapp": {"base_domain": "webapp.example.com"}}"#
+        ));
+
+        assert_eq!(
+            config.for_repository(&["acme/webapp"]).base_domain,
+            "webapp.example.com"
+        );
+    }
+
+    #[test]
+    fn pr_url_for_cloud_and_server() {
+        assert_eq!(
+            test_config().pr_url("42"),
+            "https://dev.azure.com/org/project/_git/repo/pullrequest/42"
+        );
+
+        let mut json = test_config_json();
+        json["azdo_base_url"] = "https://tfs.example.com/tfs/".into();
+        json["azdo_org"] = "DefaultCollection".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            config.pr_url("42"),
+            "https://tfs.example.com/tfs/DefaultCollection/project/_git/repo/pullrequest/42"
+        );
+    }
+
+    #[test]
+    fn prune_protect_patterns() {
+        let config = Config {
+            prune_protect: vec!["pr-42".to_string(), "br-*-keep".to_string()],
+            ..test_config()
+        };
+
+        assert!(config.is_prune_protected("pr-42"));
+        assert!(!config.is_prune_protected("pr-420"));
+        assert!(config.is_prune_protected("br-demo-keep"));
+        assert!(!config.is_prune_protected("br-demo-keeper"));
+        assert!(!test_config().is_prune_protected("pr-42"));
+    }
+
+    #[test]
+    fn command_allowlist_matches_unique_name_or_id() {
+        let config = Config {
+            allowed_command_users: vec![
+                "Fabrikamfiber4@hotmail.com".to_string(),
+                "54D125F7-69F7-4191-904F-C5B96B6261C8".to_string(),
+            ],
+            ..test_config()
+        };
+
+        assert!(config.allows_command_user(&["fabrikamfiber4@hotmail.com", "d6245f20"]));
+        assert!(
+            config.allows_command_user(&[
+                "normal@example.com",
+                "54d125f7-69f7-4191-904f-c5b96b6261c8"
+            ])
+        );
+        assert!(!config.allows_command_user(&["someone@example.com", "Someone"]));
+        assert!(!config.allows_command_user(&[]));
+        // No allowlist, no restriction
+        assert!(test_config().allows_command_user(&[]));
+    }
+
+    #[test]
+    fn merge_delete_defaults_to_main() {
+        let config = test_config();
+
+        assert!(config.deletes_preview_on_merge_into("main"));
+        assert!(!config.deletes_preview_on_merge_into("develop"));
+    }
+
+    #[test]
+    fn merge_delete_branches_from_comma_separated_env() {
+        let mut json = test_config_json();
+        json["merge_delete_branches"] = " master, develop ,release/1.0".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert!(config.deletes_preview_on_merge_into("master"));
+        assert!(config.deletes_preview_on_merge_into("develop"));
+        assert!(config.deletes_preview_on_merge_into("release/1.0"));
+        assert!(!config.deletes_preview_on_merge_into("main"));
+    }
+
+    #[test]
+    fn log_format_defaults_to_compact() {
+        assert_eq!(test_config().log_format, LogFormat::Compact);
+        let mut json = test_config_json();
+        json["log_format"] = "json".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn default_container_name_template_round_trips() {
+        let config = test_config();
+
+        let name = config.container_name("preview-pr-1-abc123", "backend");
+        assert_eq!(name, "preview-pr-1-abc123-backend-1");
+        assert_eq!(
+            config.service_from_container_name("preview-pr-1-abc123", &format!("/{name}")),
+            Some("backend")
+        );
+    }
+
+    #[test]
+    fn extracts_service_names_containing_dashes() {
+        let config = test_config();
+        let app_name = "preview-pr-1-abc123";
+
+        for service in ["api-gateway", "redis", "worker-1"] {
+            let name = config.container_name(app_name, service);
+            assert_eq!(
+                config.service_from_container_name(app_name, &name),
+                Some(service)
+            );
+        }
+    }
+
+    #[test]
+    fn extracts_service_from_any_replica() {
+        let config = test_config();
+
+        assert_eq!(
+            config.service_from_container_name(
+                "preview-pr-1-abc123",
+                "preview-pr-1-abc123-api-gateway-2"
+            ),
+            Some("api-gateway")
+        );
+        assert_eq!(
+            config.service_from_container_name("preview-pr-1-abc123", "preview-pr-1-abc123-redis"),
+            None
+        );
+    }
+
+    #[test]
+    fn custom_container_name_template_round_trips() {
+        let config = Config {
+            container_name_template: "acme_{app_name}_{service}.2".to_string(),
+            ..test_config()
+        };
+
+        let name = config.container_name("preview-pr-1-abc123", "frontend");
+        assert_eq!(name, "acme_preview-pr-1-abc123_frontend.2");
+        assert_eq!(
+            config.service_from_container_name("preview-pr-1-abc123", &name),
+            Some("frontend")
+        );
+        assert_eq!(
+            config.service_from_container_name("preview-pr-1-abc123", "other-container"),
+            None
+        );
+    }
 }
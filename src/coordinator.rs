@@ -0,0 +1,506 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::SlashCommand;
+use crate::models::azure::AzureCommentRef;
+
+/// Coordinates webhook-driven preview operations so that redelivered events,
+/// bursts of pushes and concurrent upserts don't turn into duplicate Dokploy work.
+///
+/// - Dedupe: an event id seen within `dedupe_ttl`, or a payload key seen within
+///   `duplicate_window`, is reported as a duplicate. A PR comment edited within
+///   `dedupe_ttl` without changing its slash command is one too. Keys of a delivery
+///   that failed are forgotten, so the provider's retry is processed.
+/// - Coalesce: a redeploy for an identifier waits out `coalesce_window` and is
+///   skipped if another one was requested meanwhile, so the last push is deployed.
+/// - Serialize: operations on the same identifier run one at a time.
+/// - Drafts: PRs last seen as drafts are remembered, to tell when one is marked ready.
+/// - Threads: the PR thread announcing a preview's push redeploys is remembered, to
+///   update it on later pushes instead of starting a thread per push.
+pub struct WebhookCoordinator {
+    dedupe_ttl: Duration,
+    duplicate_window: Duration,
+    coalesce_window: Duration,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    seen_events: HashMap<String, Instant>,
+    seen_payloads: HashMap<String, Instant>,
+    comment_commands: HashMap<String, (Option<SlashCommand>, Instant)>,
+    /// Latest redeploy request per identifier, still waiting out the coalesce window
+    redeploy_requests: HashMap<String, u64>,
+    redeploy_requests_total: u64,
+    /// Open PRs last seen as drafts; in memory only, so lost on restart
+    draft_prs: HashSet<String>,
+    /// Comment announcing push redeploys per identifier; in memory only as well
+    redeploy_threads: HashMap<String, AzureCommentRef>,
+    operations: HashMap<String, IdentifierOperations>,
+    deduped_total: u64,
+    coalesced_total: u64,
+}
+
+struct IdentifierOperations {
+    lock: Arc<tokio::sync::Mutex<()>>,
+    pending: usize,
+}
+
+/// Point-in-time view of the coordinator, for metrics and admin endpoints.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoordinatorSnapshot {
+    pub tracked_events: usize,
+    pub deduped_total: u64,
+    pub coalesced_total: u64,
+    /// Operations running or waiting, per identifier
+    pub pending: HashMap<String, usize>,
+}
+
+/// Held while an operation on an identifier runs; releases the identifier on drop.
+pub struct OperationGuard {
+    _lock: OwnedMutexGuard<()>,
+    _pending: PendingOperation,
+}
+
+/// Counts an operation as pending from the moment it queues up, so a waiter
+/// that gets cancelled before acquiring the lock is still uncounted.
+struct PendingOperation {
+    identifier: String,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl WebhookCoordinator {
+    pub fn new(
+        dedupe_ttl: Duration,
+        duplicate_window: Duration,
+        coalesce_window: Duration,
+    ) -> Self {
+        Self {
+            dedupe_ttl,
+            duplicate_window,
+            coalesce_window,
+            inner: Arc::new(Mutex::new(Inner::default())),
+        }
+    }
+
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().expect("coordinator mutex poisoned")
+    }
+
+    /// Record a delivered event id. Returns `false` if it was already seen within the TTL.
+    pub fn register_event(&self, event_id: &str) -> bool {
+        let now = Instant::now();
+        let ttl = self.dedupe_ttl;
+        let mut inner = self.inner();
+
+        inner.seen_events.retain(|_, seen_at| *seen_at + ttl > now);
+        if inner.seen_events.contains_key(event_id) {
+            inner.deduped_total += 1;
+            return false;
+        }
+
+        inner.seen_events.insert(event_id.to_string(), now);
+        true
+    }
+
+    /// Forget an event id whose processing failed, so its redelivery isn't a duplicate.
+    pub fn forget_event(&self, event_id: &str) {
+        self.inner().seen_events.remove(event_id);
+    }
+
+    /// Record a payload key (what a delivery says, independent of its event id).
+    /// Returns `false` if the same key was seen within the duplicate window.
+    pub fn register_payload(&self, payload_key: &str) -> bool {
+        let now = Instant::now();
+        let window = self.duplicate_window;
+        let mut inner = self.inner();
+
+        inner
+            .seen_payloads
+            .retain(|_, seen_at| *seen_at + window > now);
+        if inner.seen_payloads.contains_key(payload_key) {
+            inner.deduped_total += 1;
+            return false;
+        }
+
+        inner.seen_payloads.insert(payload_key.to_string(), now);
+        true
+    }
+
+    /// Forget a payload key whose processing failed.
+    pub fn forget_payload(&self, payload_key: &str) {
+        self.inner().seen_payloads.remove(payload_key);
+    }
+
+    /// Record the slash command a PR comment holds now, keyed by PR, thread and
+    /// comment. Returns `false` if the comment was last processed with the same
+    /// command, i.e. an edit that didn't change what it asks for.
+    pub fn register_comment_command(
+        &self,
+        comment_key: &str,
+        command: Option<SlashCommand>,
+    ) -> bool {
+        let now = Instant::now();
+        let ttl = self.dedupe_ttl;
+        let mut inner = self.inner();
+
+        inner
+            .comment_commands
+            .retain(|_, (_, seen_at)| *seen_at + ttl > now);
+        let previous = inner
+            .comment_commands
+            .insert(comment_key.to_string(), (command, now));
+        if previous.is_some_and(|(previous, _)| previous == command) {
+            inner.deduped_total += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Forget the command a comment was processed with, after processing it failed.
+    pub fn forget_comment_command(&self, comment_key: &str) {
+        self.inner().comment_commands.remove(comment_key);
+    }
+
+    /// Wait out the coalesce window for a redeploy of an identifier. Returns `false`
+    /// if another redeploy was requested meanwhile, in which case this one should be
+    /// skipped and the later one, carrying the newer commit, deploys instead.
+    pub async fn coalesce_redeploy(&self, identifier: &str) -> bool {
+        if self.coalesce_window.is_zero() {
+            return true;
+        }
+
+        let request = {
+            let mut inner = self.inner();
+            inner.redeploy_requests_total += 1;
+            let request = inner.redeploy_requests_total;
+            inner
+                .redeploy_requests
+                .insert(identifier.to_string(), request);
+            request
+        };
+
+        tokio::time::sleep(self.coalesce_window).await;
+
+        let mut inner = self.inner();
+        if inner.redeploy_requests.get(identifier) != Some(&request) {
+            inner.coalesced_total += 1;
+            return false;
+        }
+        inner.redeploy_requests.remove(identifier);
+        true
+    }
+
+    /// Record whether the PR behind an identifier is a draft now. Returns `true` if it
+    /// was last seen as a draft and no longer is, i.e. it was just marked ready for review.
+    pub fn record_draft_state(&self, identifier: &str, is_draft: bool) -> bool {
+        let mut inner = self.inner();
+        if is_draft {
+            inner.draft_prs.insert(identifier.to_string());
+            false
+        } else {
+            inner.draft_prs.remove(identifier)
+        }
+    }
+
+    /// Stop tracking the draft state and redeploy thread of a PR that was completed or
+    /// abandoned, so closed PRs don't pile up.
+    pub fn forget_closed_pr(&self, identifier: &str) {
+        let mut inner = self.inner();
+        inner.draft_prs.remove(identifier);
+        inner.redeploy_threads.remove(identifier);
+    }
+
+    /// The comment announcing push redeploys of an identifier, if one was posted.
+    pub fn redeploy_thread(&self, identifier: &str) -> Option<AzureCommentRef> {
+        self.inner().redeploy_threads.get(identifier).copied()
+    }
+
+    pub fn record_redeploy_thread(&self, identifier: &str, comment: AzureCommentRef) {
+        self.inner()
+            .redeploy_threads
+            .insert(identifier.to_string(), comment);
+    }
+
+    /// Wait until no other operation runs for this identifier, then hold it
+    /// until the returned guard is dropped.
+    pub async fn lock(&self, identifier: &str) -> OperationGuard {
+        let lock = {
+            let mut inner = self.inner();
+            let ops = inner
+                .operations
+                .entry(identifier.to_string())
+                .or_insert_with(|| IdentifierOperations {
+                    lock: Arc::new(tokio::sync::Mutex::new(())),
+                    pending: 0,
+                });
+            ops.pending += 1;
+            ops.lock.clone()
+        };
+        let pending = PendingOperation {
+            identifier: identifier.to_string(),
+            inner: self.inner.clone(),
+        };
+
+        OperationGuard {
+            _lock: lock.lock_owned().await,
+            _pending: pending,
+        }
+    }
+
+    pub fn snapshot(&self) -> CoordinatorSnapshot {
+        let inner = self.inner();
+        CoordinatorSnapshot {
+            tracked_events: inner.seen_events.len(),
+            deduped_total: inner.deduped_total,
+            coalesced_total: inner.coalesced_total,
+            pending: inner
+                .operations
+                .iter()
+                .map(|(identifier, ops)| (identifier.clone(), ops.pending))
+                .collect(),
+        }
+    }
+}
+
+impl Drop for PendingOperation {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().expect("coordinator mutex poisoned");
+        if let Some(ops) = inner.operations.get_mut(&self.identifier) {
+            ops.pending -= 1;
+            if ops.pending == 0 {
+                inner.operations.remove(&self.identifier);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn coordinator() -> WebhookCoordinator {
+        WebhookCoordinator::new(
+            Duration::from_secs(600),
+            Duration::from_secs(30),
+            Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn dedupes_redelivered_events() {
+        let coordinator = coordinator();
+
+        assert!(coordinator.register_event("evt-1"));
+        assert!(!coordinator.register_event("evt-1"));
+        assert!(coordinator.register_event("evt-2"));
+
+        let snapshot = coordinator.snapshot();
+        assert_eq!(snapshot.tracked_events, 2);
+        assert_eq!(snapshot.deduped_total, 1);
+    }
+
+    #[test]
+    fn forgets_events_after_ttl() {
+        let coordinator = WebhookCoordinator::new(Duration::ZERO, Duration::ZERO, Duration::ZERO);
+
+        assert!(coordinator.register_event("evt-1"));
+        assert!(coordinator.register_event("evt-1"));
+        assert_eq!(coordinator.snapshot().deduped_total, 0);
+    }
+
+    #[test]
+    fn drops_identical_payloads_within_window() {
+        let coordinator = coordinator();
+
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:abc"));
+        assert!(!coordinator.register_payload("updated:7:refs/heads/a:abc"));
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:def"));
+        assert_eq!(coordinator.snapshot().deduped_total, 1);
+
+        let coordinator = WebhookCoordinator::new(Duration::ZERO, Duration::ZERO, Duration::ZERO);
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:abc"));
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:abc"));
+    }
+
+    #[test]
+    fn ignores_comment_edits_that_keep_the_command() {
+        let coordinator = coordinator();
+
+        assert!(coordinator.register_comment_command("7:1:1", None));
+        // Edited to add a command
+        assert!(coordinator.register_comment_command("7:1:1", Some(SlashCommand::Preview)));
+        // Edited again, same command
+        assert!(!coordinator.register_comment_command("7:1:1", Some(SlashCommand::Preview)));
+        assert!(coordinator.register_comment_command("7:1:1", Some(SlashCommand::Delete)));
+        assert!(coordinator.register_comment_command("7:1:2", Some(SlashCommand::Delete)));
+        assert_eq!(coordinator.snapshot().deduped_total, 1);
+    }
+
+    #[test]
+    fn detects_drafts_marked_ready() {
+        let coordinator = coordinator();
+
+        // Never seen as a draft
+        assert!(!coordinator.record_draft_state("pr-7", false));
+        assert!(!coordinator.record_draft_state("pr-7", true));
+        assert!(!coordinator.record_draft_state("pr-7", true));
+        assert!(!coordinator.record_draft_state("pr-8", false));
+        assert!(coordinator.record_draft_state("pr-7", false));
+        // Only the transition counts
+        assert!(!coordinator.record_draft_state("pr-7", false));
+
+        // A closed draft is gone, even if its number shows up again
+        assert!(!coordinator.record_draft_state("pr-9", true));
+        coordinator.forget_closed_pr("pr-9");
+        assert!(!coordinator.record_draft_state("pr-9", false));
+    }
+
+    #[test]
+    fn redeploy_threads_are_forgotten_once_their_pr_closes() {
+        let coordinator = coordinator();
+        let thread = AzureCommentRef {
+            thread_id: 12,
+            comment_id: 1,
+        };
+
+        assert_eq!(coordinator.redeploy_thread("pr-7"), None);
+        coordinator.record_redeploy_thread("pr-7", thread);
+        assert_eq!(coordinator.redeploy_thread("pr-7"), Some(thread));
+        coordinator.forget_closed_pr("pr-7");
+        assert_eq!(coordinator.redeploy_thread("pr-7"), None);
+    }
+
+    #[test]
+    fn forgotten_keys_are_processed_again() {
+        let coordinator = coordinator();
+
+        assert!(coordinator.register_event("evt-1"));
+        coordinator.forget_event("evt-1");
+        assert!(coordinator.register_event("evt-1"));
+
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:abc"));
+        coordinator.forget_payload("updated:7:refs/heads/a:abc");
+        assert!(coordinator.register_payload("updated:7:refs/heads/a:abc"));
+
+        assert!(coordinator.register_comment_command("7:1:1", Some(SlashCommand::Preview)));
+        coordinator.forget_comment_command("7:1:1");
+        assert!(coordinator.register_comment_command("7:1:1", Some(SlashCommand::Preview)));
+
+        assert_eq!(coordinator.snapshot().deduped_total, 0);
+    }
+
+    #[tokio::test]
+    async fn coalesces_rapid_redeploys_into_the_last_one() {
+        let coordinator = Arc::new(WebhookCoordinator::new(
+            Duration::from_secs(600),
+            Duration::from_secs(30),
+            Duration::from_millis(50),
+        ));
+
+        let first = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move { coordinator.coalesce_redeploy("pr-1").await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let (last, other) = tokio::join!(
+            coordinator.coalesce_redeploy("pr-1"),
+            coordinator.coalesce_redeploy("pr-2"),
+        );
+
+        assert!(!first.await.unwrap());
+        assert!(last);
+        assert!(other);
+        assert_eq!(coordinator.snapshot().coalesced_total, 1);
+    }
+
+    #[tokio::test]
+    async fn zero_window_never_coalesces() {
+        let coordinator = WebhookCoordinator::new(
+            Duration::from_secs(600),
+            Duration::from_secs(30),
+            Duration::ZERO,
+        );
+
+        assert!(coordinator.coalesce_redeploy("pr-1").await);
+        assert!(coordinator.coalesce_redeploy("pr-1").await);
+    }
+
+    #[tokio::test]
+    async fn serializes_operations_on_same_identifier() {
+        let coordinator = Arc::new(coordinator());
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let coordinator = coordinator.clone();
+                let running = running.clone();
+                let max_running = max_running.clone();
+                tokio::spawn(async move {
+                    let _guard = coordinator.lock("pr-1").await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        assert!(coordinator.snapshot().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn different_identifiers_do_not_block_each_other() {
+        let coordinator = coordinator();
+
+        let _first = coordinator.lock("pr-1").await;
+        let second = tokio::time::timeout(Duration::from_secs(1), coordinator.lock("pr-2")).await;
+
+        assert!(second.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiter_is_not_left_pending() {
+        let coordinator = coordinator();
+
+        let guard = coordinator.lock("pr-1").await;
+        let waited =
+            tokio::time::timeout(Duration::from_millis(10), coordinator.lock("pr-1")).await;
+        assert!(waited.is_err());
+
+        drop(guard);
+        assert!(coordinator.snapshot().pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tracks_pending_operations_until_released() {
+        let coordinator = Arc::new(coordinator());
+
+        let guard = coordinator.lock("pr-1").await;
+        let waiter = {
+            let coordinator = coordinator.clone();
+            tokio::spawn(async move {
+                let _guard = coordinator.lock("pr-1").await;
+            })
+        };
+        // Wait for the second operation to queue up behind the first
+        while coordinator.snapshot().pending.get("pr-1") != Some(&2) {
+            tokio::task::yield_now().await;
+        }
+
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(coordinator.snapshot().pending.is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use spinploy::{ComposeDetail, DokployClient};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::api::types::PreviewStatus;
+
+/// Pause between `compose.one` polls of a watched deploy. Builds take minutes, so
+/// there's no point asking as often as a `?wait=true` request does.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Deploys watched at once; further ones aren't followed up on
+const MAX_WATCHED_DEPLOYS: usize = 32;
+
+/// Follows triggered deploys in the background until they finish, to report how they
+/// ended once the webhook that triggered them has long been answered.
+pub struct DeployWatcher {
+    tasks: Mutex<JoinSet<()>>,
+    timeout: Duration,
+    /// Set on shutdown, to make every watch stop
+    stopping: watch::Sender<bool>,
+}
+
+impl DeployWatcher {
+    /// Watches give up on deploys still going after `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            tasks: Mutex::new(JoinSet::new()),
+            timeout,
+            stopping: watch::Sender::new(false),
+        }
+    }
+
+    /// Polls `compose_id` until the deploy after its `previous` latest deployment
+    /// finishes, then calls `on_finished` with its status. It's called with `None` if
+    /// the deploy isn't followed up on to the end: it's still going after the timeout,
+    /// the watcher shuts down first, or `MAX_WATCHED_DEPLOYS` are watched already, in
+    /// which case this returns `false`.
+    pub fn watch<F, Fut>(
+        &self,
+        dokploy_client: Arc<DokployClient>,
+        api_key: String,
+        compose_id: String,
+        previous: Option<String>,
+        on_finished: F,
+    ) -> bool
+    where
+        F: FnOnce(Option<PreviewStatus>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().expect("deploy watcher mutex poisoned");
+        while tasks.try_join_next().is_some() {}
+        if tasks.len() >= MAX_WATCHED_DEPLOYS {
+            tracing::warn!(
+                compose_id,
+                "Too many deploys watched; not following this one up"
+            );
+            tokio::spawn(on_finished(None));
+            return false;
+        }
+
+        let timeout = self.timeout;
+        let mut stopping = self.stopping.subscribe();
+        tasks.spawn(async move {
+            let finished =
+                wait_until_finished(&dokploy_client, &api_key, &compose_id, previous.as_deref());
+            let status = tokio::select! {
+                finished = tokio::time::timeout(timeout, finished) => {
+                    if finished.is_err() {
+                        tracing::warn!(compose_id, "Deploy still not finished; stopped watching");
+                    }
+                    finished.ok()
+                }
+                _ = stopping.wait_for(|stopping| *stopping) => {
+                    tracing::info!(compose_id, "Shutting down; stopped watching deploy");
+                    None
+                }
+            };
+            on_finished(status).await;
+        });
+        true
+    }
+
+    /// Resolves once [`shutdown`](Self::shutdown) is called, for other waits on deploys
+    /// to give up along with the watches.
+    pub async fn shutting_down(&self) {
+        let mut stopping = self.stopping.subscribe();
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+
+    /// Stops every watch, e.g. on shutdown, and waits for them to report that they
+    /// stopped.
+    pub async fn shutdown(&self) {
+        self.stopping.send_replace(true);
+        let mut tasks =
+            std::mem::take(&mut *self.tasks.lock().expect("deploy watcher mutex poisoned"));
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+async fn wait_until_finished(
+    dokploy_client: &DokployClient,
+    api_key: &str,
+    compose_id: &str,
+    previous: Option<&str>,
+) -> PreviewStatus {
+    loop {
+        match dokploy_client.get_compose_detail(api_key, compose_id).await {
+            Ok(detail) => {
+                if let Some(status) = finished_deploy_status(&detail, previous) {
+                    return status;
+                }
+            }
+            // Keep going: Dokploy being briefly unreachable doesn't end the deploy
+            Err(e) => tracing::warn!(error = %e, compose_id, "Failed to poll watched deploy"),
+        }
+        tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+    }
+}
+
+/// How the deploy after the `previous` latest deployment ended, or `None` while it's
+/// queued or building.
+pub(crate) fn finished_deploy_status(
+    detail: &ComposeDetail,
+    previous: Option<&str>,
+) -> Option<PreviewStatus> {
+    let latest = detail.latest_deployment()?;
+    if Some(latest.deployment_id.as_str()) == previous || !latest.is_finished() {
+        return None;
+    }
+    match latest.status.as_deref().map(str::to_lowercase).as_deref() {
+        Some("error" | "cancelled") => Some(PreviewStatus::Failed),
+        // `done`, or a finish time without a status
+        _ => Some(PreviewStatus::Running),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    fn detail(deployments: serde_json::Value) -> ComposeDetail {
+        serde_json::from_value(serde_json::json!({
+            "composeId": "compose-1",
+            "deployments": deployments,
+        }))
+        .unwrap()
+    }
+
+    /// Watches a deploy on a Dokploy that never answers, returning what it reports
+    fn watch_unreachable(watcher: &DeployWatcher) -> oneshot::Receiver<Option<PreviewStatus>> {
+        let (tx, rx) = oneshot::channel();
+        let client = DokployClient::new("http://127.0.0.1:9/api", Default::default());
+        let watching = watcher.watch(
+            Arc::new(client),
+            "key".to_string(),
+            "compose-1".to_string(),
+            None,
+            |status| async move {
+                let _ = tx.send(status);
+            },
+        );
+        assert!(watching);
+        rx
+    }
+
+    #[tokio::test]
+    async fn reports_an_unknown_outcome_on_timeout() {
+        let watcher = DeployWatcher::new(Duration::from_millis(50));
+
+        let reported = watch_unreachable(&watcher);
+
+        let status = tokio::time::timeout(Duration::from_secs(5), reported).await;
+        assert_eq!(status.unwrap().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn reports_an_unknown_outcome_on_shutdown() {
+        let watcher = DeployWatcher::new(Duration::from_secs(60));
+        let mut reported = watch_unreachable(&watcher);
+
+        tokio::time::timeout(Duration::from_secs(5), watcher.shutdown())
+            .await
+            .unwrap();
+
+        assert_eq!(reported.try_recv().unwrap(), None);
+    }
+
+    #[test]
+    fn waits_for_a_deployment_after_the_previous_one() {
+        let old = detail(serde_json::json!([
+            { "deploymentId": "d1", "status": "done", "createdAt": "2024-01-01T00:00:00Z" }
+        ]));
+        assert_eq!(finished_deploy_status(&old, Some("d1")), None);
+        // Without a previous one, any finished deployment counts
+        assert_eq!(
+            finished_deploy_status(&old, None),
+            Some(PreviewStatus::Running)
+        );
+        assert_eq!(
+            finished_deploy_status(&detail(serde_json::json!([])), None),
+            None
+        );
+    }
+
+    #[test]
+    fn detects_terminal_states() {
+        let status_of = |deployment: serde_json::Value| {
+            let mut deployment = deployment;
+            deployment["deploymentId"] = "d2".into();
+            deployment["createdAt"] = "2024-01-02T00:00:00Z".into();
+            let old = serde_json::json!({
+                "deploymentId": "d1", "status": "done", "createdAt": "2024-01-01T00:00:00Z"
+            });
+            finished_deploy_status(&detail(serde_json::json!([old, deployment])), Some("d1"))
+        };
+
+        assert_eq!(status_of(serde_json::json!({ "status": "running" })), None);
+        assert_eq!(status_of(serde_json::json!({ "status": "queued" })), None);
+        assert_eq!(
+            status_of(serde_json::json!({ "status": "done" })),
+            Some(PreviewStatus::Running)
+        );
+        assert_eq!(
+            status_of(serde_json::json!({ "status": "Error" })),
+            Some(PreviewStatus::Failed)
+        );
+        assert_eq!(
+            status_of(serde_json::json!({ "status": "cancelled" })),
+            Some(PreviewStatus::Failed)
+        );
+        assert_eq!(
+            status_of(serde_json::json!({ "finishedAt": "2024-01-02T00:05:00Z" })),
+            Some(PreviewStatus::Running)
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// How long a lookup may take before the check fails
+const DNS_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Host looked up under the base domain. Previews get their own subdomains, so any
+/// name resolves when the wildcard record is in place.
+pub fn probe_host(base_domain: &str) -> String {
+    format!("spinploy-dns-check.{}", base_domain)
+}
+
+/// Checks that a preview host under `base_domain` resolves, using `resolve` to look it up.
+/// The error explains what's likely misconfigured.
+pub async fn check_base_domain<F, Fut>(base_domain: &str, resolve: F) -> Result<(), String>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = io::Result<Vec<IpAddr>>>,
+{
+    let host = probe_host(base_domain);
+    let problem = match tokio::time::timeout(DNS_TIMEOUT, resolve(host.clone())).await {
+        Ok(Ok(addrs)) if !addrs.is_empty() => return Ok(()),
+        Ok(Ok(_)) => "resolved to no addresses".to_string(),
+        Ok(Err(e)) => format!("does not resolve ({})", e),
+        Err(_) => format!("did not resolve within {}s", DNS_TIMEOUT.as_secs()),
+    };
+    Err(format!(
+        "{} {}; preview URLs won't work without a wildcard DNS record for *.{}",
+        host, problem, base_domain
+    ))
+}
+
+/// Resolves `host` with the system resolver.
+pub async fn system_resolve(host: String) -> io::Result<Vec<IpAddr>> {
+    let addrs = tokio::net::lookup_host((host.as_str(), 443)).await?;
+    Ok(addrs.map(|addr| addr.ip()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn passes_when_the_probe_host_resolves() {
+        let result = check_base_domain("preview.example.com", |host| async move {
+            assert_eq!(host, "spinploy-dns-check.preview.example.com");
+            Ok(vec![IpAddr::from([10, 0, 0, 1])])
+        })
+        .await;
+
+        assert_eq!(result, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn explains_lookup_failures() {
+        let err = check_base_domain("preview.example.com", |_| async {
+            Err(io::Error::other("no such host"))
+        })
+        .await
+        .unwrap_err();
+        assert!(err.contains("does not resolve (no such host)"), "{err}");
+        assert!(err.contains("*.preview.example.com"), "{err}");
+
+        let err = check_base_domain("preview.example.com", |_| async { Ok(vec![]) })
+            .await
+            .unwrap_err();
+        assert!(err.contains("resolved to no addresses"), "{err}");
+    }
+}
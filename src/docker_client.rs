@@ -1,14 +1,29 @@
 use std::collections::HashMap;
+use std::path::Path;
 
-use bollard::container::{ListContainersOptions, LogsOptions};
-use bollard::Docker;
-use futures_util::StreamExt;
+use bollard::container::{
+    CPUStats, ListContainersOptions, LogOutput, LogsOptions, MemoryStats, MemoryStatsStats,
+    RestartContainerOptions, StatsOptions,
+};
+use bollard::{API_DEFAULT_VERSION, Docker};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::telemetry;
+
+/// Log lines buffered per stream unless set with [`DockerClient::with_log_buffer`]
+pub const DEFAULT_LOG_BUFFER: usize = 100;
+
+/// Request timeout for Docker API calls, as in bollard's connect defaults
+const DOCKER_TIMEOUT_SECS: u64 = 120;
 
 /// A wrapper around the Docker client for container log streaming.
 #[derive(Clone)]
 pub struct DockerClient {
     docker: Docker,
+    log_buffer: usize,
 }
 
 impl DockerClient {
@@ -16,12 +31,76 @@ impl DockerClient {
     /// Expects /var/run/docker.sock to be mounted.
     pub fn new() -> Result<Self, bollard::errors::Error> {
         let docker = Docker::connect_with_socket_defaults()?;
-        Ok(Self { docker })
+        Ok(Self {
+            docker,
+            log_buffer: DEFAULT_LOG_BUFFER,
+        })
+    }
+
+    /// Creates a DockerClient for the daemon at `docker_host`, a `DOCKER_HOST`-style URL
+    /// (`unix:///var/run/docker.sock`, `tcp://host:2375`, `https://host:2376`).
+    ///
+    /// With `cert_path`, TCP connections use TLS with `key.pem`, `cert.pem` and `ca.pem`
+    /// from that directory. Falls back to the local socket when `docker_host` is `None`.
+    /// Nothing is sent until the first call; use [`ping`](Self::ping) to check the host
+    /// is reachable.
+    pub fn new_with_config(
+        docker_host: Option<&str>,
+        cert_path: Option<&Path>,
+    ) -> Result<Self, bollard::errors::Error> {
+        let Some(host) = docker_host else {
+            return Self::new();
+        };
+
+        let docker = match (host.split_once("://"), cert_path) {
+            (Some(("unix", _)), _) => {
+                Docker::connect_with_socket(host, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)?
+            }
+            (Some(("tcp" | "http" | "https", _)), Some(cert_path)) => Docker::connect_with_ssl(
+                host,
+                &cert_path.join("key.pem"),
+                &cert_path.join("cert.pem"),
+                &cert_path.join("ca.pem"),
+                DOCKER_TIMEOUT_SECS,
+                API_DEFAULT_VERSION,
+            )?,
+            (Some(("tcp" | "http", _)), None) => {
+                Docker::connect_with_http(host, DOCKER_TIMEOUT_SECS, API_DEFAULT_VERSION)?
+            }
+            _ => {
+                return Err(bollard::errors::Error::UnsupportedURISchemeError {
+                    uri: host.to_string(),
+                });
+            }
+        };
+
+        Ok(Self {
+            docker,
+            log_buffer: DEFAULT_LOG_BUFFER,
+        })
+    }
+
+    /// Checks the Docker daemon answers.
+    pub async fn ping(&self) -> Result<(), String> {
+        self.docker
+            .ping()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Docker daemon unreachable: {}", e))
+    }
+
+    /// Sets how many log lines a stream buffers for a slow reader (at least 1).
+    pub fn with_log_buffer(mut self, size: usize) -> Self {
+        self.log_buffer = size.max(1);
+        self
     }
 
     /// Streams logs from a container by name.
     /// Returns a receiver that yields log lines as they arrive.
     ///
+    /// Lines are never dropped: once the receiver falls a full buffer behind, reading
+    /// from Docker pauses until it catches up (counted in `spinploy_log_stream_stalls_total`).
+    ///
     /// # Arguments
     /// * `container_name` - The container name (not ID)
     /// * `tail` - Number of lines to return from the end of the logs (0 = all)
@@ -32,13 +111,37 @@ impl DockerClient {
         tail: u64,
         follow: bool,
     ) -> Result<mpsc::Receiver<Result<String, String>>, String> {
+        self.forward_logs(container_name, tail, follow, |output| output.to_string())
+            .await
+    }
+
+    /// Like [`stream_logs`](Self::stream_logs), but splits each line into its
+    /// timestamp, output stream and message.
+    pub async fn stream_log_lines(
+        &self,
+        container_name: &str,
+        tail: u64,
+        follow: bool,
+    ) -> Result<mpsc::Receiver<Result<LogLine, String>>, String> {
+        self.forward_logs(container_name, tail, follow, LogLine::from_output)
+            .await
+    }
+
+    /// Spawns a task forwarding container log output, converted by `convert`, to a channel.
+    async fn forward_logs<T: Send + 'static>(
+        &self,
+        container_name: &str,
+        tail: u64,
+        follow: bool,
+        convert: fn(LogOutput) -> T,
+    ) -> Result<mpsc::Receiver<Result<T, String>>, String> {
         // Verify container exists first
         self.docker
             .inspect_container(container_name, None)
             .await
             .map_err(|e| format!("Container '{}' not found: {}", container_name, e))?;
 
-        let (tx, rx) = mpsc::channel(100);
+        let (tx, rx) = mpsc::channel(self.log_buffer);
 
         let options = LogsOptions::<String> {
             follow,
@@ -57,19 +160,13 @@ impl DockerClient {
         let container = container_name.to_string();
 
         tokio::spawn(async move {
-            let mut stream = docker.logs(&container, Some(options));
-
-            while let Some(result) = stream.next().await {
-                let msg = match result {
-                    Ok(output) => Ok(output.to_string()),
+            let stream = docker
+                .logs(&container, Some(options))
+                .map(|result| match result {
+                    Ok(output) => Ok(convert(output)),
                     Err(e) => Err(format!("Log stream error: {}", e)),
-                };
-
-                if tx.send(msg).await.is_err() {
-                    // Receiver dropped, stop streaming
-                    break;
-                }
-            }
+                });
+            forward_with_backpressure(stream, tx).await;
         });
 
         Ok(rx)
@@ -108,6 +205,141 @@ impl DockerClient {
             })
             .collect())
     }
+
+    /// Restarts a container by name, giving it `stop_timeout_secs` to stop before it's killed.
+    /// Returns `false` if no container with that name exists.
+    pub async fn restart_container(
+        &self,
+        container_name: &str,
+        stop_timeout_secs: isize,
+    ) -> Result<bool, String> {
+        let options = RestartContainerOptions {
+            t: stop_timeout_secs,
+        };
+        match self
+            .docker
+            .restart_container(container_name, Some(options))
+            .await
+        {
+            Ok(()) => Ok(true),
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, ..
+            }) => Ok(false),
+            Err(e) => Err(format!(
+                "Failed to restart container '{}': {}",
+                container_name, e
+            )),
+        }
+    }
+
+    /// Takes a single CPU/memory sample of a container by name.
+    /// Returns `None` if the container exists but isn't running.
+    pub async fn container_stats(
+        &self,
+        container_name: &str,
+    ) -> Result<Option<ContainerStats>, String> {
+        let inspect = self
+            .docker
+            .inspect_container(container_name, None)
+            .await
+            .map_err(|e| format!("Container '{}' not found: {}", container_name, e))?;
+        let running = inspect.state.and_then(|s| s.running).unwrap_or(false);
+        if !running {
+            return Ok(None);
+        }
+
+        // Not streamed, but not `one_shot` either: Docker then waits for a second
+        // sample so `precpu_stats` is filled in and CPU usage can be computed.
+        let options = StatsOptions {
+            stream: false,
+            one_shot: false,
+        };
+        match self
+            .docker
+            .stats(container_name, Some(options))
+            .next()
+            .await
+        {
+            Some(Ok(stats)) => Ok(Some(ContainerStats {
+                cpu_percent: cpu_percent(&stats.cpu_stats, &stats.precpu_stats),
+                memory_usage_bytes: memory_usage(&stats.memory_stats),
+                memory_limit_bytes: stats.memory_stats.limit.unwrap_or(0),
+            })),
+            Some(Err(e)) => Err(format!("Failed to get container stats: {}", e)),
+            // Container stopped between inspect and stats
+            None => Ok(None),
+        }
+    }
+}
+
+/// Status of a container's Docker healthcheck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerHealth {
+    Healthy,
+    Unhealthy,
+    Starting,
+}
+
+/// Resource usage sample of a running container.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerStats {
+    /// Percent of a single CPU, so may exceed 100 on multi-core hosts (like `docker stats`)
+    pub cpu_percent: f64,
+    pub memory_usage_bytes: u64,
+    pub memory_limit_bytes: u64,
+}
+
+/// Sends every item of `stream` to `tx`, waiting for room when the channel is full
+/// rather than dropping lines. Stops when the receiver is dropped.
+async fn forward_with_backpressure<T>(stream: impl Stream<Item = T>, tx: mpsc::Sender<T>) {
+    let mut stream = std::pin::pin!(stream);
+    while let Some(item) = stream.next().await {
+        let item = match tx.try_send(item) {
+            Ok(()) => continue,
+            Err(TrySendError::Full(item)) => item,
+            // Receiver dropped, stop streaming
+            Err(TrySendError::Closed(_)) => break,
+        };
+        telemetry::record_log_stream_stall();
+        if tx.send(item).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// CPU usage between two samples, computed the same way as `docker stats`.
+fn cpu_percent(cpu: &CPUStats, precpu: &CPUStats) -> f64 {
+    let cpu_delta = cpu
+        .cpu_usage
+        .total_usage
+        .saturating_sub(precpu.cpu_usage.total_usage);
+    let system_delta = cpu
+        .system_cpu_usage
+        .unwrap_or(0)
+        .saturating_sub(precpu.system_cpu_usage.unwrap_or(0));
+    if cpu_delta == 0 || system_delta == 0 {
+        return 0.0;
+    }
+
+    let online_cpus = cpu
+        .online_cpus
+        .or_else(|| cpu.cpu_usage.percpu_usage.as_ref().map(|p| p.len() as u64))
+        .unwrap_or(1);
+    cpu_delta as f64 / system_delta as f64 * online_cpus as f64 * 100.0
+}
+
+/// Memory usage excluding reclaimable page cache, like `docker stats`.
+fn memory_usage(memory: &MemoryStats) -> u64 {
+    let inactive_file = match memory.stats {
+        Some(MemoryStatsStats::V1(stats)) => stats.total_inactive_file,
+        Some(MemoryStatsStats::V2(stats)) => stats.inactive_file,
+        None => 0,
+    };
+    memory.usage.unwrap_or(0).saturating_sub(inactive_file)
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -118,3 +350,229 @@ pub struct ContainerInfo {
     pub state: String,
     pub status: String,
 }
+
+impl ContainerInfo {
+    /// Result of the container's Docker healthcheck, read from the status Docker lists
+    /// it with (e.g. `Up 5 minutes (healthy)`) rather than inspecting each container.
+    /// `None` if it isn't running or has no healthcheck.
+    pub fn health(&self) -> Option<ContainerHealth> {
+        if self.state != "running" {
+            return None;
+        }
+        let status = self.status.to_lowercase();
+        if status.contains("(healthy)") {
+            Some(ContainerHealth::Healthy)
+        } else if status.contains("(unhealthy)") {
+            Some(ContainerHealth::Unhealthy)
+        } else if status.contains("(health: starting)") {
+            Some(ContainerHealth::Starting)
+        } else {
+            None
+        }
+    }
+}
+
+/// Output stream a container log line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    Stdin,
+    Console,
+}
+
+/// A single container log line with the Docker timestamp split from the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LogLine {
+    /// RFC 3339 timestamp added by Docker, if the line had one
+    pub timestamp: Option<String>,
+    pub stream: LogStream,
+    pub message: String,
+}
+
+impl LogLine {
+    fn from_output(output: LogOutput) -> Self {
+        let (stream, bytes) = match output {
+            LogOutput::StdOut { message } => (LogStream::Stdout, message),
+            LogOutput::StdErr { message } => (LogStream::Stderr, message),
+            LogOutput::StdIn { message } => (LogStream::Stdin, message),
+            LogOutput::Console { message } => (LogStream::Console, message),
+        };
+        Self::parse(stream, &String::from_utf8_lossy(&bytes))
+    }
+
+    /// Parses a `timestamps: true` line of the form `<rfc3339> <message>`.
+    /// Lines without a leading timestamp are kept whole as the message.
+    fn parse(stream: LogStream, line: &str) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (timestamp, message) = match line.split_once(' ') {
+            Some((ts, rest)) if crate::parse_ts(ts).is_some() => (Some(ts.to_string()), rest),
+            _ => (None, line),
+        };
+
+        Self {
+            timestamp,
+            stream,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamped_line() {
+        let line = LogLine::parse(
+            LogStream::Stderr,
+            "2024-05-01T12:00:00.123456789Z listening on :8080\n",
+        );
+
+        assert_eq!(
+            line.timestamp.as_deref(),
+            Some("2024-05-01T12:00:00.123456789Z")
+        );
+        assert_eq!(line.stream, LogStream::Stderr);
+        assert_eq!(line.message, "listening on :8080");
+    }
+
+    fn cpu_stats(total_usage: u64, system_cpu_usage: u64) -> CPUStats {
+        CPUStats {
+            cpu_usage: bollard::container::CPUUsage {
+                percpu_usage: None,
+                usage_in_usermode: 0,
+                total_usage,
+                usage_in_kernelmode: 0,
+            },
+            system_cpu_usage: Some(system_cpu_usage),
+            online_cpus: Some(4),
+            throttling_data: bollard::container::ThrottlingData {
+                periods: 0,
+                throttled_periods: 0,
+                throttled_time: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn computes_cpu_percent_across_online_cpus() {
+        let percent = cpu_percent(&cpu_stats(300, 2_000), &cpu_stats(100, 1_000));
+
+        assert!((percent - 80.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn cpu_percent_is_zero_without_previous_sample() {
+        let mut precpu = cpu_stats(0, 0);
+        precpu.system_cpu_usage = None;
+
+        assert_eq!(cpu_percent(&cpu_stats(0, 2_000), &precpu), 0.0);
+    }
+
+    #[test]
+    fn keeps_line_without_timestamp_as_message() {
+        let line = LogLine::parse(LogStream::Stdout, "plain output line");
+
+        assert_eq!(line.timestamp, None);
+        assert_eq!(line.message, "plain output line");
+    }
+
+    #[test]
+    fn reads_health_from_the_listed_status() {
+        let container = |state: &str, status: &str| ContainerInfo {
+            id: "abc".to_string(),
+            names: vec!["/preview-pr-1-web-1".to_string()],
+            image: "web".to_string(),
+            state: state.to_string(),
+            status: status.to_string(),
+        };
+
+        let health = |state, status| container(state, status).health();
+        assert_eq!(
+            health("running", "Up 5 minutes (healthy)"),
+            Some(ContainerHealth::Healthy)
+        );
+        assert_eq!(
+            health("running", "Up 2 hours (unhealthy)"),
+            Some(ContainerHealth::Unhealthy)
+        );
+        assert_eq!(
+            health("running", "Up 3 seconds (health: starting)"),
+            Some(ContainerHealth::Starting)
+        );
+        assert_eq!(health("running", "Up 5 minutes"), None);
+        assert_eq!(health("exited", "Exited (1) 2 minutes ago"), None);
+    }
+
+    #[tokio::test]
+    async fn connects_to_docker_host_urls() {
+        // Connecting is lazy; the refused port only shows up on ping
+        let client = DockerClient::new_with_config(Some("tcp://127.0.0.1:1"), None).unwrap();
+        let err = client.ping().await.unwrap_err();
+        assert!(err.starts_with("Docker daemon unreachable"), "{err}");
+
+        assert!(DockerClient::new_with_config(Some("ssh://docker@host"), None).is_err());
+        // Plain HTTP can't reach a TLS endpoint
+        assert!(DockerClient::new_with_config(Some("https://host:2376"), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_gets_every_line_in_order() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let producer = tokio::spawn(forward_with_backpressure(
+            futures_util::stream::iter(0..20),
+            tx,
+        ));
+
+        let mut received = Vec::new();
+        while let Some(line) = rx.recv().await {
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+            received.push(line);
+        }
+
+        producer.await.unwrap();
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn counts_stalls_on_a_full_buffer() {
+        let recorder = telemetry::prometheus_builder().build_recorder();
+        let handle = recorder.handle();
+        let _recorder = metrics::set_default_local_recorder(&recorder);
+
+        // Room for every line: nothing waits
+        let (tx, _rx) = mpsc::channel(4);
+        forward_with_backpressure(futures_util::stream::iter(0..4), tx).await;
+        assert!(!handle.render().contains(telemetry::LOG_STREAM_STALLS));
+
+        // A consumer that only reads once the producer waits on it
+        let (tx, mut rx) = mpsc::channel(1);
+        let consume = async {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            while rx.recv().await.is_some() {}
+        };
+        tokio::join!(
+            forward_with_backpressure(futures_util::stream::iter(0..4), tx),
+            consume
+        );
+        let rendered = handle.render();
+        let stalls = rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(telemetry::LOG_STREAM_STALLS))
+            .and_then(|count| count.trim().parse::<u64>().ok());
+        assert!(stalls.is_some_and(|count| count > 0), "{}", rendered);
+    }
+
+    #[tokio::test]
+    async fn stops_when_the_consumer_goes_away() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+
+        // Would never finish on an endless stream if the closed channel went unnoticed
+        forward_with_backpressure(futures_util::stream::repeat(0), tx).await;
+    }
+}
@@ -1,40 +1,209 @@
-use std::time::Duration;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+use crate::config::HttpTimeouts;
 use crate::models::dokploy::{
-    Compose, ComposeDeployRequest, ComposeDetail, CreateComposeRequest, DeleteComposeRequest,
-    Domain, DomainCreateRequest, Project, UpdateComposeRequest,
+    Compose, ComposeDeployRequest, ComposeDetail, ComposeSource, CreateComposeRequest,
+    DeleteComposeRequest, Deployment, Domain, DomainCreateRequest, Project,
+    UpdateComposeDescriptionRequest, UpdateComposeRequest, UpdateComposeSourceRequest,
 };
+use crate::telemetry;
 use anyhow::{Context, Result, bail};
 use futures_util::StreamExt;
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Serialize, de::DeserializeOwned};
-use tokio::sync::mpsc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{http::Request as WsRequest, Message},
+    tungstenite::{Message, http::Request as WsRequest},
 };
 // keep client lean; avoid verbose tracing here
 
+/// Pause between `compose.one` polls while waiting for a deployment
+pub const DEPLOYMENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest a deploy holds its slot; one Dokploy never finishes frees it after this
+const DEPLOY_SLOT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// `compose.one` polls in a row that may fail while waiting for a deployment before
+/// the wait gives up
+const MAX_POLL_FAILURES: u32 = 5;
+
+/// How [`DokployClient::deploy_compose`] handled a deploy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeployStart {
+    /// Triggered on Dokploy
+    Started,
+    /// Every deploy slot is taken; it's triggered in the background once one frees up
+    Queued,
+}
+
 /// Lightweight wrapper around the Dokploy API using manual reqwest calls.
 #[derive(Clone, Debug)]
 pub struct DokployClient {
     base_url: String,
     http: reqwest::Client,
+    /// Slots for deploys running on Dokploy; `None` when deploys aren't limited
+    deploy_slots: Option<Arc<Semaphore>>,
+    /// Pause between `compose.one` polls while waiting for a deployment
+    poll_interval: Duration,
+    /// Composes by name for [`find_compose_by_name`](Self::find_compose_by_name); `None` when disabled
+    compose_index: Option<Arc<ComposeIndex>>,
+}
+
+/// The composes a lookup by name considers: those created with `app_name_prefix` in one
+/// of `environment_ids`. Other bots sharing the Dokploy, and repositories deploying to
+/// other environments, have their own composes of the same name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComposeScope {
+    pub app_name_prefix: String,
+    pub environment_ids: Vec<String>,
+}
+
+impl ComposeScope {
+    pub fn contains(&self, compose: &Compose) -> bool {
+        compose.app_name.starts_with(&self.app_name_prefix)
+            && self.environment_ids.contains(&compose.environment_id)
+    }
+}
+
+/// Composes by name as of the last `project.all` fetch, kept current by this client's
+/// creates and deletes. Composes changed in Dokploy directly show up on the next fetch,
+/// and ones deleted there are dropped as soon as a request on them gets a 404.
+#[derive(Debug, Default)]
+struct ComposeIndex {
+    entries: RwLock<IndexedComposes>,
+}
+
+#[derive(Debug, Default)]
+struct IndexedComposes {
+    /// Key the index was fetched with; another key may not see the same projects
+    api_key: String,
+    /// Every compose of each name, whatever its scope
+    by_name: HashMap<String, Vec<Compose>>,
+    /// Bumped by every insert and removal, so a fetch that started before one doesn't
+    /// undo it
+    generation: u64,
+}
+
+impl ComposeIndex {
+    /// The compose named `name` in `scope`, if exactly one is indexed. Lookups that
+    /// would be ambiguous go to Dokploy, to fail there as they would without the index.
+    fn get(&self, api_key: &str, scope: &ComposeScope, name: &str) -> Option<Compose> {
+        let entries = self.entries.read().expect("compose index lock poisoned");
+        if entries.api_key != api_key {
+            return None;
+        }
+        let mut matching = entries
+            .by_name
+            .get(name)?
+            .iter()
+            .filter(|c| scope.contains(c));
+        match (matching.next(), matching.next()) {
+            (Some(compose), None) => Some(compose.clone()),
+            _ => None,
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.entries
+            .read()
+            .expect("compose index lock poisoned")
+            .generation
+    }
+
+    /// Replaces the index with `projects`, fetched when the index was at `generation`.
+    /// A fetch that raced a create or delete may predate it, so it's dropped instead.
+    fn rebuild(&self, api_key: &str, projects: &[Project], generation: u64) {
+        let mut by_name: HashMap<String, Vec<Compose>> = HashMap::new();
+        let composes = projects
+            .iter()
+            .flat_map(|project| &project.environments)
+            .flat_map(|env| &env.compose);
+        for compose in composes {
+            by_name
+                .entry(compose.name.clone())
+                .or_default()
+                .push(compose.clone());
+        }
+
+        let mut entries = self.entries.write().expect("compose index lock poisoned");
+        if entries.generation != generation {
+            tracing::debug!("Composes changed while fetching projects; not indexing the fetch");
+            return;
+        }
+        *entries = IndexedComposes {
+            api_key: api_key.to_string(),
+            by_name,
+            generation,
+        };
+    }
+
+    fn insert(&self, api_key: &str, compose: &Compose) {
+        let mut entries = self.entries.write().expect("compose index lock poisoned");
+        entries.generation += 1;
+        if entries.api_key == api_key {
+            let named = entries.by_name.entry(compose.name.clone()).or_default();
+            named.retain(|c| c.compose_id != compose.compose_id);
+            named.push(compose.clone());
+        }
+    }
+
+    fn remove(&self, compose_id: &str) {
+        let mut entries = self.entries.write().expect("compose index lock poisoned");
+        entries.generation += 1;
+        for named in entries.by_name.values_mut() {
+            named.retain(|compose| compose.compose_id != compose_id);
+        }
+    }
+}
+
+/// Whether Dokploy answered a request with 404.
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| e.status() == Some(reqwest::StatusCode::NOT_FOUND))
 }
 
 impl DokployClient {
-    pub fn new(base_url: impl AsRef<str>) -> Self {
+    pub fn new(base_url: impl AsRef<str>, timeouts: HttpTimeouts) -> Self {
         let http = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(15))
-            .timeout(Duration::from_secs(30))
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.total)
             .build()
             .expect("failed to build http client");
         Self {
             base_url: base_url.as_ref().trim_end_matches('/').to_string(),
             http,
+            deploy_slots: None,
+            poll_interval: DEPLOYMENT_POLL_INTERVAL,
+            compose_index: None,
         }
     }
 
+    /// Poll for deployments every `interval` instead of [`DEPLOYMENT_POLL_INTERVAL`].
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Answer [`find_compose_by_name`](Self::find_compose_by_name) from an index of the
+    /// last `project.all` fetch, fetching again only for names it doesn't know.
+    pub fn with_compose_index(mut self) -> Self {
+        self.compose_index = Some(Arc::new(ComposeIndex::default()));
+        self
+    }
+
+    /// Run at most `limit` deploys at once; further [`deploy_compose`](Self::deploy_compose)
+    /// calls queue until one of them finishes instead of failing. A `limit` of 0 disables
+    /// the limit.
+    pub fn with_deploy_limit(mut self, limit: usize) -> Self {
+        self.deploy_slots = (limit > 0).then(|| Arc::new(Semaphore::new(limit)));
+        self
+    }
+
     fn auth_headers(api_key: &str) -> Result<HeaderMap> {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -48,18 +217,29 @@ impl DokployClient {
         format!("{}/{}", self.base_url, url.trim_start_matches('/'))
     }
 
+    /// Runs a request, recording its latency under the endpoint name.
+    async fn timed<T>(url: &str, request: impl Future<Output = Result<T>>) -> Result<T> {
+        let started = Instant::now();
+        let result = request.await;
+        telemetry::record_dokploy_request(url, started, result.is_ok());
+        result
+    }
+
     async fn get<T: DeserializeOwned>(&self, api_key: &str, url: &str) -> Result<T> {
-        let resp = self
-            .http
-            .get(self.join_url(url))
-            .headers(Self::auth_headers(api_key)?)
-            .send()
-            .await?
-            .error_for_status()?;
+        Self::timed(url, async {
+            let resp = self
+                .http
+                .get(self.join_url(url))
+                .headers(Self::auth_headers(api_key)?)
+                .send()
+                .await?
+                .error_for_status()?;
 
-        resp.json::<T>()
-            .await
-            .context("failed to deserialize response")
+            resp.json::<T>()
+                .await
+                .context("failed to deserialize response")
+        })
+        .await
     }
 
     async fn post<T: DeserializeOwned>(
@@ -68,50 +248,139 @@ impl DokployClient {
         url: &str,
         body: impl Serialize,
     ) -> Result<T> {
-        let resp = self
-            .http
-            .post(self.join_url(url))
-            .headers(Self::auth_headers(api_key)?)
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
+        Self::timed(url, async {
+            let resp = self
+                .http
+                .post(self.join_url(url))
+                .headers(Self::auth_headers(api_key)?)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?;
 
-        resp.json::<T>()
-            .await
-            .context("failed to deserialize response")
+            resp.json::<T>()
+                .await
+                .context("failed to deserialize response")
+        })
+        .await
+    }
+
+    /// POST helper for endpoints that may answer with an empty body instead of the object.
+    async fn post_optional<T: DeserializeOwned>(
+        &self,
+        api_key: &str,
+        url: &str,
+        body: impl Serialize,
+    ) -> Result<Option<T>> {
+        Self::timed(url, async {
+            let resp = self
+                .http
+                .post(self.join_url(url))
+                .headers(Self::auth_headers(api_key)?)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            let body = resp.text().await?;
+            if body.trim().is_empty() {
+                return Ok(None);
+            }
+            serde_json::from_str::<T>(&body)
+                .map(Some)
+                .context("failed to deserialize response")
+        })
+        .await
     }
 
     /// POST helper for endpoints where the response body is irrelevant.
     async fn post_unit(&self, api_key: &str, url: &str, body: impl Serialize) -> Result<()> {
-        self.http
-            .post(self.join_url(url))
-            .headers(Self::auth_headers(api_key)?)
-            .json(&body)
+        Self::timed(url, async {
+            self.http
+                .post(self.join_url(url))
+                .headers(Self::auth_headers(api_key)?)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Cheap reachability check: any non-5xx answer from Dokploy, including an auth rejection,
+    /// means the upstream is up.
+    pub async fn ping(&self, timeout: Duration) -> Result<()> {
+        let resp = self
+            .http
+            .get(self.join_url("project.all"))
+            .timeout(timeout)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        if resp.status().is_server_error() {
+            bail!("dokploy responded with {}", resp.status());
+        }
         Ok(())
     }
 
     /// Retrieve all projects with nested environments and compose definitions.
+    /// Also refreshes the compose index, if enabled.
     pub async fn fetch_projects(&self, api_key: impl AsRef<str>) -> Result<Vec<Project>> {
-        self.get::<Vec<Project>>(api_key.as_ref(), "project.all")
-            .await
+        let generation = self.compose_index.as_ref().map(|index| index.generation());
+        let projects = self
+            .get::<Vec<Project>>(api_key.as_ref(), "project.all")
+            .await?;
+        if let (Some(index), Some(generation)) = (&self.compose_index, generation) {
+            index.rebuild(api_key.as_ref(), &projects, generation);
+        }
+        Ok(projects)
+    }
+
+    /// Runs a request on the compose `compose_id`. If Dokploy answers 404, the compose
+    /// was deleted behind the index's back, so it's dropped from the index and the next
+    /// lookup by name fetches again.
+    async fn on_compose<T>(
+        &self,
+        compose_id: &str,
+        request: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let result = request.await;
+        if let (Err(e), Some(index)) = (&result, &self.compose_index)
+            && is_not_found(e)
+        {
+            tracing::debug!(compose_id, "Compose not found; dropping it from the index");
+            index.remove(compose_id);
+        }
+        result
+    }
+
+    /// Refetch every project to refresh the compose index, e.g. on a timer.
+    pub async fn refresh_compose_index(&self, api_key: &str) -> Result<()> {
+        self.fetch_projects(api_key).await.map(|_| ())
     }
 
+    /// The compose named `compose_name` in `scope`, failing if there are several.
     pub async fn find_compose_by_name(
         &self,
         api_key: impl AsRef<str> + std::fmt::Debug,
+        scope: &ComposeScope,
         compose_name: impl AsRef<str> + std::fmt::Debug,
     ) -> Result<Option<Compose>> {
+        let indexed = self
+            .compose_index
+            .as_ref()
+            .and_then(|index| index.get(api_key.as_ref(), scope, compose_name.as_ref()));
+        if let Some(compose) = indexed {
+            return Ok(Some(compose));
+        }
+
         let projects = self.fetch_projects(api_key).await?;
 
         let matching_composes: Vec<_> = projects
             .into_iter()
             .flat_map(|project| project.environments.into_iter())
             .flat_map(|env| env.compose.into_iter())
-            .filter(|compose| compose.name == compose_name.as_ref())
+            .filter(|compose| compose.name == compose_name.as_ref() && scope.contains(compose))
             .collect();
 
         match matching_composes.len() {
@@ -140,48 +409,162 @@ impl DokployClient {
         compose_id: impl AsRef<str> + std::fmt::Debug,
         delete_volumes: bool,
     ) -> Result<()> {
-        self.post_unit(
-            api_key,
-            "compose.delete",
-            DeleteComposeRequest {
-                compose_id: compose_id.as_ref().to_string(),
-                delete_volumes,
-            },
-        )
-        .await
+        let body = DeleteComposeRequest {
+            compose_id: compose_id.as_ref().to_string(),
+            delete_volumes,
+        };
+        let deleted = self.post_unit(api_key, "compose.delete", body);
+        self.on_compose(compose_id.as_ref(), deleted).await?;
+        if let Some(index) = &self.compose_index {
+            index.remove(compose_id.as_ref());
+        }
+        Ok(())
     }
 
     pub async fn create_compose(
         &self,
         api_key: &str,
-        environment_id: impl AsRef<str> + std::fmt::Debug,
-        name: impl AsRef<str> + std::fmt::Debug,
-        app_name: impl AsRef<str> + std::fmt::Debug,
+        req: CreateComposeRequest,
     ) -> Result<Compose> {
-        self.post::<Compose>(
-            api_key,
-            "compose.create",
-            CreateComposeRequest {
-                environment_id: environment_id.as_ref().to_string(),
-                name: name.as_ref().to_string(),
-                app_name: app_name.as_ref().to_string(),
-                compose_type: "docker-compose".to_string(),
-            },
-        )
-        .await
+        let compose = self.post::<Compose>(api_key, "compose.create", req).await?;
+        if let Some(index) = &self.compose_index {
+            index.insert(api_key, &compose);
+        }
+        Ok(compose)
     }
 
     /// Update a compose definition.
     pub async fn update_compose(&self, api_key: &str, req: UpdateComposeRequest) -> Result<()> {
-        self.post_unit(api_key, "compose.update", req).await
+        let compose_id = req.compose_id.clone();
+        self.on_compose(&compose_id, self.post_unit(api_key, "compose.update", req))
+            .await
+    }
+
+    /// Replace the source of a compose (repository, branch and compose path, or the raw
+    /// compose file), leaving its other settings as is.
+    pub async fn update_compose_source(
+        &self,
+        api_key: &str,
+        compose_id: impl AsRef<str>,
+        source: ComposeSource,
+    ) -> Result<()> {
+        let body = UpdateComposeSourceRequest {
+            compose_id: compose_id.as_ref().to_string(),
+            source,
+        };
+        let updated = self.post_unit(api_key, "compose.update", body);
+        self.on_compose(compose_id.as_ref(), updated).await
+    }
+
+    /// Replace the description of `compose`, leaving its other settings as is.
+    pub async fn update_compose_description(
+        &self,
+        api_key: &str,
+        compose: &Compose,
+        description: String,
+    ) -> Result<()> {
+        let body = UpdateComposeDescriptionRequest {
+            compose_id: compose.compose_id.clone(),
+            description: description.clone(),
+        };
+        let updated = self.post_unit(api_key, "compose.update", body);
+        self.on_compose(&compose.compose_id, updated).await?;
+        if let Some(index) = &self.compose_index {
+            let compose = Compose {
+                description: Some(description),
+                ..compose.clone()
+            };
+            index.insert(api_key, &compose);
+        }
+        Ok(())
     }
 
-    /// Trigger deployment of a compose.
-    pub async fn deploy_compose(&self, api_key: &str, compose_id: impl AsRef<str>) -> Result<()> {
+    /// Trigger deployment of a compose: pulls the source, rebuilds images and recreates
+    /// the containers. With a [deploy limit](Self::with_deploy_limit) and no free slot,
+    /// the deploy is queued in the background instead, and failures to trigger it are
+    /// only logged. `previous` is the compose's latest deployment from before this
+    /// deploy, which the deploy limit uses to tell when the deploy finished.
+    pub async fn deploy_compose(
+        &self,
+        api_key: &str,
+        compose_id: impl AsRef<str>,
+        previous: Option<&str>,
+    ) -> Result<DeployStart> {
+        let compose_id = compose_id.as_ref();
+        let previous = previous.map(str::to_string);
+        let Some(slots) = &self.deploy_slots else {
+            let body = ComposeDeployRequest {
+                compose_id: compose_id.to_string(),
+            };
+            let deployed = self.post_unit(api_key, "compose.deploy", body);
+            self.on_compose(compose_id, deployed).await?;
+            return Ok(DeployStart::Started);
+        };
+
+        if let Ok(slot) = slots.clone().try_acquire_owned() {
+            self.deploy_in_slot(api_key, compose_id, previous, slot)
+                .await?;
+            return Ok(DeployStart::Started);
+        }
+
+        let (client, slots) = (self.clone(), slots.clone());
+        let (api_key, compose_id) = (api_key.to_string(), compose_id.to_string());
+        tokio::spawn(async move {
+            let Ok(slot) = slots.acquire_owned().await else {
+                return;
+            };
+            let deployed = client.deploy_in_slot(&api_key, &compose_id, previous, slot);
+            if let Err(e) = deployed.await {
+                tracing::warn!(error = %e, compose_id, "Failed to trigger queued deploy");
+            }
+        });
+        Ok(DeployStart::Queued)
+    }
+
+    /// Trigger a deploy holding `slot`, which is freed once the deploy after `previous`
+    /// finishes.
+    async fn deploy_in_slot(
+        &self,
+        api_key: &str,
+        compose_id: &str,
+        previous: Option<String>,
+        slot: OwnedSemaphorePermit,
+    ) -> Result<()> {
+        let body = ComposeDeployRequest {
+            compose_id: compose_id.to_string(),
+        };
+        let deployed = self.post_unit(api_key, "compose.deploy", body);
+        self.on_compose(compose_id, deployed).await?;
+
+        // compose.deploy only queues the deploy, so hold the slot until it finishes
+        let client = self.clone();
+        let (api_key, compose_id) = (api_key.to_string(), compose_id.to_string());
+        tokio::spawn(async move {
+            let _slot = slot;
+            let finished = client
+                .wait_for_triggered_deployment(
+                    &api_key,
+                    &compose_id,
+                    previous.as_deref(),
+                    DEPLOY_SLOT_TIMEOUT,
+                )
+                .await;
+            if let Err(e) = finished {
+                tracing::warn!(error = %e, compose_id, "Failed to follow deploy; freeing its slot");
+            }
+        });
+        Ok(())
+    }
+
+    /// Restart a compose's existing containers (`compose.stop`, then `compose.start`).
+    /// Unlike [`deploy_compose`](Self::deploy_compose) nothing is pulled or rebuilt, so code
+    /// and env changes since the last deploy are not picked up; it's much faster though.
+    pub async fn reload_compose(&self, api_key: &str, compose_id: impl AsRef<str>) -> Result<()> {
         let body = ComposeDeployRequest {
             compose_id: compose_id.as_ref().to_string(),
         };
-        self.post_unit(api_key, "compose.deploy", body).await
+        self.post_unit(api_key, "compose.stop", &body).await?;
+        self.post_unit(api_key, "compose.start", &body).await
     }
 
     /// List domains attached to a compose.
@@ -191,25 +574,34 @@ impl DokployClient {
         compose_id: impl AsRef<str>,
     ) -> Result<Vec<Domain>> {
         let url = format!("domain.byComposeId?composeId={}", compose_id.as_ref());
-        let resp = self
-            .http
-            .get(self.join_url(&url))
-            .headers(Self::auth_headers(api_key)?)
-            .send()
-            .await?
-            .error_for_status()?;
+        Self::timed(&url, async {
+            let resp = self
+                .http
+                .get(self.join_url(&url))
+                .headers(Self::auth_headers(api_key)?)
+                .send()
+                .await?
+                .error_for_status()?;
 
-        let body = resp.text().await?;
-        if body.trim().is_empty() {
-            return Ok(vec![]);
-        }
-        serde_json::from_str::<Vec<Domain>>(&body)
-            .context("failed to deserialize list domains response")
+            let body = resp.text().await?;
+            if body.trim().is_empty() {
+                return Ok(vec![]);
+            }
+            serde_json::from_str::<Vec<Domain>>(&body)
+                .context("failed to deserialize list domains response")
+        })
+        .await
     }
 
-    /// Create a domain for a compose service.
-    pub async fn create_domain(&self, api_key: &str, req: DomainCreateRequest) -> Result<()> {
-        self.post_unit(api_key, "domain.create", req).await
+    /// Create a domain for a compose service. Returns the created domain, or `None` when
+    /// Dokploy answers with an empty body.
+    pub async fn create_domain(
+        &self,
+        api_key: &str,
+        req: DomainCreateRequest,
+    ) -> Result<Option<Domain>> {
+        self.post_optional::<Domain>(api_key, "domain.create", req)
+            .await
     }
 
     /// List composes in a given environment with a given app name prefix
@@ -242,7 +634,59 @@ impl DokployClient {
         compose_id: &str,
     ) -> Result<ComposeDetail> {
         let url = format!("compose.one?composeId={}", compose_id);
-        self.get::<ComposeDetail>(api_key, &url).await
+        self.on_compose(compose_id, self.get::<ComposeDetail>(api_key, &url))
+            .await
+    }
+
+    /// Fetch a compose detail (compose.one) as Dokploy returns it, including the fields
+    /// [`ComposeDetail`] leaves out
+    pub async fn get_compose_detail_raw(
+        &self,
+        api_key: &str,
+        compose_id: &str,
+    ) -> Result<serde_json::Value> {
+        let url = format!("compose.one?composeId={}", compose_id);
+        self.get::<serde_json::Value>(api_key, &url).await
+    }
+
+    /// Poll a compose until the deployment of a deploy triggered on it finishes (`done`,
+    /// `error` or `cancelled`) or `timeout` elapses. `previous` is the latest deployment
+    /// from before the deploy was triggered, which Dokploy still reports as latest until
+    /// its queue picks the deploy up. Returns the deployment as last seen, still in
+    /// progress on timeout, or `None` if it never showed up. A failed poll is retried,
+    /// unless several fail in a row.
+    pub async fn wait_for_triggered_deployment(
+        &self,
+        api_key: &str,
+        compose_id: &str,
+        previous: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Option<Deployment>> {
+        let poll_interval = self.poll_interval;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut failures = 0;
+        loop {
+            let detail = match self.get_compose_detail(api_key, compose_id).await {
+                Ok(detail) => detail,
+                Err(e) if failures + 1 < MAX_POLL_FAILURES => {
+                    failures += 1;
+                    tracing::debug!(error = %e, compose_id, failures, "Deployment poll failed");
+                    tokio::time::sleep(poll_interval).await;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            failures = 0;
+            let deployment = detail
+                .latest_deployment()
+                .filter(|d| Some(d.deployment_id.as_str()) != previous)
+                .cloned();
+            let finished = deployment.as_ref().is_some_and(Deployment::is_finished);
+            if finished || tokio::time::Instant::now() + poll_interval > deadline {
+                return Ok(deployment);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
     }
 
     /// Stream deployment logs via WebSocket connection to Dokploy.
@@ -259,10 +703,7 @@ impl DokployClient {
             .replace("http://", "ws://");
 
         let encoded_log_path = urlencoding::encode(log_path);
-        let full_url = format!(
-            "{}/listen-deployment?logPath={}",
-            ws_url, encoded_log_path
-        );
+        let full_url = format!("{}/listen-deployment?logPath={}", ws_url, encoded_log_path);
 
         tracing::debug!(url = %full_url, "Connecting to Dokploy WebSocket");
 
@@ -278,7 +719,10 @@ impl DokployClient {
             .header("Connection", "Upgrade")
             .header("Upgrade", "websocket")
             .header("Sec-WebSocket-Version", "13")
-            .header("Sec-WebSocket-Key", tokio_tungstenite::tungstenite::handshake::client::generate_key())
+            .header(
+                "Sec-WebSocket-Key",
+                tokio_tungstenite::tungstenite::handshake::client::generate_key(),
+            )
             .body(())
             .context("Failed to build WebSocket request")?;
 
@@ -292,20 +736,17 @@ impl DokployClient {
         // Spawn task to read from WebSocket and forward to channel
         tokio::spawn(async move {
             while let Some(msg_result) = read.next().await {
-                match msg_result {
-                    Ok(Message::Text(text)) => {
-                        if tx.send(Ok(text.to_string())).await.is_err() {
-                            break;
-                        }
-                    }
-                    Ok(Message::Close(_)) => {
-                        break;
-                    }
-                    Err(e) => {
-                        let _ = tx.send(Err(e.to_string())).await;
-                        break;
-                    }
-                    _ => {}
+                let line = match msg_result {
+                    Ok(Message::Text(text)) => Ok(text.to_string()),
+                    Ok(Message::Close(_)) => break,
+                    Err(e) => Err(e.to_string()),
+                    _ => continue,
+                };
+
+                // Stop on a read error (after forwarding it) or once the receiver is dropped
+                let is_err = line.is_err();
+                if tx.send(line).await.is_err() || is_err {
+                    break;
                 }
             }
         });
@@ -317,20 +758,475 @@ impl DokployClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::dokploy::ComposeType;
 
     fn client_with_api_key() -> (DokployClient, String) {
         crate::test_init_env();
-        let client = DokployClient::new(std::env::var("DOKPLOY_URL").unwrap());
+        let client = DokployClient::new(
+            std::env::var("DOKPLOY_URL").unwrap(),
+            HttpTimeouts::default(),
+        );
         let api_key = std::env::var("DOKPLOY_API_KEY").unwrap();
         (client, api_key)
     }
 
+    /// Scope of the previews in `ENVIRONMENT_ID`, for tests against a real Dokploy
+    fn env_scope() -> ComposeScope {
+        ComposeScope {
+            app_name_prefix: std::env::var("APP_NAME_PREFIX").unwrap_or("preview-".to_string()),
+            environment_ids: vec![std::env::var("ENVIRONMENT_ID").unwrap()],
+        }
+    }
+
+    #[tokio::test]
+    async fn honors_configured_request_timeout() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let timeouts = HttpTimeouts {
+            connect: Duration::from_secs(1),
+            total: Duration::from_millis(200),
+        };
+        let client = DokployClient::new(format!("http://{}/api", addr), timeouts);
+
+        let started = Instant::now();
+        let err = client.fetch_projects("key").await.unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let timed_out = err
+            .chain()
+            .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+            .any(|e| e.is_timeout());
+        assert!(timed_out, "expected a timeout, got {:#}", err);
+    }
+
+    #[tokio::test]
+    async fn deploy_limit_queues_deploys_beyond_it() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let triggered = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new()
+            .route(
+                "/api/compose.deploy",
+                axum::routing::post({
+                    let (in_flight, max_in_flight) = (in_flight.clone(), max_in_flight.clone());
+                    let triggered = triggered.clone();
+                    move || async move {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(30)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        triggered.fetch_add(1, Ordering::SeqCst);
+                    }
+                }),
+            )
+            // Every deploy finishes as soon as it's triggered
+            .route(
+                "/api/compose.one",
+                axum::routing::get({
+                    let triggered = triggered.clone();
+                    move || async move {
+                        let latest = triggered.load(Ordering::SeqCst);
+                        axum::Json(serde_json::json!({
+                            "composeId": "compose",
+                            "deployments": [{ "deploymentId": format!("d-{latest}"), "status": "done" }],
+                        }))
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = DokployClient::new(format!("http://{}/api", addr), HttpTimeouts::default())
+            .with_deploy_limit(2)
+            .with_poll_interval(Duration::from_millis(10));
+
+        let deploys = (0..6).map(|i| client.deploy_compose("key", format!("compose-{i}"), None));
+        let results = futures::future::join_all(deploys).await;
+
+        let started: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            started
+                .iter()
+                .filter(|s| **s == DeployStart::Started)
+                .count(),
+            2
+        );
+        assert_eq!(
+            started
+                .iter()
+                .filter(|s| **s == DeployStart::Queued)
+                .count(),
+            4
+        );
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while triggered.load(Ordering::SeqCst) < 6 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn deploy_slots_are_held_until_the_deploy_finishes() {
+        use axum::extract::{Query, State};
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        #[derive(Clone, Default)]
+        struct Fake {
+            deploys: Arc<Mutex<Vec<String>>>,
+            finished: Arc<AtomicBool>,
+            /// Polls still to answer with an error
+            failing_polls: Arc<AtomicUsize>,
+        }
+        async fn deploy(State(fake): State<Fake>, axum::Json(req): axum::Json<serde_json::Value>) {
+            let compose_id = req["composeId"].as_str().unwrap().to_string();
+            fake.deploys.lock().unwrap().push(compose_id);
+        }
+        // Deploys build until the test lets them finish
+        async fn detail(
+            State(fake): State<Fake>,
+            Query(query): Query<HashMap<String, String>>,
+        ) -> Result<axum::Json<serde_json::Value>, axum::http::StatusCode> {
+            let failing = fake.failing_polls.load(Ordering::SeqCst);
+            if failing > 0 {
+                fake.failing_polls.store(failing - 1, Ordering::SeqCst);
+                return Err(axum::http::StatusCode::BAD_GATEWAY);
+            }
+            let compose_id = &query["composeId"];
+            let status = match fake.finished.load(Ordering::SeqCst) {
+                true => "done",
+                false => "running",
+            };
+            let deployments: Vec<_> = fake
+                .deploys
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|id| *id == compose_id)
+                .map(
+                    |id| serde_json::json!({ "deploymentId": format!("d-{id}"), "status": status }),
+                )
+                .collect();
+            let detail = serde_json::json!({ "composeId": compose_id, "deployments": deployments });
+            Ok(axum::Json(detail))
+        }
+
+        let fake = Fake::default();
+        let app = axum::Router::new()
+            .route("/api/compose.deploy", axum::routing::post(deploy))
+            .route("/api/compose.one", axum::routing::get(detail))
+            .with_state(fake.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = DokployClient::new(format!("http://{}/api", addr), HttpTimeouts::default())
+            .with_deploy_limit(1)
+            .with_poll_interval(Duration::from_millis(10));
+
+        let started = client
+            .deploy_compose("key", "compose-1", None)
+            .await
+            .unwrap();
+        assert_eq!(started, DeployStart::Started);
+        // A few failed polls don't give up on compose-1, which is still building
+        fake.failing_polls.store(3, Ordering::SeqCst);
+
+        // so compose-2 is queued for its slot, without waiting for it
+        let started = tokio::time::timeout(
+            Duration::from_millis(200),
+            client.deploy_compose("key", "compose-2", None),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(started, DeployStart::Queued);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(fake.failing_polls.load(Ordering::SeqCst), 0);
+        assert_eq!(*fake.deploys.lock().unwrap(), ["compose-1"]);
+
+        fake.finished.store(true, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while fake.deploys.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*fake.deploys.lock().unwrap(), ["compose-1", "compose-2"]);
+    }
+
+    #[tokio::test]
+    async fn compose_index_answers_known_names_without_fetching() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let composed = |id: &str, app_name: &str, environment_id: &str| {
+            serde_json::json!({
+                "composeId": id,
+                "name": "pr-1",
+                "appName": app_name,
+                "environmentId": environment_id,
+            })
+        };
+        let compose = |id: &str, name: &str| {
+            serde_json::json!({
+                "composeId": id,
+                "name": name,
+                "appName": format!("preview-{name}"),
+                "environmentId": "env",
+            })
+        };
+        let projects = serde_json::json!([{
+            "projectId": "project",
+            "name": "previews",
+            "organizationId": "org",
+            "environments": [
+                {
+                    "environmentId": "env",
+                    "name": "production",
+                    "projectId": "project",
+                    // Another bot's preview of the same name
+                    "compose": [
+                        compose("compose-1", "pr-1"),
+                        composed("compose-other-bot", "other-bot-pr-1", "env"),
+                    ],
+                },
+                {
+                    "environmentId": "env-2",
+                    "name": "other repository",
+                    "projectId": "project",
+                    "compose": [composed("compose-env-2", "preview-pr-1", "env-2")],
+                },
+            ],
+        }]);
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new()
+            .route(
+                "/api/project.all",
+                axum::routing::get({
+                    let fetches = fetches.clone();
+                    move || async move {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        axum::Json(projects)
+                    }
+                }),
+            )
+            .route(
+                "/api/compose.create",
+                axum::routing::post(
+                    move || async move { axum::Json(compose("compose-2", "pr-2")) },
+                ),
+            )
+            .route("/api/compose.delete", axum::routing::post(|| async {}));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = DokployClient::new(format!("http://{}/api", addr), HttpTimeouts::default())
+            .with_compose_index();
+        let scope = |environment_id: &str| ComposeScope {
+            app_name_prefix: "preview-".to_string(),
+            environment_ids: vec![environment_id.to_string()],
+        };
+        let find = |name: &'static str| {
+            let client = client.clone();
+            async move {
+                let found = client
+                    .find_compose_by_name("key", &scope("env"), name)
+                    .await
+                    .unwrap();
+                found.map(|c| c.compose_id)
+            }
+        };
+
+        // The first lookup fetches; known names are answered from the index. Both only
+        // consider composes in the scope
+        assert_eq!(find("pr-1").await.as_deref(), Some("compose-1"));
+        assert_eq!(find("pr-1").await.as_deref(), Some("compose-1"));
+        let in_env_2 = client
+            .find_compose_by_name("key", &scope("env-2"), "pr-1")
+            .await
+            .unwrap();
+        assert_eq!(in_env_2.unwrap().compose_id, "compose-env-2");
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Unknown names fetch again
+        assert_eq!(find("pr-2").await, None);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // Created composes are indexed, deleted ones looked up again
+        let create = CreateComposeRequest {
+            name: "pr-2".to_string(),
+            environment_id: "env".to_string(),
+            compose_type: ComposeType::DockerCompose,
+            app_name: "preview-pr-2".to_string(),
+            description: None,
+        };
+        client.create_compose("key", create).await.unwrap();
+        assert_eq!(find("pr-2").await.as_deref(), Some("compose-2"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        client
+            .delete_compose("key", "compose-1", true)
+            .await
+            .unwrap();
+        find("pr-1").await;
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        // Another key may see other projects
+        client
+            .find_compose_by_name("other-key", &scope("env"), "pr-1")
+            .await
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn compose_index_drops_composes_dokploy_no_longer_has() {
+        use axum::extract::{Query, State};
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        #[derive(Clone, Default)]
+        struct Fake {
+            composes: Arc<Mutex<Vec<serde_json::Value>>>,
+            /// Answer `project.all` with the composes as of the request, but only later
+            slow_fetches: Arc<AtomicBool>,
+        }
+        async fn projects(State(fake): State<Fake>) -> axum::Json<serde_json::Value> {
+            let composes = fake.composes.lock().unwrap().clone();
+            if fake.slow_fetches.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            axum::Json(serde_json::json!([{
+                "projectId": "project",
+                "name": "previews",
+                "organizationId": "org",
+                "environments": [{
+                    "environmentId": "env",
+                    "name": "production",
+                    "projectId": "project",
+                    "compose": composes,
+                }],
+            }]))
+        }
+        async fn detail(
+            State(fake): State<Fake>,
+            Query(query): Query<HashMap<String, String>>,
+        ) -> Result<axum::Json<serde_json::Value>, axum::http::StatusCode> {
+            let compose_id = &query["composeId"];
+            let composes = fake.composes.lock().unwrap();
+            match composes.iter().any(|c| c["composeId"] == **compose_id) {
+                true => Ok(axum::Json(serde_json::json!({ "composeId": compose_id }))),
+                false => Err(axum::http::StatusCode::NOT_FOUND),
+            }
+        }
+        async fn delete(State(fake): State<Fake>, axum::Json(req): axum::Json<serde_json::Value>) {
+            let mut composes = fake.composes.lock().unwrap();
+            composes.retain(|c| c["composeId"] != req["composeId"]);
+        }
+
+        let compose = |id: &str, name: &str| {
+            serde_json::json!({
+                "composeId": id,
+                "name": name,
+                "appName": format!("preview-{name}"),
+                "environmentId": "env",
+            })
+        };
+        let fake = Fake::default();
+        fake.composes
+            .lock()
+            .unwrap()
+            .extend([compose("compose-1", "pr-1"), compose("compose-2", "pr-2")]);
+        let app = axum::Router::new()
+            .route("/api/project.all", axum::routing::get(projects))
+            .route("/api/compose.one", axum::routing::get(detail))
+            .route("/api/compose.delete", axum::routing::post(delete))
+            .with_state(fake.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = DokployClient::new(format!("http://{}/api", addr), HttpTimeouts::default())
+            .with_compose_index();
+        let scope = ComposeScope {
+            app_name_prefix: "preview-".to_string(),
+            environment_ids: vec!["env".to_string()],
+        };
+        let find = |name: &'static str| {
+            let (client, scope) = (client.clone(), scope.clone());
+            async move {
+                let found = client
+                    .find_compose_by_name("key", &scope, name)
+                    .await
+                    .unwrap();
+                found.map(|c| c.compose_id)
+            }
+        };
+        assert_eq!(find("pr-2").await.as_deref(), Some("compose-2"));
+
+        // Deleted in Dokploy directly: the index only learns once a request on it 404s
+        fake.composes
+            .lock()
+            .unwrap()
+            .retain(|c| c["composeId"] != "compose-2");
+        assert_eq!(find("pr-2").await.as_deref(), Some("compose-2"));
+        assert!(client.get_compose_detail("key", "compose-2").await.is_err());
+        assert_eq!(find("pr-2").await, None);
+
+        // A fetch that started before a delete doesn't bring the compose back
+        fake.slow_fetches.store(true, Ordering::SeqCst);
+        let fetch = tokio::spawn({
+            let client = client.clone();
+            async move { client.fetch_projects("key").await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client
+            .delete_compose("key", "compose-1", true)
+            .await
+            .unwrap();
+        let fetched = fetch.await.unwrap();
+        assert_eq!(fetched[0].environments[0].compose.len(), 1);
+        fake.slow_fetches.store(false, Ordering::SeqCst);
+        assert_eq!(find("pr-1").await, None);
+    }
+
     #[tokio::test]
     #[ignore] // Requires environment variables
     async fn test_find_compose_id() {
         let (client, api_key) = client_with_api_key();
 
-        let res = dbg!(client.find_compose_by_name(&api_key, "pr-1774").await);
-        assert!(res.is_ok());
+        let compose = client
+            .find_compose_by_name(&api_key, &env_scope(), "pr-1774")
+            .await
+            .unwrap()
+            .expect("preview pr-1774 exists");
+        assert_eq!(compose.name, "pr-1774");
+        assert!(env_scope().contains(&compose));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires environment variables and restarts the preview's containers
+    async fn test_reload_compose() {
+        let (client, api_key) = client_with_api_key();
+
+        let compose = client
+            .find_compose_by_name(&api_key, &env_scope(), "pr-1774")
+            .await
+            .unwrap()
+            .expect("preview pr-1774 exists");
+        client
+            .reload_compose(&api_key, &compose.compose_id)
+            .await
+            .unwrap();
     }
 }
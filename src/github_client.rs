@@ -0,0 +1,56 @@
+use crate::config::HttpTimeouts;
+use crate::models::github::GithubPullRequest;
+use anyhow::Result;
+
+/// Minimal GitHub REST client for looking up pull request details
+#[derive(Clone, Debug)]
+pub struct GitHubClient {
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl GitHubClient {
+    pub fn new(token: Option<impl AsRef<str>>, timeouts: HttpTimeouts) -> Self {
+        let reqw_client = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect)
+            .timeout(timeouts.total)
+            // GitHub rejects API requests without a User-Agent
+            .user_agent("spinploy")
+            .build()
+            .expect("failed to build http client");
+        Self {
+            token: token.map(|t| t.as_ref().to_string()),
+            client: reqw_client,
+        }
+    }
+
+    /// Fetch a pull request to resolve its head and base branches.
+    /// `repo_full_name` is `owner/repo`.
+    pub async fn get_pull_request(
+        &self,
+        repo_full_name: &str,
+        number: u64,
+    ) -> Result<GithubPullRequest> {
+        let url = format!(
+            "https://api.github.com/repos/{}/pulls/{}",
+            repo_full_name, number
+        );
+
+        let mut req = self
+            .client
+            .get(url)
+            .header("Accept", "application/vnd.github+json");
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
+
+        let resp = req
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<GithubPullRequest>()
+            .await?;
+
+        Ok(resp)
+    }
+}
@@ -1,13 +1,25 @@
+pub mod audit;
 pub mod azure_client;
 pub mod config;
+pub mod coordinator;
+pub mod dns_check;
 pub mod docker_client;
 pub mod dokploy_client;
+pub mod github_client;
 pub mod models;
+pub mod rate_limit;
+pub mod retry;
 pub mod slack_client;
 pub mod slash_cmd;
+pub mod telemetry;
+
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
 
 pub use config::Config;
-pub use dokploy_client::DokployClient;
+pub use dokploy_client::{ComposeScope, DokployClient};
 pub use models::dokploy::*;
 pub use slash_cmd::*;
 
@@ -31,10 +43,63 @@ pub fn parse_ts(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
         .map(|dt| dt.with_timezone(&chrono::Utc))
 }
 
-/// Strips the common Git refs/heads/ prefix from a branch ref if present.
-/// Returns the original string when the prefix is absent.
+/// Strips a leading ref namespace (`refs/heads/`, `refs/tags/` or `refs/remotes/<remote>/`)
+/// to get the short name. Returns the original string for anything else.
+pub fn strip_ref_prefix(s: &str) -> String {
+    let short = s
+        .strip_prefix("refs/heads/")
+        .or_else(|| s.strip_prefix("refs/tags/"))
+        .or_else(|| {
+            let (_remote, name) = s.strip_prefix("refs/remotes/")?.split_once('/')?;
+            Some(name)
+        });
+    short.unwrap_or(s).to_string()
+}
+
+/// Short branch name of a ref; see [`strip_ref_prefix`], which also handles other namespaces.
 pub fn strip_refs_heads(s: &str) -> String {
-    s.strip_prefix("refs/heads/").unwrap_or(s).to_string()
+    strip_ref_prefix(s)
+}
+
+/// Verifies a hex-encoded HMAC-SHA256 `signature` of `body` keyed with `secret`.
+/// Accepts an optional `sha256=` prefix; the comparison is constant-time.
+pub fn verify_webhook_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Compares two secrets in time independent of where they first differ.
+/// Only the length may leak, which doesn't help guess the content.
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Whether `candidate` matches any of `secrets`, checking all of them so the
+/// timing doesn't reveal which one matched.
+pub fn matches_any_secret<S: AsRef<str>>(secrets: &[S], candidate: &str) -> bool {
+    secrets.iter().fold(false, |found, secret| {
+        secrets_match(secret.as_ref(), candidate) | found
+    })
+}
+
+/// Runs `futures` concurrently, at most `limit` at a time, returning their outputs in
+/// the order given.
+pub async fn join_bounded<F: Future>(
+    futures: impl IntoIterator<Item = F>,
+    limit: usize,
+) -> Vec<F::Output> {
+    futures_util::stream::iter(futures)
+        .buffered(limit)
+        .collect()
+        .await
 }
 
 /// Test-only helper to ensure required Dokploy env vars are loaded.
@@ -53,6 +118,24 @@ pub fn test_init_env() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn secrets_match_only_when_equal() {
+        assert!(secrets_match("s3cret", "s3cret"));
+        assert!(!secrets_match("s3cret", "s3creT"));
+        assert!(!secrets_match("s3cret", "s3cret-longer"));
+        assert!(!secrets_match("s3cret", ""));
+        assert!(secrets_match("", ""));
+    }
+
+    #[test]
+    fn matches_any_secret_checks_every_secret() {
+        let secrets = ["old-key", "new-key"];
+        assert!(matches_any_secret(&secrets, "old-key"));
+        assert!(matches_any_secret(&secrets, "new-key"));
+        assert!(!matches_any_secret(&secrets, "new-key "));
+        assert!(!matches_any_secret::<&str>(&[], "new-key"));
+    }
+
     #[test]
     fn test_compute_identifier() {
         assert_eq!(
@@ -67,6 +150,25 @@ mod tests {
         assert_eq!(compute_identifier(&Some("42".to_string()), "MAIN"), "pr-42");
     }
 
+    #[test]
+    fn test_verify_webhook_signature() {
+        let body = br#"{"eventType":"git.pullrequest.updated"}"#;
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        assert!(verify_webhook_signature("secret", body, &signature));
+        assert!(verify_webhook_signature(
+            "secret",
+            body,
+            &format!("sha256={}", signature)
+        ));
+        assert!(!verify_webhook_signature("other", body, &signature));
+        assert!(!verify_webhook_signature("secret", b"{}", &signature));
+        assert!(!verify_webhook_signature("secret", body, "not-hex"));
+        assert!(!verify_webhook_signature("secret", body, ""));
+    }
+
     #[test]
     fn test_strip_refs_heads() {
         assert_eq!(strip_refs_heads("refs/heads/main"), "main");
@@ -74,4 +176,22 @@ mod tests {
         assert_eq!(strip_refs_heads("main"), "main");
         assert_eq!(strip_refs_heads(""), "");
     }
+
+    #[test]
+    fn test_strip_ref_prefix() {
+        assert_eq!(strip_ref_prefix("refs/heads/feature/cool"), "feature/cool");
+        assert_eq!(strip_ref_prefix("refs/tags/v1.2.0"), "v1.2.0");
+        assert_eq!(
+            strip_ref_prefix("refs/remotes/origin/feature/cool"),
+            "feature/cool"
+        );
+        assert_eq!(strip_ref_prefix("refs/remotes/upstream/main"), "main");
+        // No prefix, or an unknown or incomplete namespace, is left alone
+        assert_eq!(strip_ref_prefix("feature/cool"), "feature/cool");
+        assert_eq!(strip_ref_prefix("refs/pull/7/head"), "refs/pull/7/head");
+        assert_eq!(
+            strip_ref_prefix("refs/remotes/origin"),
+            "refs/remotes/origin"
+        );
+    }
 }
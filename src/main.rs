@@ -1,30 +1,49 @@
 use std::collections::{BTreeSet, HashMap};
-use std::future::Future;
+use std::fmt;
+use std::future::{Future, IntoFuture};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
-use axum::body::Body;
+use axum::body::{Body, Bytes};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, MatchedPath};
 use axum::http::request::Parts;
-use axum::http::{HeaderName, HeaderValue, Request};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request};
 use axum::response::IntoResponse;
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     extract::{Path, Query, State},
     http::StatusCode,
     middleware::{self, Next},
     routing::{delete, get, post},
 };
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use futures_util::future::Either;
 use futures_util::stream::Stream;
-use serde::{Deserialize, Serialize};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+use spinploy::audit::{AuditAction, AuditLog, Auditor};
 use spinploy::azure_client::AzureDevOpsClient;
+use spinploy::config::{CertificateType, PreviewSource, PreviewTarget, RunMode};
+use spinploy::coordinator::{CoordinatorSnapshot, WebhookCoordinator};
+use spinploy::dns_check;
 use spinploy::docker_client::DockerClient;
+use spinploy::dokploy_client::DeployStart;
+use spinploy::github_client::GitHubClient;
 use spinploy::models::azure::*;
+use spinploy::models::bitbucket::*;
+use spinploy::models::github::*;
+use spinploy::models::gitlab::*;
+use spinploy::rate_limit::RateLimiter;
 use spinploy::slack_client::SlackWebhookClient;
+use spinploy::telemetry::{self, DeployKind};
 use spinploy::{
-    Config, DokployClient, DomainCreateRequest, SlashCommand, UpdateComposeRequest, parse_ts,
+    Compose, ComposeDetail, ComposeScope, Config, CreateComposeRequest, Deployment, DokployClient,
+    Domain, DomainCreateRequest, SlashCommand, SlashCommandLine, UpdateComposeRequest,
+    join_bounded, matches_any_secret, parse_ts, secrets_match,
 };
 use tokio::sync::RwLock;
 use tokio_stream::StreamExt as _;
@@ -33,11 +52,25 @@ use tower::ServiceBuilder;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
+use tracing::Instrument;
 use tracing_subscriber::EnvFilter;
 
 mod api;
+mod deploy_watch;
+mod reconcile;
+#[cfg(test)]
+mod test_support;
+
+use api::types::{LogFormat, PreviewStatus, PreviewSummary};
+use deploy_watch::DeployWatcher;
 
 const PREVIEW_LIMIT: usize = 3;
+const READINESS_TIMEOUT: Duration = Duration::from_secs(3);
+/// Audit log actor for requests made through the management API
+const API_ACTOR: &str = "api";
+const AZURE_SIGNATURE_HEADER: &str = "x-azure-signature";
+const GITLAB_TOKEN_HEADER: &str = "x-gitlab-token";
+const BITBUCKET_SIGNATURE_HEADER: &str = "x-hub-signature";
 const LEGACY_E2E_RUN_NAME: &str = "Run E2E tests";
 const MAIN_E2E_RUN_NAME: &str = "Run main E2E tests";
 const JOURNAL_TEMPLATE_E2E_RUN_NAME: &str = "Run journal template E2E tests";
@@ -149,13 +182,20 @@ impl AuthCache {
     }
 }
 
-pub struct PrTitleCache {
-    entries: RwLock<HashMap<u64, (String, Instant)>>,
+/// PR metadata shown in preview summaries.
+#[derive(Debug, Clone)]
+pub struct PrDetails {
+    pub title: String,
+    pub author: Option<String>,
+}
+
+pub struct PrDetailsCache {
+    entries: RwLock<HashMap<u64, (PrDetails, Instant)>>,
     ttl: Duration,
     max_entries: usize,
 }
 
-impl PrTitleCache {
+impl PrDetailsCache {
     fn new(ttl_secs: u64, max_entries: usize) -> Self {
         Self {
             entries: RwLock::new(HashMap::with_capacity(max_entries)),
@@ -164,20 +204,59 @@ impl PrTitleCache {
         }
     }
 
-    pub async fn get(&self, pr_id: u64) -> Option<String> {
+    pub async fn get(&self, pr_id: u64) -> Option<PrDetails> {
         let entries = self.entries.read().await;
         entries
             .get(&pr_id)
             .filter(|(_, expires_at)| *expires_at > Instant::now())
-            .map(|(title, _)| title.clone())
+            .map(|(details, _)| details.clone())
     }
 
-    pub async fn insert(&self, pr_id: u64, title: String) {
+    pub async fn insert(&self, pr_id: u64, details: PrDetails) {
         let mut entries = self.entries.write().await;
         if entries.len() >= self.max_entries {
             entries.clear();
         }
-        entries.insert(pr_id, (title, Instant::now() + self.ttl));
+        entries.insert(pr_id, (details, Instant::now() + self.ttl));
+    }
+}
+
+/// A preview's status changing between two observations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusTransition {
+    pub identifier: String,
+    pub from: PreviewStatus,
+    pub to: PreviewStatus,
+}
+
+/// Last-known status per preview, so notifications fire on changes rather than
+/// on every time a status is computed.
+pub struct StatusTracker {
+    entries: RwLock<HashMap<String, PreviewStatus>>,
+}
+
+impl StatusTracker {
+    fn new() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record the current status, returning the transition if it differs from
+    /// the previous observation. The first observation of a preview is not a transition.
+    pub async fn record(
+        &self,
+        identifier: &str,
+        status: PreviewStatus,
+    ) -> Option<StatusTransition> {
+        let mut entries = self.entries.write().await;
+        let previous = entries.insert(identifier.to_string(), status)?;
+        (previous != status).then(|| StatusTransition {
+            identifier: identifier.to_string(),
+            from: previous,
+            to: status,
+        })
     }
 }
 
@@ -186,16 +265,120 @@ pub struct AppState {
     pub dokploy_client: Arc<DokployClient>,
     pub config: Config,
     pub azure_client: Arc<AzureDevOpsClient>,
+    pub github_client: Arc<GitHubClient>,
     pub docker_client: Option<Arc<DockerClient>>,
+    pub audit_log: AuditLog,
     pub slack_client: Arc<SlackWebhookClient>,
+    pub deploy_watcher: Arc<DeployWatcher>,
     pub(crate) auth_cache: Arc<AuthCache>,
-    pub pr_title_cache: Arc<PrTitleCache>,
+    pub pr_details_cache: Arc<PrDetailsCache>,
+    pub coordinator: Arc<WebhookCoordinator>,
+    pub status_tracker: Arc<StatusTracker>,
+    pub metrics: PrometheusHandle,
+    pub in_flight: Arc<AtomicUsize>,
 }
 
 async fn healthz(State(_state): State<AppState>) -> &'static str {
     "ok"
 }
 
+/// Ready only once Dokploy answers; authenticates with `DOKPLOY_API_KEY` when configured.
+async fn readyz(State(state): State<AppState>) -> Result<&'static str, (StatusCode, String)> {
+    let result = match state.config.dokploy_api_key.as_deref() {
+        Some(api_key) => {
+            match tokio::time::timeout(
+                READINESS_TIMEOUT,
+                state.dokploy_client.fetch_projects(api_key),
+            )
+            .await
+            {
+                Ok(res) => res.map(|_| ()),
+                Err(_) => Err(anyhow::anyhow!("timed out")),
+            }
+        }
+        None => state.dokploy_client.ping(READINESS_TIMEOUT).await,
+    };
+    result.map_err(|e| {
+        tracing::warn!(error = %e, "Readiness check failed");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("dokploy unreachable: {e}"),
+        )
+    })?;
+
+    if let Err(e) = check_dns(&state.config).await {
+        if state.config.strict_dns_check {
+            tracing::warn!(error = %e, "Readiness check failed");
+            return Err((StatusCode::SERVICE_UNAVAILABLE, e));
+        }
+        tracing::warn!(error = %e, "Base domain DNS check failed");
+    }
+    Ok("ready")
+}
+
+/// Checks that hosts under the base domain resolve, when `DNS_CHECK` or
+/// `STRICT_DNS_CHECK` is set.
+async fn check_dns(config: &Config) -> Result<(), String> {
+    if !config.dns_check && !config.strict_dns_check {
+        return Ok(());
+    }
+    dns_check::check_base_domain(&config.base_domain, dns_check::system_resolve).await
+}
+
+/// GET /metrics - Prometheus text exposition
+async fn render_metrics(State(state): State<AppState>) -> String {
+    telemetry::record_coordinator(&state.coordinator.snapshot());
+    state.metrics.render()
+}
+
+/// Counts webhook deliveries by route and response status.
+async fn record_webhook_metrics(
+    path: MatchedPath,
+    req: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let resp = next.run(req).await;
+    telemetry::record_webhook(path.as_str(), resp.status().as_u16());
+    resp
+}
+
+/// Answers 429 once a source address exceeds `WEBHOOK_RATE_LIMIT_PER_MINUTE`, so a
+/// misbehaving subscription can't set off a deploy storm. Off unless configured: the
+/// source is the peer address, and behind a reverse proxy every delivery shares the
+/// proxy's, making the limit effectively global.
+async fn limit_webhook_rate(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    let source = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_default();
+    if !limiter.try_acquire(&source) {
+        tracing::warn!(
+            source,
+            path = req.uri().path(),
+            "Webhook rate limit exceeded"
+        );
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "webhook rate limit exceeded".to_string(),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+/// GET /api/admin/coordinator - Inspect webhook dedupe/coalesce state and pending operations
+async fn coordinator_state(
+    State(state): State<AppState>,
+    ApiKey(_api_key): ApiKey,
+) -> Json<CoordinatorSnapshot> {
+    Json(state.coordinator.snapshot())
+}
+
 // Middleware to protect static storage with a simple header token check
 async fn storage_auth(
     State(state): State<AppState>,
@@ -212,60 +395,227 @@ async fn storage_auth(
         .get(&header_name)
         .and_then(|v| v.to_str().ok());
 
-    if Some(expected).as_deref() == provided {
+    if provided.is_some_and(|provided| secrets_match(provided, &expected)) {
         Ok(next.run(req).await)
     } else {
         Err(StatusCode::UNAUTHORIZED)
     }
 }
 
+/// Creates a client for the configured Docker daemon (the local socket by default).
+///
+/// The client connects lazily, so a daemon that's down at startup is picked up once it
+/// answers; it's pinged in the background only to log whether it's reachable. Returns
+/// `None`, with a warning, when DOCKER_HOST/DOCKER_CERT_PATH can't be used at all.
+fn connect_docker(config: &Config) -> Option<DockerClient> {
+    let host = config
+        .docker_host
+        .clone()
+        .unwrap_or_else(|| "local socket".to_string());
+    let dc = match DockerClient::new_with_config(
+        config.docker_host.as_deref(),
+        config.docker_cert_path.as_deref().map(std::path::Path::new),
+    ) {
+        Ok(dc) => dc.with_log_buffer(config.log_buffer_size),
+        Err(e) => {
+            tracing::warn!(
+                host,
+                error = %e,
+                "Docker client unavailable. Container log streaming will be disabled. \
+                Check DOCKER_HOST and DOCKER_CERT_PATH."
+            );
+            return None;
+        }
+    };
+
+    let probe = dc.clone();
+    tokio::spawn(async move {
+        match tokio::time::timeout(Duration::from_secs(5), probe.ping()).await {
+            Ok(Ok(())) => tracing::info!(host, "Docker client initialized successfully"),
+            Ok(Err(e)) => tracing::warn!(
+                host,
+                error = %e,
+                "Docker daemon not reachable yet; container features fail until it is. \
+                Ensure /var/run/docker.sock is mounted or DOCKER_HOST is reachable."
+            ),
+            Err(_) => tracing::warn!(host, "Docker daemon did not answer within 5s"),
+        }
+    });
+    Some(dc)
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let config = Config::load()?;
+
     // Initialize tracing with env filter, defaulting to debug levels if RUST_LOG is unset.
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("debug,axum=info,reqwest=info,hyper_util=info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .compact()
-        .init();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        spinploy::config::LogFormat::Compact => subscriber.compact().init(),
+        // One object per line; event fields at the top level, plus the fields of the
+        // current span (e.g. `request_id`) and of every enclosing span
+        spinploy::config::LogFormat::Json => subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
 
-    let config = Config::load()?;
-    let client = DokployClient::new(&config.dokploy_url);
+    config.validate()?;
+    let audit_log = AuditLog::open(config.audit_log_path.as_deref().map(std::path::Path::new))
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to open AUDIT_LOG_PATH: {e}"))?;
 
-    // Try to connect to Docker socket; if unavailable, log a warning and proceed without it
-    let docker_client = match DockerClient::new() {
-        Ok(dc) => {
-            tracing::info!("Docker client initialized successfully");
-            Some(Arc::new(dc))
+    if let Err(e) = check_dns(&config).await {
+        if config.strict_dns_check {
+            anyhow::bail!(e);
         }
-        Err(e) => {
-            tracing::warn!(
-                error = %e,
-                "Docker client unavailable. Container log streaming will be disabled. \
-                Ensure /var/run/docker.sock is mounted."
-            );
-            None
+        tracing::warn!(error = %e, "Base domain DNS check failed");
+    }
+
+    if config.run_mode == RunMode::Reconcile || std::env::args().skip(1).any(|arg| arg == "--once")
+    {
+        let dokploy_client = DokployClient::new(&config.dokploy_url, config.http_timeouts())
+            .with_deploy_limit(config.max_concurrent_deploys);
+        let azure_client = AzureDevOpsClient::new(
+            &config.azdo_base_url,
+            &config.azdo_org,
+            &config.azdo_project,
+            &config.azdo_pat,
+            config.http_timeouts(),
+        );
+        return reconcile::run_once(&dokploy_client, &azure_client, &audit_log, &config).await;
+    }
+
+    let metrics_handle = telemetry::install_recorder()?;
+    // Histograms are only compacted on upkeep, which nothing else triggers without the exporter's listener
+    tokio::spawn({
+        let handle = metrics_handle.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                handle.run_upkeep();
+            }
         }
-    };
+    });
+    let mut client = DokployClient::new(&config.dokploy_url, config.http_timeouts())
+        .with_deploy_limit(config.max_concurrent_deploys);
+    if config.compose_index_refresh_secs > 0 {
+        client = client.with_compose_index();
+    }
+
+    // Try to connect to Docker; if unavailable, log a warning and proceed without it
+    let docker_client = connect_docker(&config).map(Arc::new);
 
     let state = AppState {
         dokploy_client: Arc::new(client),
         azure_client: Arc::new(AzureDevOpsClient::new(
+            &config.azdo_base_url,
             &config.azdo_org,
             &config.azdo_project,
             &config.azdo_pat,
+            config.http_timeouts(),
+        )),
+        github_client: Arc::new(GitHubClient::new(
+            config.github_token.as_ref(),
+            config.http_timeouts(),
         )),
         docker_client,
+        audit_log,
         slack_client: Arc::new(SlackWebhookClient::new(&config.slack_webhook_url)?),
+        deploy_watcher: Arc::new(DeployWatcher::new(Duration::from_secs(
+            config.deploy_watch_timeout_secs,
+        ))),
         auth_cache: Arc::new(AuthCache::new(
             config.auth_cache_ttl_secs,
             config.auth_cache_negative_ttl_secs,
             1024, // At the moment there will only be one valid key, but could be useful in the future
         )),
-        pr_title_cache: Arc::new(PrTitleCache::new(600, 256)), // 10 minute TTL, max 256 entries
+        pr_details_cache: Arc::new(PrDetailsCache::new(600, 256)), // 10 minute TTL, max 256 entries
+        status_tracker: Arc::new(StatusTracker::new()),
+        metrics: metrics_handle,
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        coordinator: Arc::new(WebhookCoordinator::new(
+            Duration::from_secs(config.webhook_dedupe_ttl_secs),
+            Duration::from_secs(config.webhook_duplicate_window_secs),
+            Duration::from_secs(config.redeploy_coalesce_secs),
+        )),
         config,
     };
 
+    // Keep the compose index fresh for the key webhooks act with; other keys refresh
+    // it on their next lookup miss
+    if state.config.compose_index_refresh_secs > 0
+        && let Some(api_key) = state.config.dokploy_api_key.clone()
+    {
+        let dokploy_client = state.dokploy_client.clone();
+        let period = Duration::from_secs(state.config.compose_index_refresh_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = dokploy_client.refresh_compose_index(&api_key).await {
+                    tracing::warn!(error = %e, "Failed to refresh compose index");
+                }
+            }
+        });
+    }
+
+    let app = build_router(&state);
+
+    let addr: SocketAddr = std::env::var("BIND_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:8080".to_string())
+        .parse()?;
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!("listening on {}", addr);
+
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        let _ = stop_rx.await;
+    })
+    .into_future();
+    let mut server = std::pin::pin!(server);
+    tokio::select! {
+        result = &mut server => result?,
+        () = shutdown_signal() => {
+            tracing::info!(
+                in_flight = state.in_flight.load(Ordering::SeqCst),
+                "Shutting down; draining in-flight requests"
+            );
+            let _ = stop_tx.send(());
+            // Watched deploys report that they're no longer followed while requests drain
+            let drained = async {
+                let (result, ()) = tokio::join!(server, state.deploy_watcher.shutdown());
+                result
+            };
+            // Followed log streams never end on their own, so don't wait forever
+            let timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+            match tokio::time::timeout(timeout, drained).await {
+                Ok(result) => result?,
+                Err(_) => tracing::warn!(
+                    in_flight = state.in_flight.load(Ordering::SeqCst),
+                    "Timed out draining requests; exiting anyway"
+                ),
+            }
+        }
+    }
+    tracing::info!("Shut down");
+
+    Ok(())
+}
+
+/// Every route the server answers, with their middleware, under the configured base
+/// path.
+fn build_router(state: &AppState) -> Router {
     // Frontend serving: index.html with no-cache headers
     let serve_index = ServiceBuilder::new()
         .layer(SetResponseHeaderLayer::if_not_present(
@@ -280,22 +630,52 @@ async fn main() -> anyhow::Result<()> {
     let api_routes = api::preview_routes()
         .route("/previews", post(create_or_update_preview))
         .route("/previews", delete(delete_preview))
+        .route("/previews/prune", post(prune_now))
+        .route("/previews/prune/plan", get(prune_plan))
         .route("/containers", get(list_containers))
-        .route("/containers/{name}/logs", get(stream_container_logs));
+        .route("/admin/coordinator", get(coordinator_state))
+        .route("/containers/{name}/logs", get(stream_container_logs))
+        .layer(DefaultBodyLimit::max(state.config.max_body_bytes));
 
-    let mut app = Router::new()
-        .route("/healthz", get(healthz))
+    let webhook_routes = Router::new()
         .route("/webhooks/azure/pr-comment", post(azure_pr_comment_webhook))
         .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+        .route("/webhooks/github/pr", post(github_pr_webhook))
+        .route("/webhooks/gitlab/mr", post(gitlab_mr_webhook))
+        .route("/webhooks/bitbucket/pr", post(bitbucket_pr_webhook))
         .route(
             "/webhooks/azure/build-completed",
             post(azure_build_completed_webhook),
         )
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::new(
+                state.config.webhook_rate_limit_per_minute,
+                Duration::from_secs(60),
+            )),
+            limit_webhook_rate,
+        ))
+        .route_layer(middleware::from_fn(record_webhook_metrics))
+        .layer(DefaultBodyLimit::max(state.config.max_body_bytes));
+
+    let mut app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(render_metrics))
+        .merge(webhook_routes)
         .nest("/api", api_routes)
         .fallback_service(serve_frontend)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_in_flight,
+        ))
         .with_state(state.clone())
         .layer(TraceLayer::new_for_http());
 
+    #[cfg(feature = "openapi")]
+    {
+        app = app.route("/openapi.json", get(api::openapi::openapi_json));
+    }
+
     if let Some(storage_config) = state.config.storage.clone() {
         let storage_router = Router::new()
             .route_service("/{*path}", ServeDir::new(storage_config.dir))
@@ -310,18 +690,147 @@ async fn main() -> anyhow::Result<()> {
         );
     }
 
-    let addr: SocketAddr = std::env::var("BIND_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:8080".to_string())
-        .parse()?;
+    let app = nest_under_base_path(app, state.config.base_path());
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    tracing::info!("listening on {}", addr);
-    axum::serve(listener, app).await?;
+    // Outermost, so the trace layer and every handler log inside the request id span
+    app.layer(middleware::from_fn(assign_request_id))
+}
 
-    Ok(())
+/// Serves `app` under `base_path` (e.g. `/spinploy/healthz`), or at the root if unset.
+fn nest_under_base_path(app: Router, base_path: Option<&str>) -> Router {
+    match base_path {
+        Some(base_path) => {
+            tracing::info!(base_path, "Serving routes under base path");
+            Router::new().nest(base_path, app)
+        }
+        None => app,
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Correlation id for one request, available to handlers as an extension, along with
+/// the span the request logs in, so handlers can record what they learn on it.
+#[derive(Clone, Debug)]
+pub struct RequestId {
+    pub id: String,
+    pub span: tracing::Span,
+}
+
+/// Reuses the caller's `x-request-id` when it looks sane, otherwise generates one.
+fn request_id_from(headers: &HeaderMap) -> String {
+    headers
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Tags the request with an id, logs everything it does inside a span carrying it,
+/// and echoes it on the response (errors included) so reporters can quote it.
+async fn assign_request_id(mut req: Request<Body>, next: Next) -> axum::response::Response {
+    let id = request_id_from(req.headers());
+    let header = HeaderValue::from_str(&id).expect("request id is visible ascii");
+    req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+    // Fields handlers may fill in later, like the author of a PR comment
+    let span = tracing::info_span!("request", request_id = %id, author = tracing::field::Empty);
+    req.extensions_mut().insert(RequestId {
+        id: id.clone(),
+        span: span.clone(),
+    });
+
+    let mut response = next.run(req).instrument(span).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+    response
+}
+
+/// Counts requests whose handler is still running, for the shutdown log.
+async fn track_in_flight(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> axum::response::Response {
+    struct InFlight(Arc<AtomicUsize>);
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    state.in_flight.fetch_add(1, Ordering::SeqCst);
+    // Decrements even if the client disconnects and the handler is dropped
+    let _in_flight = InFlight(state.in_flight.clone());
+    next.run(req).await
+}
+
+/// How an incoming key is authorized, given the configured management keys.
+#[derive(Debug, PartialEq)]
+enum KeyCheck {
+    /// A management key; requests go to Dokploy with the server-side key
+    Management(String),
+    /// Treat it as a Dokploy key: validate it against Dokploy and pass it through
+    Passthrough,
+    Rejected,
+}
+
+fn check_api_key(config: &Config, api_key: &str) -> KeyCheck {
+    if config.api_keys.is_empty() {
+        return KeyCheck::Passthrough;
+    }
+    if matches_any_secret(&config.api_keys, api_key)
+        && let Some(dokploy_api_key) = &config.dokploy_api_key
+    {
+        return KeyCheck::Management(dokploy_api_key.clone());
+    }
+    if config.api_key_passthrough {
+        KeyCheck::Passthrough
+    } else {
+        KeyCheck::Rejected
+    }
+}
+
+/// Key carried in a Basic `Authorization` header: the password, or the username when
+/// the password is empty, since some webhook configurators only fill in the username.
+fn basic_auth_key(authorization: &str) -> Option<String> {
+    let auth = authorization.trim();
+    let b64 = auth
+        .strip_prefix("Basic ")
+        .or_else(|| auth.strip_prefix("basic "))?;
+    let decoded = BASE64.decode(b64.as_bytes()).ok()?;
+    let creds = String::from_utf8(decoded).ok()?; // username:password
+    let (username, password) = creds.split_once(':').unwrap_or((&creds, ""));
+    [password, username]
+        .into_iter()
+        .find(|key| !key.is_empty())
+        .map(str::to_string)
 }
 
-// Extractor to pull API key from `x-api-key` or fallback Basic auth password
+// Extractor to pull API key from `x-api-key` or fallback Basic auth password (or username).
+// Holds the key to call Dokploy with, which for management keys is the server-side key.
 pub struct ApiKey(pub String);
 
 impl axum::extract::FromRequestParts<AppState> for ApiKey {
@@ -341,22 +850,7 @@ impl axum::extract::FromRequestParts<AppState> for ApiKey {
                     .headers
                     .get(axum::http::header::AUTHORIZATION)
                     .and_then(|v| v.to_str().ok())
-                    .and_then(|auth| {
-                        let auth = auth.trim();
-                        let b64 = auth
-                            .strip_prefix("Basic ")
-                            .or_else(|| auth.strip_prefix("basic "))?;
-                        let decoded = BASE64.decode(b64.as_bytes()).ok()?;
-                        let creds = String::from_utf8(decoded).ok()?; // username:password
-                        let mut it = creds.splitn(2, ':');
-                        let _username = it.next();
-                        let password = it.next().unwrap_or("");
-                        if password.is_empty() {
-                            None
-                        } else {
-                            Some(password.to_string())
-                        }
-                    })
+                    .and_then(basic_auth_key)
             });
 
         let state = state.clone();
@@ -365,10 +859,18 @@ impl axum::extract::FromRequestParts<AppState> for ApiKey {
             let Some(api_key) = api_key else {
                 return Err((
                     StatusCode::BAD_REQUEST,
-                    "missing x-api-key or Basic auth password".to_string(),
+                    "missing x-api-key or Basic auth credentials".to_string(),
                 ));
             };
 
+            match check_api_key(&state.config, &api_key) {
+                KeyCheck::Management(dokploy_api_key) => return Ok(ApiKey(dokploy_api_key)),
+                KeyCheck::Rejected => {
+                    return Err((StatusCode::UNAUTHORIZED, "Invalid API key".to_string()));
+                }
+                KeyCheck::Passthrough => {}
+            }
+
             // Check cache first
             if let Some(decision) = state.auth_cache.get(&api_key).await {
                 return match decision {
@@ -422,180 +924,663 @@ impl axum::extract::FromRequestParts<AppState> for ApiKey {
 }
 
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct ComposeCreateUpdateRequest {
     pub git_branch: String,
     pub pr_id: Option<String>,
+    /// Repository key selecting per-repository config overrides
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// Compose file to create the preview from instead of the configured one
+    #[serde(default)]
+    pub compose_path: Option<String>,
+}
+
+impl ComposeCreateUpdateRequest {
+    /// Rejects requests that would make a nonsensical identifier (e.g. `br-` for an
+    /// empty branch) with 422 and a message naming the field. An empty `prId` means no
+    /// PR, as it does for [`spinploy::compute_identifier`], and comes back as `None`.
+    fn validate(mut self) -> Result<Self, (StatusCode, String)> {
+        let invalid = |message: &str| Err((StatusCode::UNPROCESSABLE_ENTITY, message.to_string()));
+        if self.git_branch.trim().is_empty() {
+            return invalid("gitBranch must not be empty");
+        }
+        self.pr_id = self.pr_id.filter(|pr_id| !pr_id.is_empty());
+        if let Some(pr_id) = &self.pr_id
+            && !pr_id.bytes().all(|b| b.is_ascii_digit())
+        {
+            return invalid("prId must be a PR number");
+        }
+        Ok(self)
+    }
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct ComposeCreateUpdateResponse {
     pub compose_id: String,
     pub domains: Vec<String>,
+    /// Status right after the deploy was triggered, usually `Building`; with `?wait=true`,
+    /// the status once it finished
+    pub status: PreviewStatus,
 }
 
-async fn upsert_preview_internal(
-    dokploy_client: &DokployClient,
-    config: &Config,
-    api_key: &str,
-    git_branch: &str,
-    pr_id: &Option<String>,
-) -> Result<ComposeCreateUpdateResponse, (StatusCode, String)> {
-    let identifier = spinploy::compute_identifier(pr_id, git_branch);
-    let app_name = format!("preview-{}", &identifier);
+/// Query parameters for endpoints that trigger a deploy
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::IntoParams),
+    into_params(parameter_in = Query)
+)]
+pub struct WaitParams {
+    /// Respond only once the deploy finished, or after `DEPLOY_WAIT_TIMEOUT_SECS`
+    #[serde(default)]
+    pub wait: bool,
+}
 
-    if let Some(compose) = dokploy_client
-        .find_compose_by_name(api_key, &identifier)
-        .await
-        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?
-    {
-        dokploy_client
-            .deploy_compose(api_key, &compose.compose_id)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        let domains = dokploy_client
-            .list_domains_by_compose_id(api_key, &compose.compose_id)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// A domain to attach to one of a new preview's compose services.
+#[derive(Debug)]
+struct PlannedDomain {
+    service_name: String,
+    host: String,
+    port: u16,
+    certificate_type: CertificateType,
+}
 
-        Ok(ComposeCreateUpdateResponse {
-            compose_id: compose.compose_id,
-            domains: domains.into_iter().map(|d| d.host).collect(),
+impl PlannedDomain {
+    fn into_request(self, compose_id: &str) -> DomainCreateRequest {
+        DomainCreateRequest {
+            compose_id: compose_id.to_string(),
+            service_name: self.service_name,
+            domain_type: "compose".to_string(),
+            host: self.host,
+            path: "/".to_string(),
+            port: self.port,
+            https: true,
+            certificate_type: self.certificate_type.as_str().to_string(),
+        }
+    }
+}
+
+/// Domains `identifier` gets in `target`, refusing to exceed `max_domains_per_preview`.
+fn plan_preview_domains(
+    config: &Config,
+    target: &PreviewTarget,
+    identifier: &str,
+) -> Result<Vec<PlannedDomain>, (StatusCode, String)> {
+    let planned: Vec<PlannedDomain> = target
+        .services()
+        .map(|service| PlannedDomain {
+            host: service.host(identifier, &target.base_domain),
+            service_name: service.name.clone(),
+            port: service.port,
+            certificate_type: config.certificate_type,
         })
-    } else {
-        let compose = dokploy_client
-            .create_compose(api_key, &config.environment_id, &identifier, &app_name)
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .collect();
 
-        let frontend_domain = format!("{}.{}", &identifier, &config.base_domain);
-        let backend_domain = format!("api-{}.{}", &identifier, &config.base_domain);
+    if planned.len() > config.max_domains_per_preview {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "preview {} would get {} domains, exceeding MAX_DOMAINS_PER_PREVIEW ({})",
+                identifier,
+                planned.len(),
+                config.max_domains_per_preview
+            ),
+        ));
+    }
 
-        let dynamic_env_vars = format!(
-            "APP_URL=https://{}\nBACKEND_API_URL=https://{}\nEMAIL_ENVIRONMENT_PREFIX=\"[{}] \"\n",
-            frontend_domain, backend_domain, identifier
-        );
-        let project_env_vars = r#"
-COOKIE_DOMAIN=${{project.COOKIE_DOMAIN}}
-STORAGE_URL=${{project.STORAGE_URL}}
-STORAGE_TOKEN=${{project.STORAGE_TOKEN}}
+    Ok(planned)
+}
 
-EMAIL_INVOICE_CREDENTIALS_PASSWORD=${{project.EMAIL_INVOICE_CREDENTIALS_PASSWORD}}
-EMAIL_DIRECT_REGULATION_CREDENTIALS_PASSWORD=${{project.EMAIL_DIRECT_REGULATION_CREDENTIALS_PASSWORD}}
-EMAIL_TEST_ANSWER_CREDENTIALS_PASSWORD=${{project.EMAIL_TEST_ANSWER_CREDENTIALS_PASSWORD}}
-EMAIL_REFERRAL_CREDENTIALS_PASSWORD=${{project.EMAIL_REFERRAL_CREDENTIALS_PASSWORD}}
-EMAIL_NO_REPLY_CREDENTIALS_PASSWORD=${{project.EMAIL_NO_REPLY_CREDENTIALS_PASSWORD}}
+/// Env vars written to a newly created preview compose: `ENV_TEMPLATE` rendered, then
+/// `PREVIEW_BASIC_AUTH` if set and `EXTRA_ENV` appended. Project-level secrets are `${{project.*}}` references resolved
+/// by Dokploy, never their actual values.
+pub(crate) fn build_preview_env(
+    config: &Config,
+    target: &PreviewTarget,
+    identifier: &str,
+) -> String {
+    let (frontend_domain, backend_domain) = target.hosts(identifier);
+
+    let mut env = render_env_template(
+        &config.env_template,
+        &[
+            ("frontend_domain", &frontend_domain),
+            ("backend_domain", &backend_domain),
+            ("base_domain", &target.base_domain),
+            ("identifier", identifier),
+        ],
+    );
+    if (!config.extra_env.is_empty() || config.preview_basic_auth.is_some())
+        && !env.is_empty()
+        && !env.ends_with('\n')
+    {
+        env.push('\n');
+    }
+    if let Some(users) = &config.preview_basic_auth {
+        // Single-quoted so Compose doesn't interpolate the `$`s in the hashes
+        env.push_str(&format!("PREVIEW_BASIC_AUTH='{}'\n", users));
+    }
+    for (key, value) in &config.extra_env {
+        env.push_str(&format!("{}={}\n", key, value));
+    }
+    env
+}
 
-FEATURE_MANAGEMENT_FREJA_POLLING_JOB=${{project.FEATURE_MANAGEMENT_FREJA_POLLING_JOB}}
-FEATURE_MANAGEMENT_VARA_IMPORT_JOB=${{project.FEATURE_MANAGEMENT_VARA_IMPORT_JOB}}
-FEATURE_MANAGEMENT_SMS_JOBS=${{project.FEATURE_MANAGEMENT_SMS_JOBS}}
+/// Substitute `{name}` placeholders. Unknown names are left verbatim rather than
+/// blanked, so a typo shows up in the preview's env instead of silently vanishing.
+fn render_env_template(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter()
+        .fold(template.to_string(), |env, (name, value)| {
+            env.replace(&format!("{{{}}}", name), value)
+        })
+}
 
-SMS_PASSWORD_BASIC_AUTH=${{project.SMS_PASSWORD_BASIC_AUTH}}
-SMS_PASSWORD_XML=${{project.SMS_PASSWORD_XML}}
+/// Best-effort status of a deploy that was just triggered; `previous` is the latest
+/// deployment from before it. Dokploy may not have recorded the deployment yet, in
+/// which case `previous` is still the latest and the deploy, queued, counts as building.
+async fn status_after_deploy(
+    dokploy_client: &DokployClient,
+    api_key: &str,
+    compose_id: &str,
+    previous: Option<&str>,
+) -> PreviewStatus {
+    match dokploy_client.get_compose_detail(api_key, compose_id).await {
+        Ok(detail) => detail
+            .latest_deployment()
+            .filter(|latest| Some(latest.deployment_id.as_str()) != previous)
+            .and_then(|_| api::previews::deployment_status(&detail))
+            .unwrap_or(PreviewStatus::Building),
+        Err(e) => {
+            tracing::warn!(error = %e, compose_id, "Failed to fetch status after deploy");
+            PreviewStatus::Unknown
+        }
+    }
+}
 
-VARA_PASSWORD=${{project.VARA_PASSWORD}}
-IMAGE_ANALYSIS_API_KEY=${{project.IMAGE_ANALYSIS_API_KEY}}
-        "#;
+/// Slack follow-up on how a watched preview deploy ended.
+fn deploy_finished_message(identifier: &str, status: PreviewStatus, frontend_url: &str) -> String {
+    match status {
+        PreviewStatus::Running => format!("✅ Preview `{}` deployed: {}", identifier, frontend_url),
+        _ => format!("❌ Preview `{}` failed to deploy", identifier),
+    }
+}
 
-        dokploy_client
-            .update_compose(
-                api_key,
-                UpdateComposeRequest {
-                    compose_id: compose.compose_id.clone(),
-                    name: identifier.clone(),
-                    app_name: app_name.clone(),
-                    env: dynamic_env_vars + project_env_vars,
-                    environment_id: config.environment_id.clone(),
-                    auto_deploy: true,
-                    isolated_deployment: true,
-                    compose_path: config.compose_path.clone(),
-                    source_type: "git".to_string(),
-                    compose_type: "docker-compose".to_string(),
-                    custom_git_url: config.custom_git_url.clone(),
-                    custom_git_branch: git_branch.to_string(),
-                    custom_git_ssh_key_id: config.custom_git_ssh_key_id.clone(),
-                },
-            )
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+/// Id of the compose's latest deployment, to tell it apart from a deploy triggered next.
+pub(crate) async fn latest_deployment_id(
+    dokploy_client: &DokployClient,
+    api_key: &str,
+    compose_id: &str,
+) -> Option<String> {
+    let detail = dokploy_client
+        .get_compose_detail(api_key, compose_id)
+        .await
+        .ok()?;
+    detail.latest_deployment().map(|d| d.deployment_id.clone())
+}
 
-        dokploy_client
-            .create_domain(
-                api_key,
-                DomainCreateRequest {
-                    compose_id: compose.compose_id.clone(),
-                    service_name: config.frontend_service_name.clone(),
-                    domain_type: "compose".to_string(),
-                    host: frontend_domain,
-                    path: "/".to_string(),
-                    port: config.frontend_port,
-                    https: true,
-                    certificate_type: "none".to_string(),
-                },
-            )
+/// Waits up to `DEPLOY_WAIT_TIMEOUT_SECS` for a deploy triggered on `compose_id` to
+/// finish and returns the resulting status; `previous` is the latest deployment from
+/// before it was triggered. A deploy still going at the deadline, or when the server
+/// starts shutting down, is `Building`.
+pub(crate) async fn wait_for_triggered_deploy(
+    dokploy_client: &DokployClient,
+    deploy_watcher: &DeployWatcher,
+    config: &Config,
+    api_key: &str,
+    compose_id: &str,
+    previous: Option<&str>,
+) -> PreviewStatus {
+    let waited = dokploy_client.wait_for_triggered_deployment(
+        api_key,
+        compose_id,
+        previous,
+        Duration::from_secs(config.deploy_wait_timeout_secs),
+    );
+    // The wait can outlast SHUTDOWN_TIMEOUT_SECS, which would cut the response off
+    let waited = tokio::select! {
+        waited = waited => waited,
+        () = deploy_watcher.shutting_down() => {
+            tracing::info!(compose_id, "Shutting down; stopped waiting for deploy");
+            return PreviewStatus::Building;
+        }
+    };
+    match waited {
+        // Dokploy only records the deployment once its queue picks the deploy up
+        Ok(None) => PreviewStatus::Building,
+        Ok(Some(deployment)) => {
+            tracing::info!(
+                compose_id,
+                deployment_id = deployment.deployment_id,
+                status = deployment.status,
+                "Waited for deploy"
+            );
+            status_after_deploy(dokploy_client, api_key, compose_id, previous).await
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, compose_id, "Failed to wait for deploy");
+            PreviewStatus::Unknown
+        }
+    }
+}
+
+/// Create the planned domains whose host isn't among the compose's `existing` domains,
+/// so an upsert retried after a partial create fills in what's missing instead of
+/// duplicating hosts. Returns all of the compose's domains.
+async fn ensure_preview_domains(
+    dokploy_client: &DokployClient,
+    api_key: &str,
+    compose_id: &str,
+    planned: Vec<PlannedDomain>,
+    existing: Vec<Domain>,
+) -> Result<Vec<Domain>, (StatusCode, String)> {
+    let mut domains = existing;
+    let mut complete = true;
+    for domain in planned {
+        if domains
+            .iter()
+            .any(|d| d.host.eq_ignore_ascii_case(&domain.host))
+        {
+            tracing::debug!(host = domain.host, compose_id, "Domain already exists");
+            continue;
+        }
+        match dokploy_client
+            .create_domain(api_key, domain.into_request(compose_id))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        {
+            Some(created) => domains.push(created),
+            None => complete = false,
+        }
+    }
+
+    // Only ask Dokploy for the domains if it didn't return every one it created
+    if complete {
+        return Ok(domains);
+    }
+    dokploy_client
+        .list_domains_by_compose_id(api_key, compose_id)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Creates or redeploys the preview of `git_branch`/`pr_id`, recorded in the audit
+/// log by `audit`, recording `source` as the repository it's for. Also
+/// returns the previews pruned to make room for a new one, for the caller to
+/// [comment on](spawn_pruned_pr_comments) once it's released the preview's lock.
+async fn upsert_preview_internal(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    git_branch: &str,
+    pr_id: &Option<String>,
+    source: Option<&PreviewSource>,
+) -> Result<(Upserted, Vec<PruneCandidate>), (StatusCode, String)> {
+    let identifier = spinploy::compute_identifier(pr_id, git_branch);
+    let result = upsert_compose(
+        dokploy_client,
+        config,
+        api_key,
+        &config.preview_target(),
+        &identifier,
+        git_branch,
+        source,
+    )
+    .await;
+    audit_result(audit, &identifier, AuditAction::Deploy, &result).await;
+    let upserted = result?;
+
+    // Prune previews in the environment after creating this one
+    let pruned = match upserted.created {
+        true => {
+            let compose_id = &upserted.resp.compose_id;
+            prune_previews_if_over_limit(dokploy_client, config, api_key, audit, compose_id).await
+        }
+        false => vec![],
+    };
+    Ok((upserted, pruned))
+}
+
+/// Whether Dokploy refused a request with 409 Conflict.
+fn is_conflict(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .is_some_and(|status| status == StatusCode::CONFLICT)
+}
+
+/// Records a handler-level result in the audit log.
+async fn audit_result<T>(
+    audit: &Auditor,
+    identifier: &str,
+    action: AuditAction,
+    result: &Result<T, (StatusCode, String)>,
+) {
+    let result = result.as_ref().map_err(|(_, e)| e);
+    audit.record(identifier, action, &result).await;
+}
+
+/// A compose [`upsert_compose`] created or redeployed.
+struct Upserted {
+    resp: ComposeCreateUpdateResponse,
+    /// Whether the compose was newly created
+    created: bool,
+    /// Latest deployment from before the deploy it triggered, to tell that deploy apart
+    previous_deployment: Option<String>,
+}
+
+/// Creates the compose `identifier` in `target`'s environment tracking `git_branch`,
+/// or redeploys it if it already exists. Domains and host env vars come from `target`,
+/// everything else (git source, env template) from `config`. `source` is recorded as
+/// the compose's description, see [`PreviewSource`].
+async fn upsert_compose(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    target: &PreviewTarget,
+    identifier: &str,
+    git_branch: &str,
+    source: Option<&PreviewSource>,
+) -> Result<Upserted, (StatusCode, String)> {
+    let app_name = format!("{}{}", config.app_name_prefix, identifier);
+    let scope = config.compose_scope_in(&target.environment_id);
+
+    if let Some(compose) = dokploy_client
+        .find_compose_by_name(api_key, &scope, identifier)
+        .await
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?
+    {
+        let existing_domains = dokploy_client
+            .list_domains_by_compose_id(api_key, &compose.compose_id)
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let domains = ensure_preview_domains(
+            dokploy_client,
+            api_key,
+            &compose.compose_id,
+            plan_preview_domains(config, target, identifier)?,
+            existing_domains,
+        )
+        .await?;
 
+        // Git sources pick up new commits on deploy, but a raw file only changes when
+        // re-uploaded, and a different compose path only once it's set
         dokploy_client
-            .create_domain(
+            .update_compose_source(
                 api_key,
-                DomainCreateRequest {
-                    compose_id: compose.compose_id.clone(),
-                    service_name: config.backend_service_name.clone(),
-                    domain_type: "compose".to_string(),
-                    host: backend_domain,
-                    path: "/".to_string(),
-                    port: config.backend_port,
-                    https: true,
-                    certificate_type: "none".to_string(),
-                },
+                &compose.compose_id,
+                config.compose_source(git_branch),
             )
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        // Previews created before sources were recorded get theirs on their next deploy
+        if let Some(source) = source
+            && compose.description.is_none()
+            && let Err(e) = dokploy_client
+                .update_compose_description(api_key, &compose, source.description())
+                .await
+        {
+            tracing::warn!(identifier, error = %e, "Failed to record the preview's source");
+        }
 
-        dokploy_client
-            .deploy_compose(api_key, &compose.compose_id)
+        let previous = latest_deployment_id(dokploy_client, api_key, &compose.compose_id).await;
+        let started = dokploy_client
+            .deploy_compose(api_key, &compose.compose_id, previous.as_deref())
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        let domains = dokploy_client
-            .list_domains_by_compose_id(api_key, &compose.compose_id)
+        telemetry::record_deploy(DeployKind::Updated);
+
+        let status = match started {
+            DeployStart::Queued => PreviewStatus::Queued,
+            DeployStart::Started => {
+                let previous = previous.as_deref();
+                status_after_deploy(dokploy_client, api_key, &compose.compose_id, previous).await
+            }
+        };
+        let resp = ComposeCreateUpdateResponse {
+            compose_id: compose.compose_id,
+            domains: domains.into_iter().map(|d| d.host).collect(),
+            status,
+        };
+        Ok(Upserted {
+            resp,
+            created: false,
+            previous_deployment: previous,
+        })
+    } else {
+        // Plan domains up front so a bad config fails before anything is created
+        let planned_domains = plan_preview_domains(config, target, identifier)?;
+
+        let created = dokploy_client
+            .create_compose(
+                api_key,
+                CreateComposeRequest {
+                    name: identifier.to_string(),
+                    environment_id: target.environment_id.clone(),
+                    compose_type: config.compose_type,
+                    app_name: app_name.clone(),
+                    description: source.map(PreviewSource::description),
+                },
+            )
+            .await;
+        let (compose, adopted) = match created {
+            Ok(compose) => (compose, false),
+            // Created since the lookup above, e.g. by a retried webhook racing this one.
+            // Dokploy isn't known to answer duplicates with 409 specifically, so any
+            // failure is checked for a compose that exists now. That compose may not be
+            // configured yet, so it's configured below like a new one before deploying.
+            Err(e) => match dokploy_client
+                .find_compose_by_name(api_key, &scope, identifier)
+                .await
+            {
+                Ok(Some(compose)) => {
+                    tracing::info!(identifier, error = %e, "Preview was created concurrently");
+                    (compose, true)
+                }
+                _ if is_conflict(&e) => {
+                    return Err((
+                        StatusCode::CONFLICT,
+                        format!("Preview '{}' already exists", identifier),
+                    ));
+                }
+                _ => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+            },
+        };
+
+        dokploy_client
+            .update_compose(
+                api_key,
+                UpdateComposeRequest {
+                    compose_id: compose.compose_id.clone(),
+                    name: identifier.to_string(),
+                    app_name: app_name.clone(),
+                    env: build_preview_env(config, target, identifier),
+                    environment_id: target.environment_id.clone(),
+                    auto_deploy: true,
+                    isolated_deployment: true,
+                    source: config.compose_source(git_branch),
+                    compose_type: config.compose_type,
+                },
+            )
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-        // Prune previews in the environment after creating this one
-        prune_previews_if_over_limit(
+        let (existing_domains, previous) = if adopted {
+            let domains = dokploy_client
+                .list_domains_by_compose_id(api_key, &compose.compose_id)
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let previous = latest_deployment_id(dokploy_client, api_key, &compose.compose_id).await;
+            (domains, previous)
+        } else {
+            (Vec::new(), None)
+        };
+        let domains = ensure_preview_domains(
             dokploy_client,
             api_key,
-            &config.environment_id,
             &compose.compose_id,
+            planned_domains,
+            existing_domains,
         )
-        .await;
+        .await?;
 
-        Ok(ComposeCreateUpdateResponse {
+        let started = dokploy_client
+            .deploy_compose(api_key, &compose.compose_id, previous.as_deref())
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        telemetry::record_deploy(if adopted {
+            DeployKind::Updated
+        } else {
+            DeployKind::Created
+        });
+
+        let status = match started {
+            DeployStart::Queued => PreviewStatus::Queued,
+            DeployStart::Started => {
+                let previous = previous.as_deref();
+                status_after_deploy(dokploy_client, api_key, &compose.compose_id, previous).await
+            }
+        };
+        let resp = ComposeCreateUpdateResponse {
             compose_id: compose.compose_id,
             domains: domains.into_iter().map(|d| d.host).collect(),
+            status,
+        };
+        Ok(Upserted {
+            resp,
+            created: !adopted,
+            previous_deployment: previous,
         })
     }
 }
 
+/// Prefix of staging deployments' names, which no preview identifier starts with.
+const STAGING_PREFIX: &str = "staging-";
+
+/// Identifier of the staging deployment promoted from the preview `identifier`.
+fn staging_identifier(identifier: &str) -> String {
+    format!("{}{}", STAGING_PREFIX, identifier)
+}
+
+/// Deploys the branch of the preview `identifier` to the staging environment, creating
+/// its staging compose on first promotion. Callers hold the lock of its
+/// [staging identifier](staging_identifier). Returns `None` if promotion isn't
+/// configured, otherwise the deploy and the staging frontend URL.
+async fn promote_to_staging(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    identifier: &str,
+    git_branch: &str,
+    source: Option<&PreviewSource>,
+) -> Result<Option<(ComposeCreateUpdateResponse, String)>, (StatusCode, String)> {
+    let Some(staging) = config.staging_target() else {
+        return Ok(None);
+    };
+    let staging_identifier = staging_identifier(identifier);
+
+    let result = upsert_compose(
+        dokploy_client,
+        config,
+        api_key,
+        &staging,
+        &staging_identifier,
+        git_branch,
+        source,
+    )
+    .await;
+    audit_result(audit, &staging_identifier, AuditAction::Deploy, &result).await;
+    let Upserted { resp, .. } = result?;
+    let (frontend_domain, _) = staging.hosts(&staging_identifier);
+    Ok(Some((resp, format!("https://{}", frontend_domain))))
+}
+
+/// Deletes the preview of `git_branch`/`pr_id`, if there is one, recorded in the audit
+/// log by `audit`.
 async fn delete_preview_internal(
     dokploy_client: &DokployClient,
+    config: &Config,
     api_key: &str,
+    audit: &Auditor,
     pr_id: &Option<String>,
     git_branch: &str,
 ) -> Result<StatusCode, (StatusCode, String)> {
     let identifier = spinploy::compute_identifier(pr_id, git_branch);
+    let scope = config.compose_scope();
+    delete_compose_named(dokploy_client, &scope, api_key, audit, &identifier).await
+}
+
+/// Deletes the staging deployment promoted from the preview `identifier`, if promotion
+/// is configured and there is one, e.g. once its PR is closed.
+async fn delete_staging_deployment(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    coordinator: &WebhookCoordinator,
+    identifier: &str,
+) -> Result<(), (StatusCode, String)> {
+    let Some(staging) = config.staging_target() else {
+        return Ok(());
+    };
+    let staging_identifier = staging_identifier(identifier);
+    let scope = config.compose_scope_in(&staging.environment_id);
+
+    let _guard = coordinator.lock(&staging_identifier).await;
+    delete_compose_named(dokploy_client, &scope, api_key, audit, &staging_identifier).await?;
+    Ok(())
+}
+
+/// Deletes the preview of a closed PR and the staging deployment promoted from it.
+/// Only Azure DevOps and GitHub PRs can be promoted.
+async fn delete_closed_pr_deployments(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    coordinator: &WebhookCoordinator,
+    pr_id: &Option<String>,
+    branch: &str,
+) -> Result<(), (StatusCode, String)> {
+    let identifier = spinploy::compute_identifier(pr_id, branch);
+    {
+        let _guard = coordinator.lock(&identifier).await;
+        delete_preview_internal(dokploy_client, config, api_key, audit, pr_id, branch).await?;
+    }
+    delete_staging_deployment(
+        dokploy_client,
+        config,
+        api_key,
+        audit,
+        coordinator,
+        &identifier,
+    )
+    .await
+}
 
+/// Deletes the compose named `identifier` in `scope`, if there is one.
+async fn delete_compose_named(
+    dokploy_client: &DokployClient,
+    scope: &ComposeScope,
+    api_key: &str,
+    audit: &Auditor,
+    identifier: &str,
+) -> Result<StatusCode, (StatusCode, String)> {
     match dokploy_client
-        .find_compose_by_name(&api_key, &identifier)
+        .find_compose_by_name(api_key, scope, identifier)
         .await
     {
         Ok(Some(compose)) => {
-            dokploy_client
+            let result = dokploy_client
                 .delete_compose(api_key, &compose.compose_id, true)
-                .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                .await;
+            audit.record(identifier, AuditAction::Delete, &result).await;
+            result.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            telemetry::record_delete();
             Ok(StatusCode::NO_CONTENT)
         }
         Ok(None) => Ok(StatusCode::NO_CONTENT),
@@ -603,15 +1588,83 @@ async fn delete_preview_internal(
     }
 }
 
+/// How a refresh failed. The preview is gone only if recreating it failed.
+#[derive(Debug)]
+enum RefreshError {
+    Delete(StatusCode, String),
+    Recreate(StatusCode, String),
+}
+
+impl RefreshError {
+    /// What a PR reply says went wrong; the error itself stays in the logs.
+    fn reply(&self) -> &'static str {
+        match self {
+            RefreshError::Delete(..) => "Deleting the preview failed, it is unchanged",
+            RefreshError::Recreate(..) => {
+                "Preview was deleted but recreating it failed, comment /preview to try again"
+            }
+        }
+    }
+
+    fn into_error(self) -> (StatusCode, String) {
+        match self {
+            RefreshError::Delete(status, e) => (
+                status,
+                format!(
+                    "failed to delete the preview, so it was left as it was: {}",
+                    e
+                ),
+            ),
+            RefreshError::Recreate(status, e) => (
+                status,
+                format!(
+                    "the preview was deleted but recreating it failed: {}. Comment /preview to try again",
+                    e
+                ),
+            ),
+        }
+    }
+}
+
+/// Delete a preview and create it from scratch, for composes a redeploy can't fix.
+/// Callers hold the identifier's lock across both steps, like for
+/// [`upsert_preview_internal`], so nothing deploys in between.
+async fn refresh_preview(
+    dokploy_client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    pr_id: &Option<String>,
+    git_branch: &str,
+    source: Option<&PreviewSource>,
+) -> Result<(Upserted, Vec<PruneCandidate>), RefreshError> {
+    delete_preview_internal(dokploy_client, config, api_key, audit, pr_id, git_branch)
+        .await
+        .map_err(|(status, e)| RefreshError::Delete(status, e))?;
+    upsert_preview_internal(
+        dokploy_client,
+        config,
+        api_key,
+        audit,
+        git_branch,
+        pr_id,
+        source,
+    )
+    .await
+    .map_err(|(status, e)| RefreshError::Recreate(status, e))
+}
+
+/// Returns whether a preview existed and was redeployed.
 async fn redeploy_preview_if_exists(
     dokploy_client: &DokployClient,
+    config: &Config,
     api_key: &str,
     pr_id: &Option<String>,
     git_branch: &str,
-) -> Result<(), (StatusCode, String)> {
+) -> Result<bool, (StatusCode, String)> {
     let identifier = spinploy::compute_identifier(pr_id, git_branch);
     match dokploy_client
-        .find_compose_by_name(api_key, &identifier)
+        .find_compose_by_name(api_key, &config.compose_scope(), &identifier)
         .await
     {
         Ok(Some(compose)) => {
@@ -620,173 +1673,1001 @@ async fn redeploy_preview_if_exists(
                 identifier,
                 "Redeploying existing preview"
             );
-            dokploy_client
-                .deploy_compose(api_key, &compose.compose_id)
+            let previous = latest_deployment_id(dokploy_client, api_key, &compose.compose_id).await;
+            let started = dokploy_client
+                .deploy_compose(api_key, &compose.compose_id, previous.as_deref())
                 .await
                 .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            Ok(())
+            if started == DeployStart::Queued {
+                tracing::info!(identifier, "Redeploy queued until a deploy slot frees up");
+            }
+            telemetry::record_deploy(DeployKind::Redeployed);
+            Ok(true)
         }
         Ok(None) => {
             tracing::info!(identifier, "No existing preview to redeploy; skipping");
-            Ok(())
+            Ok(false)
         }
         Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
     }
 }
 
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        post,
+        path = "/api/previews",
+        params(WaitParams),
+        request_body = ComposeCreateUpdateRequest,
+        responses(
+            (status = 200, body = ComposeCreateUpdateResponse),
+            (status = 422, description = "Empty `gitBranch` or non-numeric `prId`")
+        )
+    )
+)]
 async fn create_or_update_preview(
     State(AppState {
         dokploy_client,
+        azure_client,
         config,
+        coordinator,
+        audit_log,
+        deploy_watcher,
         ..
     }): State<AppState>,
     ApiKey(api_key): ApiKey,
+    Query(params): Query<WaitParams>,
     Json(body): Json<ComposeCreateUpdateRequest>,
 ) -> Result<Json<ComposeCreateUpdateResponse>, (StatusCode, String)> {
-    let resp = upsert_preview_internal(
-        &dokploy_client,
-        &config,
-        &api_key,
-        &body.git_branch,
-        &body.pr_id,
-    )
-    .await?;
+    let body = body.validate()?;
+    let identifier = spinploy::compute_identifier(&body.pr_id, &body.git_branch);
+    let config = config
+        .for_repository(body.repository.as_deref().as_slice())
+        .with_compose_path(body.compose_path.as_deref());
+    let (mut resp, previous_deployment) = {
+        let _guard = coordinator.lock(&identifier).await;
+        let (upserted, pruned) = upsert_preview_internal(
+            &dokploy_client,
+            &config,
+            &api_key,
+            &audit_log.actor(API_ACTOR),
+            &body.git_branch,
+            &body.pr_id,
+            None,
+        )
+        .await?;
+        spawn_pruned_pr_comments(&azure_client, &config, pruned);
+        (upserted.resp, upserted.previous_deployment)
+    };
 
+    if params.wait {
+        resp.status = wait_for_triggered_deploy(
+            &dokploy_client,
+            &deploy_watcher,
+            &config,
+            &api_key,
+            &resp.compose_id,
+            previous_deployment.as_deref(),
+        )
+        .await;
+    }
     Ok(Json(resp))
 }
 
+#[cfg_attr(
+    feature = "openapi",
+    utoipa::path(
+        delete,
+        path = "/api/previews",
+        request_body = ComposeCreateUpdateRequest,
+        responses(
+            (status = 204, description = "Preview deleted, or there was none"),
+            (status = 422, description = "Empty `gitBranch` or non-numeric `prId`")
+        )
+    )
+)]
 async fn delete_preview(
-    State(AppState { dokploy_client, .. }): State<AppState>,
+    State(AppState {
+        dokploy_client,
+        config,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
     ApiKey(api_key): ApiKey,
     Json(body): Json<ComposeCreateUpdateRequest>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    delete_preview_internal(&dokploy_client, &api_key, &body.pr_id, &body.git_branch).await?;
+    let body = body.validate()?;
+    let identifier = spinploy::compute_identifier(&body.pr_id, &body.git_branch);
+    let config = config.for_repository(body.repository.as_deref().as_slice());
+    let _guard = coordinator.lock(&identifier).await;
+
+    delete_preview_internal(
+        &dokploy_client,
+        &config,
+        &api_key,
+        &audit_log.actor(API_ACTOR),
+        &body.pr_id,
+        &body.git_branch,
+    )
+    .await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
-async fn azure_pr_comment_webhook(
-    State(AppState {
-        dokploy_client,
-        config,
-        azure_client,
-        ..
-    }): State<AppState>,
-    ApiKey(api_key): ApiKey,
-    Json(payload): Json<AzurePrCommentEvent>,
-) -> Result<axum::response::Response, (StatusCode, String)> {
-    if payload.event_type != "ms.vss-code.git-pullrequest-comment-event" {
-        return Ok(StatusCode::NO_CONTENT.into_response());
+/// Verifies the Azure webhook signature (when a secret is configured) against the
+/// raw body, then deserializes the payload.
+fn parse_azure_webhook<T: DeserializeOwned>(
+    config: &Config,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<T, (StatusCode, String)> {
+    if let Some(secret) = &config.azure_webhook_secret {
+        let signature = headers
+            .get(AZURE_SIGNATURE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !spinploy::verify_webhook_signature(secret, body, signature) {
+            tracing::warn!("Rejected Azure webhook with missing or invalid signature");
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "invalid webhook signature".to_string(),
+            ));
+        }
     }
 
-    // No-op on deleted comments or missing/empty content
-    if payload.resource.comment.is_deleted
-        || payload
-            .resource
-            .comment
-            .content
-            .as_deref()
-            .map(|s| s.trim().is_empty())
-            .unwrap_or(true)
-    {
-        return Ok(StatusCode::NO_CONTENT.into_response());
-    }
+    serde_json::from_slice(body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid Azure webhook payload: {}", e),
+        )
+    })
+}
 
-    let Some(cmd) = &payload
-        .resource
-        .comment
-        .content
-        .as_deref()
-        .unwrap_or("")
-        .parse::<SlashCommand>()
-        .ok()
-    else {
-        return Ok(StatusCode::NO_CONTENT.into_response());
-    };
+/// Outcome of a PR comment slash command. It's both the webhook's JSON response and,
+/// through [`command_reply`], the reply posted in the PR thread, so the two can't drift.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandResult {
+    pub command: SlashCommand,
+    pub success: bool,
+    pub message: String,
+    pub frontend_url: Option<String>,
+    pub backend_url: Option<String>,
+    pub compose_id: Option<String>,
+    /// Dokploy status of the deployment the command triggered, if known
+    pub deployment_status: Option<String>,
+}
 
-    let branch = spinploy::strip_refs_heads(&payload.resource.pull_request.source_ref_name);
-    let pr_id = Some(payload.resource.pull_request.pull_request_id.to_string());
+impl CommandResult {
+    fn succeeded(command: SlashCommand, message: impl Into<String>) -> Self {
+        Self {
+            command,
+            success: true,
+            message: message.into(),
+            frontend_url: None,
+            backend_url: None,
+            compose_id: None,
+            deployment_status: None,
+        }
+    }
 
-    tracing::info!(
-        pr = pr_id.as_deref().unwrap_or("?"),
-        branch,
-        ?cmd,
-        "Received Azure PR comment webhook"
-    );
+    fn failed(command: SlashCommand, message: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            ..Self::succeeded(command, message)
+        }
+    }
 
-    // Extract thread id from the threads link ending with /threads/{id}
-    let thread_href = &payload.resource.comment.links.threads.href;
-    let thread_id = thread_href
-        .rsplit('/')
-        .next()
-        .and_then(|s| s.parse::<u64>().ok())
-        .ok_or((
-            StatusCode::BAD_REQUEST,
-            "invalid threads href in payload".to_string(),
-        ))?;
-    let repo_id = &config.azdo_repository_id;
+    /// `command` failed with `error`, which is logged but left out of the message, since
+    /// it may reveal internals to everyone reading the PR. The message points at the
+    /// logs with the request id instead.
+    fn errored(
+        command: SlashCommand,
+        what: &str,
+        error: impl fmt::Display,
+        request_id: Option<&RequestId>,
+    ) -> Self {
+        tracing::error!(error = %error, ?command, "Slash command failed");
+        let message = match request_id {
+            Some(request_id) => format!(
+                "❌ {}. Details are in the spinploy logs under request id `{}`",
+                what, request_id.id
+            ),
+            None => format!("❌ {}. Details are in the spinploy logs", what),
+        };
+        Self::failed(command, message)
+    }
 
-    match cmd {
-        SlashCommand::Preview => {
-            let resp = upsert_preview_internal(&dokploy_client, &config, &api_key, &branch, &pr_id)
-                .await?;
+    /// `command` came from `name`, a commenter `ALLOWED_COMMAND_USERS` leaves out.
+    fn not_allowed(command: SlashCommand, name: &str) -> Self {
+        let message = format!(
+            "🙏 Sorry {}, slash commands on this repository can only be run by allowed users. Ask one of them to run it for you",
+            name
+        );
+        Self::failed(command, message)
+    }
+}
 
-            let identifier = spinploy::compute_identifier(&pr_id, &branch);
-            let frontend = format!("https://{}.{}", identifier, &config.base_domain);
-            if let Err(e) = azure_client
-                .reply_in_thread(
-                    repo_id,
-                    payload.resource.pull_request.pull_request_id,
-                    thread_id,
-                    &format!("👷 Preview building, should be available soon: {} \n\n💻 View the status of all previews here: {}", frontend, config.deployed_preview_api_path),
-                )
+/// PR thread reply for a slash command's result: its message, then its URLs and
+/// deployment status as a list.
+fn command_reply(config: &Config, result: &CommandResult) -> String {
+    let details: Vec<String> = [
+        ("🖥️ Frontend", &result.frontend_url),
+        ("🔌 Backend", &result.backend_url),
+        ("📦 Deployment status", &result.deployment_status),
+    ]
+    .into_iter()
+    .filter_map(|(label, value)| Some(format!("- {}: {}", label, value.as_ref()?)))
+    .collect();
+
+    let mut reply = result.message.clone();
+    if !details.is_empty() {
+        reply.push_str(":\n\n");
+        reply.push_str(&details.join("\n"));
+    }
+    if result.command == SlashCommand::Preview && result.success {
+        reply.push_str(&format!(
+            "\n\n💻 View the status of all previews here: {}",
+            config.deployed_preview_api_path
+        ));
+    }
+    reply
+}
+
+/// Most previews listed in a `/list` reply
+const PREVIEW_LIST_LIMIT: usize = 20;
+
+/// Active previews by identifier, capped at `PREVIEW_LIST_LIMIT`, summarized as the API
+/// lists them; also returns how many there are in total.
+async fn list_active_previews(
+    state: &AppState,
+    api_key: &str,
+) -> Result<(Vec<PreviewSummary>, usize), (StatusCode, String)> {
+    let config = &state.config;
+    let mut composes = state
+        .dokploy_client
+        .list_composes_with_prefix(api_key, &config.environment_id, &config.app_name_prefix)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    composes.sort_by(|a, b| a.name.cmp(&b.name));
+    let total = composes.len();
+    composes.truncate(PREVIEW_LIST_LIMIT);
+
+    let previews = spinploy::join_bounded(
+        composes.into_iter().map(|compose| async move {
+            let detail = state
+                .dokploy_client
+                .get_compose_detail(api_key, &compose.compose_id)
                 .await
-            {
-                tracing::warn!(error = %e, "Failed to post ADO reply for /preview");
+                .ok();
+            let status = api::previews::preview_status(state, &compose, detail.as_ref()).await;
+            api::previews::build_preview_summary(state, api_key, &compose, detail.as_ref(), status)
+                .await
+        }),
+        config.dokploy_concurrency,
+    )
+    .await;
+    Ok((previews, total))
+}
+
+/// `/list` reply: one line per preview, noting how many were left out.
+fn preview_list_message(previews: &[PreviewSummary], total: usize) -> String {
+    if previews.is_empty() {
+        return "📋 No active previews".to_string();
+    }
+    let mut message = format!("📋 Active previews ({}):\n", total);
+    for preview in previews {
+        match &preview.frontend_url {
+            Some(url) => message.push_str(&format!("\n- `{}`: {}", preview.identifier, url)),
+            None => message.push_str(&format!("\n- `{}`", preview.identifier)),
+        }
+    }
+    if total > previews.len() {
+        message.push_str(&format!("\n\n…and {} more", total - previews.len()));
+    }
+    message
+}
+
+/// Push redeploy announcement; it's edited on every push, so it says when the latest
+/// one was.
+fn redeploy_thread_message(
+    config: &Config,
+    identifier: &str,
+    at: chrono::DateTime<chrono::Utc>,
+) -> String {
+    let (frontend_domain, backend_domain) = config.preview_hosts(identifier);
+    format!(
+        "🔄 Preview redeploying with the latest changes (pushed {}):\n\n- 🖥️ Frontend: https://{}\n- 🔌 Backend: https://{}",
+        at.format("%Y-%m-%d %H:%M UTC"),
+        frontend_domain,
+        backend_domain
+    )
+}
+
+/// Announce a push redeploy on its PR. The thread started for the first one is edited
+/// for later ones, so busy PRs don't get a thread per push; failures are only logged.
+async fn announce_redeploy(
+    azure_client: &AzureDevOpsClient,
+    coordinator: &WebhookCoordinator,
+    config: &Config,
+    repo_id: &str,
+    pr_id: u64,
+    identifier: &str,
+) {
+    let message = redeploy_thread_message(config, identifier, chrono::Utc::now());
+    if let Some(thread) = coordinator.redeploy_thread(identifier) {
+        let (thread_id, comment_id) = (thread.thread_id, thread.comment_id);
+        match azure_client
+            .update_comment(repo_id, pr_id, thread_id, comment_id, &message)
+            .await
+        {
+            Ok(_) => return,
+            // E.g. the thread was deleted; start another
+            Err(e) => {
+                tracing::warn!(error = %e, identifier, "Failed to update ADO redeploy thread");
             }
+        }
+    }
+    match azure_client.create_thread(repo_id, pr_id, &message).await {
+        Ok(thread) => coordinator.record_redeploy_thread(identifier, thread),
+        Err(e) => tracing::warn!(error = %e, identifier, "Failed to post ADO thread for redeploy"),
+    }
+}
 
-            Ok(Json(resp).into_response())
+fn ready_thread_message(config: &Config, identifier: &str) -> String {
+    let (frontend_domain, backend_domain) = config.preview_hosts(identifier);
+    format!(
+        "🚀 PR is ready for review, preview deploying:\n\n- 🖥️ Frontend: https://{}\n- 🔌 Backend: https://{}",
+        frontend_domain, backend_domain
+    )
+}
+
+/// Update the PR's preview status check; failures are only logged.
+async fn report_pr_status(
+    azure_client: &AzureDevOpsClient,
+    repo_id: &str,
+    pr_id: u64,
+    state: AzurePrStatusState,
+    description: &str,
+    target_url: Option<&str>,
+) {
+    if let Err(e) = azure_client
+        .set_pr_status(repo_id, pr_id, state, description, target_url)
+        .await
+    {
+        tracing::warn!(error = %e, pr = pr_id, ?state, "Failed to set ADO PR status");
+    }
+}
+
+/// Dedupe keys a webhook delivery registered, forgotten again if handling it fails
+/// so that the provider's retry isn't ignored as a duplicate.
+#[derive(Default)]
+struct DeliveryKeys {
+    event_id: Option<String>,
+    payload_key: Option<String>,
+    comment_key: Option<String>,
+}
+
+impl DeliveryKeys {
+    fn forget_if_failed(
+        &self,
+        coordinator: &WebhookCoordinator,
+        response: &Result<axum::response::Response, (StatusCode, String)>,
+    ) {
+        if response
+            .as_ref()
+            .is_ok_and(|response| response.status().is_success())
+        {
+            return;
+        }
+        self.forget(coordinator);
+    }
+
+    fn forget(&self, coordinator: &WebhookCoordinator) {
+        if let Some(event_id) = &self.event_id {
+            coordinator.forget_event(event_id);
+        }
+        if let Some(payload_key) = &self.payload_key {
+            coordinator.forget_payload(payload_key);
+        }
+        if let Some(comment_key) = &self.comment_key {
+            coordinator.forget_comment_command(comment_key);
+        }
+    }
+}
+
+/// Whether a webhook delivery carries an event id we've already handled.
+fn is_redelivery(
+    coordinator: &WebhookCoordinator,
+    keys: &mut DeliveryKeys,
+    event_id: Option<&str>,
+) -> bool {
+    let Some(event_id) = event_id else {
+        return false;
+    };
+    if coordinator.register_event(event_id) {
+        keys.event_id = Some(event_id.to_string());
+        return false;
+    }
+    tracing::info!(event_id, "Ignoring redelivered webhook event");
+    true
+}
+
+/// Keys a delivery by what it says rather than its envelope, so the same event resent
+/// under a new event id still matches.
+fn azure_payload_key(event_type: &str, pr_id: u64, source_ref: &str, body: &[u8]) -> String {
+    let resource = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|payload| payload.get("resource").cloned())
+        .unwrap_or_default();
+    let digest = Sha256::digest(resource.to_string().as_bytes());
+    format!(
+        "{}:{}:{}:{}",
+        event_type,
+        pr_id,
+        source_ref,
+        hex::encode(&digest[..8])
+    )
+}
+
+/// Whether an identical payload was delivered moments ago.
+fn is_duplicate_payload(
+    coordinator: &WebhookCoordinator,
+    keys: &mut DeliveryKeys,
+    payload_key: &str,
+) -> bool {
+    if coordinator.register_payload(payload_key) {
+        keys.payload_key = Some(payload_key.to_string());
+        return false;
+    }
+    tracing::info!(payload_key, "Ignoring duplicate webhook payload");
+    true
+}
+
+/// Keys an Azure repository's `REPOSITORIES` overrides may be under: its id and name.
+fn azure_repository_keys(repository: Option<&AzureRepositoryRef>) -> Vec<&str> {
+    repository
+        .map(|r| {
+            std::iter::once(r.id.as_str())
+                .chain(r.name.as_deref())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Thread id from a comment's threads link, which ends with `/threads/{id}`.
+fn thread_id_from_href(href: &str) -> Option<u64> {
+    href.rsplit('/').next().and_then(|s| s.parse().ok())
+}
+
+async fn azure_pr_comment_webhook(
+    State(state): State<AppState>,
+    api_key: ApiKey,
+    request_id: Option<Extension<RequestId>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let coordinator = state.coordinator.clone();
+    let mut keys = DeliveryKeys::default();
+    let request_id = request_id.map(|Extension(request_id)| request_id);
+    let response =
+        handle_azure_pr_comment(State(state), api_key, request_id, headers, body, &mut keys).await;
+    keys.forget_if_failed(&coordinator, &response);
+    response
+}
+
+async fn handle_azure_pr_comment(
+    State(state): State<AppState>,
+    ApiKey(api_key): ApiKey,
+    request_id: Option<RequestId>,
+    headers: HeaderMap,
+    body: Bytes,
+    keys: &mut DeliveryKeys,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    // `/list` summarizes previews from the whole state, as the API does
+    let AppState {
+        dokploy_client,
+        config,
+        azure_client,
+        slack_client,
+        coordinator,
+        audit_log,
+        deploy_watcher,
+        ..
+    } = state.clone();
+    let payload: AzurePrCommentEvent = parse_azure_webhook(&config, &headers, &body)?;
+    if payload.event_type != "ms.vss-code.git-pullrequest-comment-event"
+        || is_redelivery(&coordinator, keys, payload.id.as_deref())
+    {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    // Deleted comments and missing/empty content carry no command
+    let comment = &payload.resource.comment;
+    let line = comment
+        .content
+        .as_deref()
+        .filter(|_| !comment.is_deleted)
+        .and_then(|content| content.parse::<SlashCommandLine>().ok());
+    let cmd = line.as_ref().map(|line| line.command);
+
+    // Edits fire the same event; only act when the edit changed the command
+    let thread_href = &comment.links.threads.href;
+    if let (Some(thread_id), Some(comment_id)) = (thread_id_from_href(thread_href), comment.id) {
+        let comment_key = format!(
+            "{}:{}:{}",
+            payload.resource.pull_request.pull_request_id, thread_id, comment_id
+        );
+        if !coordinator.register_comment_command(&comment_key, cmd) {
+            tracing::info!(comment_key, "Ignoring comment edit that kept its command");
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+        keys.comment_key = Some(comment_key);
+    }
+
+    let Some(SlashCommandLine {
+        command: cmd,
+        compose_path,
+    }) = line
+    else {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+
+    let branch = spinploy::strip_refs_heads(&payload.resource.pull_request.source_ref_name);
+    let pr_id = Some(payload.resource.pull_request.pull_request_id.to_string());
+
+    if let (Some(request_id), Some(author)) = (&request_id, &comment.author) {
+        request_id.span.record("author", author.login());
+    }
+    let request_id = request_id.as_ref();
+    tracing::info!(
+        pr = pr_id.as_deref().unwrap_or("?"),
+        branch,
+        ?cmd,
+        "Received Azure PR comment webhook"
+    );
+
+    let thread_id = thread_id_from_href(thread_href).ok_or((
+        StatusCode::BAD_REQUEST,
+        "invalid threads href in payload".to_string(),
+    ))?;
+    let repository = payload.resource.pull_request.repository.as_ref();
+    let config = config
+        .for_repository(&azure_repository_keys(repository))
+        .with_compose_path(compose_path.as_deref());
+    // Reply on the repository the comment came from, unless it isn't in the payload
+    let repo_id = repository
+        .map(|r| r.id.clone())
+        .unwrap_or_else(|| config.azdo_repository_id.clone());
+    let source = PreviewSource::Azure(repo_id.clone());
+    let repo_id = repo_id.as_str();
+    let identifier = spinploy::compute_identifier(&pr_id, &branch);
+
+    let author = comment.author.as_ref();
+    // Display names aren't unique and anyone can pick theirs, so they don't identify a user
+    let author_names: Vec<&str> = author
+        .map(|a| {
+            a.unique_name
+                .as_deref()
+                .into_iter()
+                .chain(a.id.as_deref())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let actor = author.map_or_else(|| "azure".to_string(), |a| format!("azure:{}", a.login()));
+    let audit = audit_log.actor(actor);
+
+    let pr_number = payload.resource.pull_request.pull_request_id;
+    // Latest deployment before the one `/preview` triggered, if it triggered one
+    let mut deploy_to_watch = None;
+    let (status, result) = match cmd {
+        _ if !config.allows_command_user(&author_names) => {
+            tracing::info!(author = ?author_names, ?cmd, "Ignoring slash command from a user not allowed to run it");
+            let name = author.map_or("there", |a| a.display_name.as_str());
+            (StatusCode::OK, CommandResult::not_allowed(cmd, name))
+        }
+        SlashCommand::Preview => {
+            report_pr_status(
+                &azure_client,
+                repo_id,
+                pr_number,
+                AzurePrStatusState::Pending,
+                "Deploying preview",
+                None,
+            )
+            .await;
+
+            let result = {
+                let _guard = coordinator.lock(&identifier).await;
+                upsert_preview_internal(
+                    &dokploy_client,
+                    &config,
+                    &api_key,
+                    &audit,
+                    &branch,
+                    &pr_id,
+                    Some(&source),
+                )
+                .await
+            };
+            match result {
+                Ok((
+                    Upserted {
+                        resp,
+                        previous_deployment: previous,
+                        ..
+                    },
+                    pruned,
+                )) => {
+                    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+                    let (frontend_domain, backend_domain) = config.preview_hosts(&identifier);
+                    let frontend_url = format!("https://{}", frontend_domain);
+
+                    // Best effort: the deploy was just queued, so this is usually "running".
+                    // Until Dokploy records it, the latest deployment is still the previous
+                    // one, whose status isn't this deploy's
+                    let deployment_status = dokploy_client
+                        .get_compose_detail(&api_key, &resp.compose_id)
+                        .await
+                        .map_err(|e| {
+                            tracing::warn!(error = %e, compose_id = resp.compose_id, "Failed to fetch compose status for /preview reply");
+                        })
+                        .ok()
+                        .and_then(|detail| {
+                            let latest = detail.latest_deployment()?;
+                            if previous.as_deref() == Some(latest.deployment_id.as_str()) {
+                                Some("queued".to_string())
+                            } else {
+                                latest.status.clone()
+                            }
+                        });
+
+                    // Followed up on once the reply is posted, to edit it
+                    deploy_to_watch = Some(previous);
+
+                    let result = CommandResult {
+                        frontend_url: Some(frontend_url),
+                        backend_url: Some(format!("https://{}", backend_domain)),
+                        compose_id: Some(resp.compose_id),
+                        deployment_status,
+                        ..CommandResult::succeeded(
+                            cmd,
+                            "👷 Preview building, should be available soon",
+                        )
+                    };
+                    (StatusCode::OK, result)
+                }
+                Err((status, e)) => {
+                    report_pr_status(
+                        &azure_client,
+                        repo_id,
+                        pr_number,
+                        AzurePrStatusState::Failed,
+                        "Preview deploy failed",
+                        None,
+                    )
+                    .await;
+                    let what = "Preview deploy failed";
+                    (status, CommandResult::errored(cmd, what, e, request_id))
+                }
+            }
         }
         SlashCommand::Delete => {
-            delete_preview_internal(&dokploy_client, &api_key, &pr_id, &branch).await?;
+            let result = {
+                let _guard = coordinator.lock(&identifier).await;
+                delete_preview_internal(&dokploy_client, &config, &api_key, &audit, &pr_id, &branch)
+                    .await
+            };
+            match result {
+                Ok(_) => (
+                    StatusCode::OK,
+                    CommandResult::succeeded(cmd, "🗑️ Preview deleted"),
+                ),
+                Err((status, e)) => {
+                    let what = "Failed to delete preview";
+                    (status, CommandResult::errored(cmd, what, e, request_id))
+                }
+            }
+        }
+        SlashCommand::Promote => {
+            let result = {
+                let _guard = coordinator.lock(&staging_identifier(&identifier)).await;
+                promote_to_staging(
+                    &dokploy_client,
+                    &config,
+                    &api_key,
+                    &audit,
+                    &identifier,
+                    &branch,
+                    Some(&source),
+                )
+                .await
+            };
+            match result {
+                Ok(Some((resp, url))) => {
+                    let result = CommandResult {
+                        frontend_url: Some(url),
+                        compose_id: Some(resp.compose_id),
+                        ..CommandResult::succeeded(
+                            cmd,
+                            format!("🚀 Promoted `{}` to staging", branch),
+                        )
+                    };
+                    (StatusCode::OK, result)
+                }
+                Ok(None) => (
+                    StatusCode::OK,
+                    CommandResult::failed(cmd, "⚠️ Promotion to staging isn't configured"),
+                ),
+                Err((status, e)) => {
+                    let what = "Promotion to staging failed";
+                    (status, CommandResult::errored(cmd, what, e, request_id))
+                }
+            }
+        }
+        SlashCommand::Refresh => {
+            let result = {
+                let _guard = coordinator.lock(&identifier).await;
+                refresh_preview(
+                    &dokploy_client,
+                    &config,
+                    &api_key,
+                    &audit,
+                    &pr_id,
+                    &branch,
+                    Some(&source),
+                )
+                .await
+            };
+            match result {
+                Ok((Upserted { resp, .. }, pruned)) => {
+                    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+                    let (frontend_domain, backend_domain) = config.preview_hosts(&identifier);
+                    let result = CommandResult {
+                        frontend_url: Some(format!("https://{}", frontend_domain)),
+                        backend_url: Some(format!("https://{}", backend_domain)),
+                        compose_id: Some(resp.compose_id),
+                        ..CommandResult::succeeded(
+                            cmd,
+                            "♻️ Preview recreated, should be available soon",
+                        )
+                    };
+                    (StatusCode::OK, result)
+                }
+                Err(e) => {
+                    let what = e.reply();
+                    let (status, e) = e.into_error();
+                    (status, CommandResult::errored(cmd, what, e, request_id))
+                }
+            }
+        }
+        SlashCommand::List => match list_active_previews(&state, &api_key).await {
+            Ok((previews, total)) => (
+                StatusCode::OK,
+                CommandResult::succeeded(cmd, preview_list_message(&previews, total)),
+            ),
+            Err((status, e)) => {
+                let what = "Failed to list previews";
+                (status, CommandResult::errored(cmd, what, e, request_id))
+            }
+        },
+    };
+
+    let reply = command_reply(&config, &result);
+    let comment = azure_client
+        .reply_in_thread(repo_id, pr_number, thread_id, &reply)
+        .await
+        .map_err(|e| tracing::warn!(error = %e, ?cmd, "Failed to post ADO reply"))
+        .ok();
+
+    if let (Some(previous), Some(compose_id)) = (deploy_to_watch, result.compose_id.clone()) {
+        let watched = WatchedPreviewDeploy {
+            azure_client: azure_client.clone(),
+            slack_client: slack_client.clone(),
+            config,
+            repo_id: repo_id.to_string(),
+            pr_number,
+            identifier,
+            reply: comment,
+            started: result.clone(),
+        };
+        let client = dokploy_client.clone();
+        deploy_watcher.watch(client, api_key, compose_id, previous, |status| {
+            watched.report(status)
+        });
+    }
+
+    Ok((status, Json(result)).into_response())
+}
 
+/// An Azure `/preview` deploy followed up on in the background, and where to report
+/// how it ended.
+struct WatchedPreviewDeploy {
+    azure_client: Arc<AzureDevOpsClient>,
+    slack_client: Arc<SlackWebhookClient>,
+    config: Config,
+    repo_id: String,
+    pr_number: u64,
+    identifier: String,
+    /// The `/preview` reply, edited with the result
+    reply: Option<AzureCommentRef>,
+    started: CommandResult,
+}
+
+impl WatchedPreviewDeploy {
+    /// Sets the PR status check, edits the reply and notifies Slack once the deploy
+    /// finished with `status`, or only flags the status check if it wasn't followed to
+    /// the end.
+    async fn report(self, status: Option<PreviewStatus>) {
+        let Self {
+            azure_client,
+            slack_client,
+            config,
+            repo_id,
+            pr_number,
+            identifier,
+            reply,
+            started,
+        } = self;
+        let Some(status) = status else {
+            report_pr_status(
+                &azure_client,
+                &repo_id,
+                pr_number,
+                AzurePrStatusState::Error,
+                "Preview deploy not followed up on; check Dokploy for its outcome",
+                None,
+            )
+            .await;
+            return;
+        };
+        let finished = finished_command_result(started, status);
+        let (state, description) = match status {
+            PreviewStatus::Running => (AzurePrStatusState::Succeeded, "Preview deployed"),
+            _ => (AzurePrStatusState::Failed, "Preview deploy failed"),
+        };
+        let frontend_url = finished
+            .frontend_url
+            .as_deref()
+            .filter(|_| status == PreviewStatus::Running);
+        report_pr_status(
+            &azure_client,
+            &repo_id,
+            pr_number,
+            state,
+            description,
+            frontend_url,
+        )
+        .await;
+        if let Some(reply) = reply {
+            let content = command_reply(&config, &finished);
+            let (thread_id, comment_id) = (reply.thread_id, reply.comment_id);
             if let Err(e) = azure_client
-                .reply_in_thread(
-                    repo_id,
-                    payload.resource.pull_request.pull_request_id,
-                    thread_id,
-                    "🗑️ Preview deleted",
-                )
+                .update_comment(&repo_id, pr_number, thread_id, comment_id, &content)
                 .await
             {
-                tracing::warn!(error = %e, "Failed to post ADO reply for /delete");
+                tracing::warn!(
+                    error = %e,
+                    identifier,
+                    "Failed to update ADO reply with deploy result"
+                );
+            }
+        }
+        if config.slack_deploy_notifications {
+            let frontend_url = finished.frontend_url.as_deref().unwrap_or_default();
+            let message = deploy_finished_message(&identifier, status, frontend_url);
+            if let Err(e) = slack_client.send_text(message).await {
+                tracing::warn!(error = %e, identifier, "Failed to post deploy result to Slack");
             }
-
-            Ok(StatusCode::NO_CONTENT.into_response())
         }
     }
 }
 
+/// `/preview` result `started` once its deploy finished with `status`.
+fn finished_command_result(started: CommandResult, status: PreviewStatus) -> CommandResult {
+    let (success, message, deployment_status) = match status {
+        PreviewStatus::Running => (true, "✅ Preview deployed", "done"),
+        _ => (false, "❌ Preview deploy failed", "error"),
+    };
+    CommandResult {
+        success,
+        message: message.to_string(),
+        deployment_status: Some(deployment_status.to_string()),
+        ..started
+    }
+}
+
+/// What an Azure PR update that isn't a completion or abandonment means for its preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AzurePushAction {
+    /// Draft PR, with `skip_draft_previews` set
+    SkipDraft,
+    /// The PR was just marked ready for review; create its first preview, as pushes
+    /// to it only redeploy existing ones
+    Upsert,
+    RedeployIfExists,
+}
+
+/// `marked_ready` is whether the PR was a draft in its previous update and isn't now.
+fn azure_push_action(config: &Config, is_draft: bool, marked_ready: bool) -> AzurePushAction {
+    if is_draft && config.skip_draft_previews {
+        AzurePushAction::SkipDraft
+    } else if marked_ready {
+        AzurePushAction::Upsert
+    } else {
+        AzurePushAction::RedeployIfExists
+    }
+}
+
+fn has_pr_status(resource: &AzurePrUpdatedResource, status: &str) -> bool {
+    resource
+        .status
+        .as_deref()
+        .map(|s| s.eq_ignore_ascii_case(status))
+        .unwrap_or(false)
+}
+
 async fn azure_pr_updated_webhook(
-    State(AppState { dokploy_client, .. }): State<AppState>,
+    State(state): State<AppState>,
+    api_key: ApiKey,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let coordinator = state.coordinator.clone();
+    let mut keys = DeliveryKeys::default();
+    let response = handle_azure_pr_updated(State(state), api_key, headers, body, &mut keys).await;
+    keys.forget_if_failed(&coordinator, &response);
+    response
+}
+
+async fn handle_azure_pr_updated(
+    State(AppState {
+        dokploy_client,
+        azure_client,
+        config,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
     ApiKey(api_key): ApiKey,
-    Json(payload): Json<AzurePrUpdatedEvent>,
+    headers: HeaderMap,
+    body: Bytes,
+    keys: &mut DeliveryKeys,
 ) -> Result<axum::response::Response, (StatusCode, String)> {
-    if payload.event_type != "git.pullrequest.updated" {
+    let payload: AzurePrUpdatedEvent = parse_azure_webhook(&config, &headers, &body)?;
+    if payload.event_type != "git.pullrequest.updated"
+        || is_redelivery(&coordinator, keys, payload.id.as_deref())
+    {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let payload_key = azure_payload_key(
+        &payload.event_type,
+        payload.resource.pull_request_id,
+        &payload.resource.source_ref_name,
+        &body,
+    );
+    if is_duplicate_payload(&coordinator, keys, &payload_key) {
         return Ok(StatusCode::NO_CONTENT.into_response());
     }
 
+    let config =
+        config.for_repository(&azure_repository_keys(payload.resource.repository.as_ref()));
+    let repo_id = payload
+        .resource
+        .repository
+        .as_ref()
+        .map_or_else(|| config.azdo_repository_id.clone(), |r| r.id.clone());
+    let source = PreviewSource::Azure(repo_id);
+    let audit = audit_log.actor("azure");
     let branch = spinploy::strip_refs_heads(&payload.resource.source_ref_name);
     let pr_id = Some(payload.resource.pull_request_id.to_string());
+    let identifier = spinploy::compute_identifier(&pr_id, &branch);
 
-    // If this is a status update and PR is completed, delete preview (if target is main)
-    if payload
-        .resource
-        .status
-        .as_deref()
-        .map(|s| s.eq_ignore_ascii_case("completed"))
-        .unwrap_or(false)
-    {
+    // If this is a status update and PR is completed, delete preview (if target is a merge-delete branch)
+    if has_pr_status(&payload.resource, "completed") {
+        coordinator.forget_closed_pr(&identifier);
         let target_branch =
             spinploy::strip_refs_heads(payload.resource.target_ref_name.as_deref().unwrap_or(""));
 
@@ -794,34 +2675,694 @@ async fn azure_pr_updated_webhook(
             pr = pr_id.as_deref().unwrap_or("?"),
             source_branch = branch,
             target_branch,
+            merge_status = ?payload.resource.merge_status,
             "Received Azure PR updated webhook (status=completed)"
         );
 
-        if target_branch == "main" {
-            delete_preview_internal(&dokploy_client, &api_key, &pr_id, &branch).await?;
+        if config.deletes_preview_on_merge_into(&target_branch) {
+            delete_closed_pr_deployments(
+                &dokploy_client,
+                &config,
+                &api_key,
+                &audit,
+                &coordinator,
+                &pr_id,
+                &branch,
+            )
+            .await?;
         }
         return Ok(StatusCode::NO_CONTENT.into_response());
     }
 
+    // Abandoned PRs will never merge, so their preview can go regardless of target
+    if has_pr_status(&payload.resource, "abandoned") {
+        coordinator.forget_closed_pr(&identifier);
+        tracing::info!(
+            pr = pr_id.as_deref().unwrap_or("?"),
+            source_branch = branch,
+            "Received Azure PR updated webhook (status=abandoned). Deleting preview"
+        );
+
+        delete_closed_pr_deployments(
+            &dokploy_client,
+            &config,
+            &api_key,
+            &audit,
+            &coordinator,
+            &pr_id,
+            &branch,
+        )
+        .await?;
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    match &payload.resource.merge_status {
+        MergeStatus::Conflicts => tracing::info!(
+            pr = pr_id.as_deref().unwrap_or("?"),
+            branch,
+            "PR has merge conflicts with its target; its preview deploys the branch as is"
+        ),
+        MergeStatus::Failure | MergeStatus::Other(_) => tracing::info!(
+            pr = pr_id.as_deref().unwrap_or("?"),
+            branch,
+            merge_status = ?payload.resource.merge_status,
+            "PR can't be merged into its target"
+        ),
+        MergeStatus::Succeeded | MergeStatus::Queued | MergeStatus::NotSet => {}
+    }
+
+    let marked_ready = coordinator.record_draft_state(&identifier, payload.resource.is_draft);
+    match azure_push_action(&config, payload.resource.is_draft, marked_ready) {
+        AzurePushAction::SkipDraft => {
+            tracing::info!(
+                pr = pr_id.as_deref().unwrap_or("?"),
+                branch,
+                "Received Azure PR updated webhook (push) for a draft PR. Skipping preview"
+            );
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+        AzurePushAction::Upsert => {
+            tracing::info!(
+                pr = pr_id.as_deref().unwrap_or("?"),
+                branch,
+                "Received Azure PR updated webhook for a PR marked ready for review. Creating preview"
+            );
+            let (_, pruned) = {
+                let _guard = coordinator.lock(&identifier).await;
+                upsert_preview_internal(
+                    &dokploy_client,
+                    &config,
+                    &api_key,
+                    &audit,
+                    &branch,
+                    &pr_id,
+                    Some(&source),
+                )
+                .await?
+            };
+            spawn_pruned_pr_comments(&azure_client, &config, pruned);
+            let repo_id = payload
+                .resource
+                .repository
+                .as_ref()
+                .map_or(config.azdo_repository_id.as_str(), |r| r.id.as_str());
+            let message = ready_thread_message(&config, &identifier);
+            if let Err(e) = azure_client
+                .create_thread(repo_id, payload.resource.pull_request_id, &message)
+                .await
+            {
+                tracing::warn!(error = %e, identifier, "Failed to post ADO thread for ready PR");
+            }
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+        AzurePushAction::RedeployIfExists => {}
+    }
+
     tracing::info!(
         pr = pr_id.as_deref().unwrap_or("?"),
         branch,
         "Received Azure PR updated webhook (push). Attempting redeploy if exists"
     );
 
-    redeploy_preview_if_exists(&dokploy_client, &api_key, &pr_id, &branch).await?;
+    // Acknowledged right away; waiting out the coalesce window here could outlast the
+    // provider's webhook timeout
+    let redeploy = PushRedeploy {
+        dokploy_client,
+        azure_client,
+        coordinator,
+        repo_id: payload
+            .resource
+            .repository
+            .as_ref()
+            .map_or_else(|| config.azdo_repository_id.clone(), |r| r.id.clone()),
+        config,
+        api_key,
+        pr_number: payload.resource.pull_request_id,
+        pr_id,
+        branch: branch.to_string(),
+        identifier,
+        keys: std::mem::take(keys),
+    };
+    // Logged under the request's span, with the PR, so the redeploy's logs can be traced
+    // back to the push
+    let span = tracing::info_span!("push_redeploy", pr = redeploy.pr_number);
+    tokio::spawn(redeploy.run().instrument(span));
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
-async fn azure_build_completed_webhook(
-    State(AppState {
-        azure_client,
-        slack_client,
-        ..
+/// A push redeploy, done in the background once its webhook has been acknowledged.
+struct PushRedeploy {
+    dokploy_client: Arc<DokployClient>,
+    azure_client: Arc<AzureDevOpsClient>,
+    coordinator: Arc<WebhookCoordinator>,
+    config: Config,
+    api_key: String,
+    repo_id: String,
+    pr_number: u64,
+    pr_id: Option<String>,
+    branch: String,
+    identifier: String,
+    /// Forgotten if the redeploy fails, so a redelivery of the push isn't ignored
+    keys: DeliveryKeys,
+}
+
+impl PushRedeploy {
+    /// Waits out the coalesce window, then redeploys the preview if it exists and
+    /// announces that on the PR. Failures are only logged.
+    async fn run(self) {
+        let Self {
+            dokploy_client,
+            azure_client,
+            coordinator,
+            config,
+            api_key,
+            repo_id,
+            pr_number,
+            pr_id,
+            branch,
+            identifier,
+            keys,
+        } = self;
+        if !coordinator.coalesce_redeploy(&identifier).await {
+            tracing::info!(
+                identifier,
+                "Newer push arrived while coalescing; leaving the redeploy to it"
+            );
+            return;
+        }
+
+        let redeployed = {
+            let _guard = coordinator.lock(&identifier).await;
+            redeploy_preview_if_exists(&dokploy_client, &config, &api_key, &pr_id, &branch).await
+        };
+        match redeployed {
+            Ok(true) => {
+                announce_redeploy(
+                    &azure_client,
+                    &coordinator,
+                    &config,
+                    &repo_id,
+                    pr_number,
+                    &identifier,
+                )
+                .await;
+            }
+            Ok(false) => {}
+            Err((_, e)) => {
+                tracing::error!(error = %e, identifier, "Failed to redeploy preview after push");
+                keys.forget(&coordinator);
+            }
+        }
+    }
+}
+
+/// What a GitHub `pull_request` event means for its preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GithubPrAction {
+    Upsert,
+    Delete,
+}
+
+/// Audit log actor for a GitHub webhook, `github:<login>` when it names its sender.
+fn github_actor(sender: Option<&GithubUser>) -> String {
+    sender.map_or_else(|| "github".to_string(), |s| format!("github:{}", s.login))
+}
+
+fn github_pr_action(event: &GithubPullRequestEvent) -> Option<GithubPrAction> {
+    match event.action.as_str() {
+        "opened" | "synchronize" => Some(GithubPrAction::Upsert),
+        "closed" => {
+            let default_branch = event.repository.default_branch.as_deref().unwrap_or("main");
+            (event.pull_request.merged && event.pull_request.base.ref_name == default_branch)
+                .then_some(GithubPrAction::Delete)
+        }
+        _ => None,
+    }
+}
+
+fn invalid_github_payload(e: serde_json::Error) -> (StatusCode, String) {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("invalid GitHub webhook payload: {}", e),
+    )
+}
+
+/// The PR a GitHub comment was made on. Comment payloads don't carry the PR branch, so
+/// commands that deploy it look it up.
+async fn fetch_commented_pr(
+    github_client: &GitHubClient,
+    event: &GithubIssueCommentEvent,
+) -> Result<GithubPullRequest, (StatusCode, String)> {
+    github_client
+        .get_pull_request(&event.repository.full_name, event.issue.number)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, pr = event.issue.number, "Failed to fetch GitHub PR");
+            (
+                StatusCode::BAD_GATEWAY,
+                "failed to fetch pull request details".to_string(),
+            )
+        })
+}
+
+async fn github_pr_webhook(
+    State(state): State<AppState>,
+    api_key: ApiKey,
+    headers: HeaderMap,
+    payload: Json<serde_json::Value>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let coordinator = state.coordinator.clone();
+    let mut keys = DeliveryKeys::default();
+    let response = handle_github_pr(State(state), api_key, headers, payload, &mut keys).await;
+    keys.forget_if_failed(&coordinator, &response);
+    response
+}
+
+async fn handle_github_pr(
+    State(AppState {
+        dokploy_client,
+        azure_client,
+        config,
+        github_client,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
+    ApiKey(api_key): ApiKey,
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+    keys: &mut DeliveryKeys,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let event_type = headers
+        .get("x-github-event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let delivery_id = headers
+        .get("x-github-delivery")
+        .and_then(|v| v.to_str().ok());
+
+    if is_redelivery(&coordinator, keys, delivery_id) {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    match event_type {
+        "pull_request" => {
+            let event: GithubPullRequestEvent =
+                serde_json::from_value(payload).map_err(invalid_github_payload)?;
+            let config = config.for_repository(&[&event.repository.full_name]);
+            let source = PreviewSource::GitHub(event.repository.full_name.clone());
+            let audit = audit_log.actor(github_actor(event.sender.as_ref()));
+            let branch = event.pull_request.head.ref_name.clone();
+            let pr_id = Some(event.number.to_string());
+            let identifier = spinploy::compute_identifier(&pr_id, &branch);
+
+            tracing::info!(
+                pr = event.number,
+                branch,
+                action = event.action,
+                "Received GitHub pull_request webhook"
+            );
+
+            match github_pr_action(&event) {
+                Some(GithubPrAction::Upsert) => {
+                    let _guard = coordinator.lock(&identifier).await;
+                    let (Upserted { resp, .. }, pruned) = upsert_preview_internal(
+                        &dokploy_client,
+                        &config,
+                        &api_key,
+                        &audit,
+                        &branch,
+                        &pr_id,
+                        Some(&source),
+                    )
+                    .await?;
+                    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+                    Ok(Json(resp).into_response())
+                }
+                Some(GithubPrAction::Delete) => {
+                    delete_closed_pr_deployments(
+                        &dokploy_client,
+                        &config,
+                        &api_key,
+                        &audit,
+                        &coordinator,
+                        &pr_id,
+                        &branch,
+                    )
+                    .await?;
+                    Ok(StatusCode::NO_CONTENT.into_response())
+                }
+                None => Ok(StatusCode::NO_CONTENT.into_response()),
+            }
+        }
+        "issue_comment" => {
+            let event: GithubIssueCommentEvent =
+                serde_json::from_value(payload).map_err(invalid_github_payload)?;
+
+            // Only new comments on pull requests can carry slash commands
+            if event.action != "created" || event.issue.pull_request.is_none() {
+                return Ok(StatusCode::NO_CONTENT.into_response());
+            }
+
+            let Some(SlashCommandLine {
+                command: cmd,
+                compose_path,
+            }) = event
+                .comment
+                .body
+                .as_deref()
+                .and_then(|body| body.parse::<SlashCommandLine>().ok())
+            else {
+                return Ok(StatusCode::NO_CONTENT.into_response());
+            };
+
+            let config = config
+                .for_repository(&[&event.repository.full_name])
+                .with_compose_path(compose_path.as_deref());
+            let source = PreviewSource::GitHub(event.repository.full_name.clone());
+            let audit = audit_log.actor(github_actor(event.sender.as_ref()));
+            let pr_id = Some(event.issue.number.to_string());
+            let identifier = spinploy::compute_identifier(&pr_id, "");
+
+            tracing::info!(
+                pr = event.issue.number,
+                repo = event.repository.full_name,
+                ?cmd,
+                "Received GitHub issue_comment webhook"
+            );
+
+            let login = event.sender.as_ref().map(|sender| sender.login.as_str());
+            if !config.allows_command_user(login.as_slice()) {
+                tracing::info!(
+                    author = login,
+                    ?cmd,
+                    "Ignoring slash command from a user not allowed to run it"
+                );
+                let result = CommandResult::not_allowed(cmd, login.unwrap_or("there"));
+                return Ok(Json(result).into_response());
+            }
+
+            match cmd {
+                SlashCommand::Preview => {
+                    let pr = fetch_commented_pr(&github_client, &event).await?;
+
+                    let _guard = coordinator.lock(&identifier).await;
+                    let (Upserted { resp, .. }, pruned) = upsert_preview_internal(
+                        &dokploy_client,
+                        &config,
+                        &api_key,
+                        &audit,
+                        &pr.head.ref_name,
+                        &pr_id,
+                        Some(&source),
+                    )
+                    .await?;
+                    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+                    Ok(Json(resp).into_response())
+                }
+                SlashCommand::Delete => {
+                    // PR previews are identified by number alone; the branch is not needed
+                    let _guard = coordinator.lock(&identifier).await;
+                    delete_preview_internal(&dokploy_client, &config, &api_key, &audit, &pr_id, "")
+                        .await?;
+                    Ok(StatusCode::NO_CONTENT.into_response())
+                }
+                SlashCommand::Promote => {
+                    let pr = fetch_commented_pr(&github_client, &event).await?;
+
+                    let _guard = coordinator.lock(&staging_identifier(&identifier)).await;
+                    match promote_to_staging(
+                        &dokploy_client,
+                        &config,
+                        &api_key,
+                        &audit,
+                        &identifier,
+                        &pr.head.ref_name,
+                        Some(&source),
+                    )
+                    .await?
+                    {
+                        Some((resp, _)) => Ok(Json(resp).into_response()),
+                        None => Ok(StatusCode::NO_CONTENT.into_response()),
+                    }
+                }
+                SlashCommand::Refresh => {
+                    let pr = fetch_commented_pr(&github_client, &event).await?;
+
+                    let _guard = coordinator.lock(&identifier).await;
+                    let (Upserted { resp, .. }, pruned) = refresh_preview(
+                        &dokploy_client,
+                        &config,
+                        &api_key,
+                        &audit,
+                        &pr_id,
+                        &pr.head.ref_name,
+                        Some(&source),
+                    )
+                    .await
+                    .map_err(RefreshError::into_error)?;
+                    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+                    Ok(Json(resp).into_response())
+                }
+                // Listing is only useful as a PR reply, which GitHub doesn't get yet
+                SlashCommand::List => Ok(StatusCode::NO_CONTENT.into_response()),
+            }
+        }
+        _ => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+/// What a GitLab merge request event means for its preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GitlabMrAction {
+    Upsert,
+    Delete,
+}
+
+fn gitlab_mr_action(event: &GitlabMergeRequestEvent) -> Option<GitlabMrAction> {
+    let mr = &event.object_attributes;
+    match mr.action.as_deref()? {
+        "open" | "reopen" | "update" => Some(GitlabMrAction::Upsert),
+        "merge" => {
+            let default_branch = event.project.default_branch.as_deref().unwrap_or("main");
+            (mr.target_branch == default_branch).then_some(GitlabMrAction::Delete)
+        }
+        _ => None,
+    }
+}
+
+async fn gitlab_mr_webhook(
+    State(AppState {
+        dokploy_client,
+        azure_client,
+        config,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
+    ApiKey(api_key): ApiKey,
+    headers: HeaderMap,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    if let Some(expected) = &config.gitlab_webhook_token {
+        let token = headers
+            .get(GITLAB_TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !secrets_match(token, expected) {
+            tracing::warn!("Rejected GitLab webhook with missing or invalid token");
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "invalid webhook token".to_string(),
+            ));
+        }
+    }
+
+    let event: GitlabMergeRequestEvent = serde_json::from_value(payload).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid GitLab webhook payload: {}", e),
+        )
+    })?;
+    if event.object_kind != "merge_request" {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let config = config.for_repository(&[&event.project.path_with_namespace]);
+    let source = PreviewSource::GitLab(event.project.path_with_namespace.clone());
+    let audit = audit_log.actor("gitlab");
+    let mr = &event.object_attributes;
+    let branch = mr.source_branch.clone();
+    let pr_id = Some(mr.iid.to_string());
+    let identifier = spinploy::compute_identifier(&pr_id, &branch);
+
+    tracing::info!(
+        mr = mr.iid,
+        project = event.project.path_with_namespace,
+        branch,
+        action = mr.action.as_deref().unwrap_or("?"),
+        state = mr.state,
+        "Received GitLab merge request webhook"
+    );
+
+    match gitlab_mr_action(&event) {
+        Some(GitlabMrAction::Upsert) => {
+            let _guard = coordinator.lock(&identifier).await;
+            let (Upserted { resp, .. }, pruned) = upsert_preview_internal(
+                &dokploy_client,
+                &config,
+                &api_key,
+                &audit,
+                &branch,
+                &pr_id,
+                Some(&source),
+            )
+            .await?;
+            spawn_pruned_pr_comments(&azure_client, &config, pruned);
+            Ok(Json(resp).into_response())
+        }
+        Some(GitlabMrAction::Delete) => {
+            let _guard = coordinator.lock(&identifier).await;
+            delete_preview_internal(&dokploy_client, &config, &api_key, &audit, &pr_id, &branch)
+                .await?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+/// What a Bitbucket Server pull request event means for its preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitbucketPrAction {
+    Upsert,
+    Delete,
+}
+
+fn bitbucket_pr_action(
+    config: &Config,
+    event: &BitbucketPullRequestEvent,
+) -> Option<BitbucketPrAction> {
+    match event.event_key.as_str() {
+        "pr:opened" => Some(BitbucketPrAction::Upsert),
+        // The payload doesn't name the default branch, so use the merge-delete branches
+        "pr:merged" => config
+            .deletes_preview_on_merge_into(&event.pull_request.to_ref.display_id)
+            .then_some(BitbucketPrAction::Delete),
+        "pr:declined" => Some(BitbucketPrAction::Delete),
+        _ => None,
+    }
+}
+
+async fn bitbucket_pr_webhook(
+    State(AppState {
+        dokploy_client,
+        azure_client,
+        config,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
+    ApiKey(api_key): ApiKey,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    if let Some(secret) = &config.bitbucket_webhook_secret {
+        let signature = headers
+            .get(BITBUCKET_SIGNATURE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if !spinploy::verify_webhook_signature(secret, &body, signature) {
+            tracing::warn!("Rejected Bitbucket webhook with missing or invalid signature");
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "invalid webhook signature".to_string(),
+            ));
+        }
+    }
+
+    let payload: serde_json::Value =
+        serde_json::from_slice(&body).map_err(invalid_bitbucket_payload)?;
+    // Anything without a pull request (e.g. the `diagnostics:ping` test event) is acknowledged
+    if payload.get("pullRequest").is_none() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let event: BitbucketPullRequestEvent =
+        serde_json::from_value(payload).map_err(invalid_bitbucket_payload)?;
+
+    let pr = &event.pull_request;
+    // Overrides may be keyed by repository slug or `PROJECT/slug`
+    let repo = pr.to_ref.repository.as_ref();
+    let project_slug =
+        repo.and_then(|repo| Some(format!("{}/{}", repo.project.as_ref()?.key, repo.slug)));
+    let repo_keys: Vec<&str> = repo
+        .map(|repo| repo.slug.as_str())
+        .into_iter()
+        .chain(project_slug.as_deref())
+        .collect();
+    let config = config.for_repository(&repo_keys);
+    let source = project_slug
+        .as_deref()
+        .or(repo_keys.first().copied())
+        .map(|repo| PreviewSource::Bitbucket(repo.to_string()));
+    let audit = audit_log.actor("bitbucket");
+    let branch = pr.from_ref.display_id.clone();
+    let pr_id = Some(pr.id.to_string());
+    let identifier = spinploy::compute_identifier(&pr_id, &branch);
+
+    tracing::info!(
+        pr = pr.id,
+        branch,
+        target_branch = pr.to_ref.display_id,
+        event = event.event_key,
+        state = pr.state,
+        "Received Bitbucket pull request webhook"
+    );
+
+    match bitbucket_pr_action(&config, &event) {
+        Some(BitbucketPrAction::Upsert) => {
+            let _guard = coordinator.lock(&identifier).await;
+            let (Upserted { resp, .. }, pruned) = upsert_preview_internal(
+                &dokploy_client,
+                &config,
+                &api_key,
+                &audit,
+                &branch,
+                &pr_id,
+                source.as_ref(),
+            )
+            .await?;
+            spawn_pruned_pr_comments(&azure_client, &config, pruned);
+            Ok(Json(resp).into_response())
+        }
+        Some(BitbucketPrAction::Delete) => {
+            let _guard = coordinator.lock(&identifier).await;
+            delete_preview_internal(&dokploy_client, &config, &api_key, &audit, &pr_id, &branch)
+                .await?;
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+fn invalid_bitbucket_payload(e: serde_json::Error) -> (StatusCode, String) {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("invalid Bitbucket webhook payload: {}", e),
+    )
+}
+
+async fn azure_build_completed_webhook(
+    State(AppState {
+        config,
+        azure_client,
+        slack_client,
+        ..
     }): State<AppState>,
     ApiKey(_api_key): ApiKey,
-    Json(payload): Json<AzureBuildCompletedEvent>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Result<axum::response::Response, (StatusCode, String)> {
+    let payload: AzureBuildCompletedEvent = parse_azure_webhook(&config, &headers, &body)?;
     let event_ok = payload.event_type.eq_ignore_ascii_case("build.complete")
         || payload.event_type.eq_ignore_ascii_case("build.completed");
     if !event_ok {
@@ -1030,6 +3571,9 @@ struct LogsQuery {
     /// Whether to follow the log stream in real-time (default: true)
     #[serde(default = "default_follow")]
     follow: bool,
+    /// `text` (default) for raw lines or `json` for `{ timestamp, stream, message }` events
+    #[serde(default)]
+    format: LogFormat,
 }
 
 fn default_tail() -> u64 {
@@ -1067,6 +3611,7 @@ async fn list_containers(
 /// Query parameters:
 /// - `tail`: Number of lines to return from the end (default: 100, 0 = all)
 /// - `follow`: Whether to follow logs in real-time (default: true)
+/// - `format`: `text` (default) or `json` for structured lines
 ///
 /// Example: GET /containers/my-app/logs?tail=50&follow=true
 async fn stream_container_logs(
@@ -1088,103 +3633,2630 @@ async fn stream_container_logs(
         "Starting log stream"
     );
 
-    let rx = docker
-        .stream_logs(&container_name, query.tail, query.follow)
-        .await
-        .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+    let error_event = |e: String| Event::default().event("error").data(e);
 
-    let stream = ReceiverStream::new(rx).map(|result| {
-        let event = match result {
-            Ok(line) => Event::default().data(line),
-            Err(e) => Event::default().event("error").data(e),
-        };
-        Ok::<_, std::convert::Infallible>(event)
-    });
+    let stream = match query.format {
+        LogFormat::Text => {
+            let rx = docker
+                .stream_logs(&container_name, query.tail, query.follow)
+                .await
+                .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+
+            Either::Left(ReceiverStream::new(rx).map(move |result| {
+                let event = match result {
+                    Ok(line) => Event::default().data(line),
+                    Err(e) => error_event(e),
+                };
+                Ok::<_, std::convert::Infallible>(event)
+            }))
+        }
+        LogFormat::Json => {
+            let rx = docker
+                .stream_log_lines(&container_name, query.tail, query.follow)
+                .await
+                .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+
+            Either::Right(ReceiverStream::new(rx).map(move |result| {
+                let event = result
+                    .and_then(|line| Event::default().json_data(line).map_err(|e| e.to_string()))
+                    .unwrap_or_else(error_event);
+                Ok::<_, std::convert::Infallible>(event)
+            }))
+        }
+    };
 
     Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
+/// A preview considered by the prune policy.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PruneCandidate {
+    identifier: String,
+    compose_id: String,
+    /// Records the repository the preview was created for, see [`PreviewSource`]
+    #[serde(skip)]
+    description: Option<String>,
+    /// Latest deployment activity; previews without any sort first
+    last_activity_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Matches `PRUNE_PROTECT`; counts toward the limit but is never deleted
+    protected: bool,
+    would_delete: bool,
+}
+
+/// Which previews pruning would delete, oldest activity first.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrunePlan {
+    limit: usize,
+    candidates: Vec<PruneCandidate>,
+}
+
+/// Latest deployment timestamp (finishedAt -> startedAt -> createdAt), falling back to
+/// the compose's own createdAt.
+fn last_activity(detail: &ComposeDetail) -> Option<chrono::DateTime<chrono::Utc>> {
+    let latest = |ts: fn(&Deployment) -> Option<&str>| {
+        detail
+            .deployments
+            .iter()
+            .filter_map(ts)
+            .filter_map(parse_ts)
+            .max()
+    };
+
+    latest(|d| d.finished_at.as_deref())
+        .or_else(|| latest(|d| d.started_at.as_deref()))
+        .or_else(|| latest(|d| d.created_at.as_deref()))
+        .or_else(|| detail.created_at.as_deref().and_then(parse_ts))
+}
+
+/// Order previews oldest first and mark the unprotected ones over `limit` for deletion.
+/// `incoming` counts previews about to exist that aren't in `previews` yet.
+fn build_prune_plan(
+    config: &Config,
+    previews: Vec<(Compose, Option<ComposeDetail>)>,
+    incoming: usize,
+    limit: usize,
+) -> PrunePlan {
+    let mut candidates: Vec<PruneCandidate> = previews
+        .into_iter()
+        .map(|(compose, detail)| PruneCandidate {
+            protected: config.is_prune_protected(&compose.name),
+            identifier: compose.name,
+            compose_id: compose.compose_id,
+            description: compose.description,
+            last_activity_at: detail.as_ref().and_then(last_activity),
+            would_delete: false,
+        })
+        .collect();
+    candidates.sort_by_key(|c| c.last_activity_at);
+
+    let to_delete = (candidates.len() + incoming).saturating_sub(limit);
+    for candidate in candidates
+        .iter_mut()
+        .filter(|c| !c.protected)
+        .take(to_delete)
+    {
+        candidate.would_delete = true;
+    }
+
+    PrunePlan { limit, candidates }
+}
+
+/// Work out which previews in the environment pruning would delete, without deleting.
+/// Staging deployments are left out, even when they share the environment, so new
+/// previews can't evict them; they go with their PR or on reconcile.
+/// `incoming_compose_id` is a just-created preview that must survive.
+async fn plan_prune(
+    client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    incoming_compose_id: Option<&str>,
+) -> anyhow::Result<PrunePlan> {
+    let mut comps = client
+        .list_composes_with_prefix(api_key, &config.environment_id, &config.app_name_prefix)
+        .await?;
+    comps.retain(|c| {
+        !c.name.starts_with(STAGING_PREFIX) && Some(c.compose_id.as_str()) != incoming_compose_id
+    });
+
+    // Fetch compose details concurrently
+    let detailed = join_bounded(
+        comps.into_iter().map(|c| async move {
+            let detail = client.get_compose_detail(api_key, &c.compose_id).await.ok();
+            (c, detail)
+        }),
+        config.dokploy_concurrency,
+    )
+    .await;
+
+    Ok(build_prune_plan(
+        config,
+        detailed,
+        usize::from(incoming_compose_id.is_some()),
+        PREVIEW_LIMIT,
+    ))
+}
+
+/// Prunes down to the preview limit after `exclude_compose_id` was created, returning
+/// the previews deleted.
 async fn prune_previews_if_over_limit(
     client: &DokployClient,
+    config: &Config,
     api_key: &str,
-    environment_id: &str,
+    audit: &Auditor,
     exclude_compose_id: &str,
-) {
-    if let Ok(mut comps) = client
-        .list_composes_with_prefix(api_key, environment_id, "preview-")
-        .await
-    {
-        comps.retain(|c| c.compose_id != exclude_compose_id);
-        let total_after_creation = comps.len() + 1; // include the newly created preview
-        if total_after_creation > PREVIEW_LIMIT {
-            let to_delete = total_after_creation - PREVIEW_LIMIT;
-
-            // Fetch compose details concurrently
-            let mut detailed = futures::future::join_all(comps.iter().map(|c| async move {
-                (
-                    c.clone(),
-                    client.get_compose_detail(api_key, &c.compose_id).await,
-                )
-            }))
-            .await;
+) -> Vec<PruneCandidate> {
+    let plan = match plan_prune(client, config, api_key, Some(exclude_compose_id)).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to plan preview pruning");
+            return vec![];
+        }
+    };
 
-            // Sort by latest deployment timestamp (finishedAt -> startedAt -> createdAt), fallback to compose createdAt
-            detailed.sort_by_key(|(_c, detail)| {
-                detail
-                    .as_ref()
-                    .ok()
-                    .and_then(|dd| {
-                        dd.deployments
-                            .iter()
-                            .filter_map(|d| d.finished_at.as_deref())
-                            .filter_map(parse_ts)
-                            .max()
-                    })
-                    .or_else(|| {
-                        detail.as_ref().ok().and_then(|dd| {
-                            dd.deployments
-                                .iter()
-                                .filter_map(|d| d.started_at.as_deref())
-                                .filter_map(parse_ts)
-                                .max()
-                        })
-                    })
-                    .or_else(|| {
-                        detail.as_ref().ok().and_then(|dd| {
-                            dd.deployments
-                                .iter()
-                                .filter_map(|d| d.created_at.as_deref())
-                                .filter_map(parse_ts)
-                                .max()
-                        })
-                    })
-                    .or_else(|| {
-                        detail
-                            .as_ref()
-                            .ok()
-                            .and_then(|dd| dd.created_at.as_deref().and_then(parse_ts))
-                    })
-            });
+    // The caller holds the incoming preview's lock; taking others here could deadlock
+    execute_prune(client, config, api_key, audit, None, plan).await
+}
 
-            for (doomed, _detail) in detailed.into_iter().take(to_delete) {
-                if let Err(e) = client
-                    .delete_compose(api_key, &doomed.compose_id, true)
-                    .await
-                {
-                    tracing::warn!(
-                        compose_id = doomed.compose_id,
-                        error = %e,
-                        "Failed to prune preview"
-                    );
-                }
+/// Deletes the previews the plan marks (unless `PRUNE_DRY_RUN`), returning the
+/// previews actually deleted. With a coordinator, each delete waits for that
+/// preview's lock so it can't race a deploy in progress.
+async fn execute_prune(
+    client: &DokployClient,
+    config: &Config,
+    api_key: &str,
+    audit: &Auditor,
+    coordinator: Option<&WebhookCoordinator>,
+    plan: PrunePlan,
+) -> Vec<PruneCandidate> {
+    let mut deleted = Vec::new();
+    for doomed in plan.candidates.into_iter().filter(|c| c.would_delete) {
+        if config.prune_dry_run {
+            tracing::info!(
+                identifier = doomed.identifier,
+                compose_id = doomed.compose_id,
+                "Dry run: would prune preview"
+            );
+            continue;
+        }
+
+        let _guard = match coordinator {
+            Some(coordinator) => Some(coordinator.lock(&doomed.identifier).await),
+            None => None,
+        };
+        let result = client
+            .delete_compose(api_key, &doomed.compose_id, true)
+            .await;
+        audit
+            .record(&doomed.identifier, AuditAction::Prune, &result)
+            .await;
+        match result {
+            Ok(()) => {
+                telemetry::record_prune();
+                deleted.push(doomed);
             }
+            Err(e) => tracing::warn!(
+                compose_id = doomed.compose_id,
+                error = %e,
+                "Failed to prune preview"
+            ),
         }
     }
+    deleted
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Azure PR number of a pruned preview; branch previews have none.
+fn pruned_pr_id(identifier: &str) -> Option<u64> {
+    api::previews::parse_preview_identifier(identifier)
+        .0?
+        .parse()
+        .ok()
+}
+
+fn pruned_preview_message(identifier: &str) -> String {
+    format!(
+        "🧹 Preview `{}` was removed to stay under the preview limit. Comment `/preview` to bring it back",
+        identifier
+    )
+}
+
+/// Comments on the PRs of the `pruned` previews in the background, so nothing waits
+/// on Azure DevOps while holding a preview's lock or before replying.
+fn spawn_pruned_pr_comments(
+    azure_client: &Arc<AzureDevOpsClient>,
+    config: &Config,
+    pruned: Vec<PruneCandidate>,
+) {
+    if pruned.is_empty() || !config.prune_pr_comments {
+        return;
+    }
+    let azure_client = azure_client.clone();
+    let config = config.clone();
+    tokio::spawn(async move { comment_on_pruned_prs(&azure_client, &config, &pruned).await });
+}
+
+/// Tells the PRs of the `pruned` previews that their preview is gone, if
+/// `PRUNE_PR_COMMENTS` is set. Only previews created for a PR of `AZDO_REPOSITORY_ID`
+/// get a comment; `pr-<n>` of another repository or provider isn't that repository's
+/// PR n. Failures are only logged.
+async fn comment_on_pruned_prs(
+    azure_client: &AzureDevOpsClient,
+    config: &Config,
+    pruned: &[PruneCandidate],
+) {
+    if !config.prune_pr_comments {
+        return;
+    }
+    for PruneCandidate {
+        identifier,
+        description,
+        ..
+    } in pruned
+    {
+        if !config.is_azure_repository_preview(description.as_deref()) {
+            continue;
+        }
+        let Some(pr_id) = pruned_pr_id(identifier) else {
+            continue;
+        };
+        if let Err(e) = azure_client
+            .create_thread(
+                &config.azdo_repository_id,
+                pr_id,
+                &pruned_preview_message(identifier),
+            )
+            .await
+        {
+            tracing::warn!(error = %e, identifier, "Failed to comment on pruned preview's PR");
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PruneResponse {
+    /// Identifiers of the previews deleted by this run
+    deleted: Vec<String>,
+    /// `PRUNE_DRY_RUN` is set, so nothing was deleted
+    dry_run: bool,
+}
+
+/// POST /api/previews/prune - Prune down to the preview limit right now
+async fn prune_now(
+    State(AppState {
+        dokploy_client,
+        azure_client,
+        config,
+        coordinator,
+        audit_log,
+        ..
+    }): State<AppState>,
+    ApiKey(api_key): ApiKey,
+) -> Result<Json<PruneResponse>, (StatusCode, String)> {
+    let plan = plan_prune(&dokploy_client, &config, &api_key, None)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to plan preview pruning");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to plan pruning: {}", e),
+            )
+        })?;
+
+    let pruned = execute_prune(
+        &dokploy_client,
+        &config,
+        &api_key,
+        &audit_log.actor(API_ACTOR),
+        Some(&coordinator),
+        plan,
+    )
+    .await;
+    let deleted: Vec<String> = pruned.iter().map(|c| c.identifier.clone()).collect();
+    tracing::info!(?deleted, "Pruned previews on demand");
+    spawn_pruned_pr_comments(&azure_client, &config, pruned);
+
+    Ok(Json(PruneResponse {
+        deleted,
+        dry_run: config.prune_dry_run,
+    }))
+}
+
+/// GET /api/previews/prune/plan - What pruning would delete right now, without deleting
+async fn prune_plan(
+    State(AppState {
+        dokploy_client,
+        config,
+        ..
+    }): State<AppState>,
+    ApiKey(api_key): ApiKey,
+) -> Result<Json<PrunePlan>, (StatusCode, String)> {
+    plan_prune(&dokploy_client, &config, &api_key, None)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to plan preview pruning");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to plan pruning: {}", e),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        FakeDokploy, test_auditor, test_config, test_config_json, test_state,
+    };
+    use hmac::{Hmac, Mac};
+    use tower::ServiceExt;
+
+    fn pr_updated_request(event_id: &str) -> Request<Body> {
+        draft_pr_updated_request(event_id, false)
+    }
+
+    fn draft_pr_updated_request(event_id: &str, is_draft: bool) -> Request<Body> {
+        let body = serde_json::json!({
+            "id": event_id,
+            "eventType": "git.pullrequest.updated",
+            "resource": {
+                "pullRequestId": 7,
+                "sourceRefName": "refs/heads/feature/foo",
+                "targetRefName": "refs/heads/main",
+                "status": "active",
+                "isDraft": is_draft,
+            },
+        });
+        Request::post("/webhooks/azure/pr-updated")
+            .header("x-api-key", "dokploy-key")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn identical_pr_updated_payloads_redeploy_once() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            // Keep coalescing out of the way so only payload dedupe can drop the second delivery
+            redeploy_coalesce_secs: 0,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+            .with_state(test_state(config));
+
+        // Resent under a new event id, so event id dedupe doesn't catch it
+        for event_id in ["evt-1", "evt-2"] {
+            let resp = app
+                .clone()
+                .oneshot(pr_updated_request(event_id))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        // The redeploy runs after the push is acknowledged
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while fake.deploys().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("push never redeployed");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(fake.deploys(), vec!["compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_processed_when_retried() {
+        let unreachable = test_state(Config {
+            dokploy_url: "http://127.0.0.1:1".to_string(),
+            redeploy_coalesce_secs: 0,
+            ..test_config()
+        });
+        // Validated before Dokploy went away, so only the redeploy fails
+        unreachable
+            .auth_cache
+            .insert("dokploy-key".to_string(), AuthDecision::Valid)
+            .await;
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let reachable = AppState {
+            coordinator: unreachable.coordinator.clone(),
+            ..test_state(Config {
+                dokploy_url: fake.serve().await,
+                redeploy_coalesce_secs: 0,
+                ..test_config()
+            })
+        };
+
+        let resp = Router::new()
+            .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+            .with_state(unreachable)
+            .oneshot(pr_updated_request("evt-1"))
+            .await
+            .unwrap();
+        // Acknowledged before the redeploy fails in the background
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // The same delivery is redelivered once Dokploy is back, and redeploys once its
+        // failed attempt has been forgotten
+        let app = Router::new()
+            .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+            .with_state(reachable);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while fake.deploys().is_empty() {
+                let resp = app
+                    .clone()
+                    .oneshot(pr_updated_request("evt-1"))
+                    .await
+                    .unwrap();
+                assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("redelivery never redeployed");
+        assert_eq!(fake.deploys(), vec!["compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn pushes_are_acknowledged_before_coalescing() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            redeploy_coalesce_secs: 1,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+            .with_state(test_state(config));
+
+        let resp = tokio::time::timeout(
+            Duration::from_millis(500),
+            app.oneshot(pr_updated_request("evt-1")),
+        )
+        .await
+        .expect("push not acknowledged until the coalesce window passed")
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(fake.deploys().is_empty());
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while fake.deploys().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("push never redeployed");
+    }
+
+    #[test]
+    fn draft_prs_skip_previews_until_ready() {
+        let skipping = Config {
+            skip_draft_previews: true,
+            ..test_config()
+        };
+        assert_eq!(
+            azure_push_action(&skipping, true, false),
+            AzurePushAction::SkipDraft
+        );
+        assert_eq!(
+            azure_push_action(&skipping, false, true),
+            AzurePushAction::Upsert
+        );
+        assert_eq!(
+            azure_push_action(&skipping, false, false),
+            AzurePushAction::RedeployIfExists
+        );
+
+        // Drafts are treated like any PR unless skipping is enabled
+        assert_eq!(
+            azure_push_action(&test_config(), true, false),
+            AzurePushAction::RedeployIfExists
+        );
+    }
+
+    #[tokio::test]
+    async fn draft_marked_ready_gets_its_first_preview() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+            .with_state(test_state(config));
+
+        let resp = app
+            .clone()
+            .oneshot(draft_pr_updated_request("evt-1", true))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(fake.composes.lock().unwrap().is_empty());
+
+        let resp = app
+            .oneshot(draft_pr_updated_request("evt-2", false))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(fake.deploys(), vec!["compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_deletes_then_recreates_the_preview() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let state = test_state(Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        });
+
+        let (Upserted { resp, .. }, _) = refresh_preview(
+            &state.dokploy_client,
+            &state.config,
+            "key",
+            &test_auditor(),
+            &Some("7".to_string()),
+            "feature/foo",
+            None,
+        )
+        .await
+        .unwrap();
+
+        // Recreating before the delete would have found and redeployed the old compose,
+        // which the delete would then have removed
+        let composes = fake.composes.lock().unwrap().clone();
+        assert_eq!(composes.len(), 1);
+        assert_eq!(composes[0].app_name, "preview-pr-7");
+        assert_eq!(resp.compose_id, composes[0].compose_id);
+        assert_eq!(fake.deploys(), vec!["compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn blank_branches_and_malformed_pr_ids_are_rejected() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route(
+                "/previews",
+                post(create_or_update_preview).delete(delete_preview),
+            )
+            .with_state(test_state(config));
+
+        for (method, body, message) in [
+            (
+                "POST",
+                r#"{ "gitBranch": "" }"#,
+                "gitBranch must not be empty",
+            ),
+            (
+                "POST",
+                r#"{ "gitBranch": "  \t" }"#,
+                "gitBranch must not be empty",
+            ),
+            (
+                "DELETE",
+                r#"{ "gitBranch": " ", "prId": "7" }"#,
+                "gitBranch must not be empty",
+            ),
+            (
+                "POST",
+                r#"{ "gitBranch": "feature/foo", "prId": "seven" }"#,
+                "prId must be a PR number",
+            ),
+            (
+                "DELETE",
+                r#"{ "gitBranch": "feature/foo", "prId": "7a" }"#,
+                "prId must be a PR number",
+            ),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri("/previews")
+                .header("x-api-key", "dokploy-key")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let resp = app.clone().oneshot(request).await.unwrap();
+
+            assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+            let text = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(text, message);
+        }
+        assert!(fake.composes.lock().unwrap().is_empty());
+
+        // An empty prId means no PR, so the branch names the preview
+        let request = Request::post("/previews")
+            .header("x-api-key", "dokploy-key")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{ "gitBranch": "feature/foo", "prId": "" }"#))
+            .unwrap();
+        let resp = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let composes = fake.composes.lock().unwrap();
+        assert!(composes.iter().any(|c| c.name == "br-feature-foo"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_upserts_create_a_single_compose() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/previews", post(create_or_update_preview))
+            .with_state(test_state(config));
+
+        let upsert = || {
+            let request = Request::post("/previews")
+                .header("x-api-key", "dokploy-key")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{ "gitBranch": "feature/foo", "prId": "7" }"#))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+        let (first, second) = tokio::join!(upsert(), upsert());
+
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.unwrap().status(), StatusCode::OK);
+        let composes = fake.composes.lock().unwrap().clone();
+        assert_eq!(composes.len(), 1);
+        // The second upsert found the first one's compose and redeployed it
+        assert_eq!(fake.deploys(), vec!["compose-pr-7", "compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn create_requests_can_override_the_compose_path() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/previews", post(create_or_update_preview))
+            .with_state(test_state(config));
+
+        for body in [
+            r#"{ "gitBranch": "feature/foo", "prId": "7", "composePath": "infra/docker-compose.yml" }"#,
+            r#"{ "gitBranch": "feature/bar", "prId": "8" }"#,
+            // Applies to an existing preview too
+            r#"{ "gitBranch": "feature/foo", "prId": "7", "composePath": "ops/compose.yml" }"#,
+        ] {
+            let request = Request::post("/previews")
+                .header("x-api-key", "dokploy-key")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            assert_eq!(
+                app.clone().oneshot(request).await.unwrap().status(),
+                StatusCode::OK
+            );
+        }
+
+        assert_eq!(
+            fake.compose_paths.lock().unwrap().clone(),
+            vec![
+                "infra/docker-compose.yml",
+                "./docker-compose.yml",
+                "ops/compose.yml"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_can_wait_for_the_deploy_to_finish() {
+        let fake = FakeDokploy {
+            // Still running for the first polls of the wait
+            finish_deploys_after_polls: Some(3),
+            ..FakeDokploy::default()
+        };
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let state = AppState {
+            dokploy_client: Arc::new(
+                DokployClient::new(&config.dokploy_url, config.http_timeouts())
+                    .with_poll_interval(Duration::from_millis(10)),
+            ),
+            ..test_state(config)
+        };
+        let coordinator = state.coordinator.clone();
+        let app = Router::new()
+            .route("/previews", post(create_or_update_preview))
+            .with_state(state);
+
+        let request = Request::post("/previews?wait=true")
+            .header("x-api-key", "dokploy-key")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{ "gitBranch": "feature/foo", "prId": "7" }"#))
+            .unwrap();
+        let resp = tokio::spawn(app.oneshot(request));
+
+        // The preview isn't held while its deploy is waited for
+        while fake.deploys().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::timeout(Duration::from_millis(500), coordinator.lock("pr-7"))
+            .await
+            .expect("preview still locked while waiting for its deploy");
+
+        let resp = resp.await.unwrap().unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "Running");
+        assert!(fake.detail_polls.load(std::sync::atomic::Ordering::SeqCst) > 3);
+    }
+
+    #[tokio::test]
+    async fn waiting_for_a_deploy_stops_on_shutdown() {
+        // Deploys never finish
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let state = test_state(config);
+        let deploy_watcher = state.deploy_watcher.clone();
+        let app = Router::new()
+            .route("/previews", post(create_or_update_preview))
+            .with_state(state);
+
+        let request = Request::post("/previews?wait=true")
+            .header("x-api-key", "dokploy-key")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{ "gitBranch": "feature/foo", "prId": "7" }"#))
+            .unwrap();
+        let resp = tokio::spawn(app.oneshot(request));
+        while fake.deploys().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        deploy_watcher.shutdown().await;
+
+        let resp = tokio::time::timeout(Duration::from_secs(5), resp)
+            .await
+            .expect("still waiting for the deploy after shutdown")
+            .unwrap()
+            .unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "Building");
+    }
+
+    fn pr_comment_request(event_id: &str, content: &str) -> Request<Body> {
+        let body = serde_json::json!({
+            "id": event_id,
+            "eventType": "ms.vss-code.git-pullrequest-comment-event",
+            "resource": {
+                "comment": {
+                    "id": 1,
+                    "content": content,
+                    "author": {
+                        "displayName": "Jamal Hartnett",
+                        "uniqueName": "fabrikamfiber4@hotmail.com",
+                    },
+                    "_links": {
+                        "threads": { "href": "https://dev.azure.com/org/project/_apis/git/repositories/repo/pullRequests/7/threads/3" }
+                    },
+                },
+                "pullRequest": {
+                    "pullRequestId": 7,
+                    "sourceRefName": "refs/heads/feature/foo",
+                },
+            },
+        });
+        Request::post("/webhooks/azure/pr-comment")
+            .header("x-api-key", "dokploy-key")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn azure_handlers_deploy_the_branch_without_refs_heads() {
+        let requests = [
+            (
+                "/webhooks/azure/pr-comment",
+                vec![pr_comment_request("evt-1", "/preview")],
+            ),
+            (
+                "/webhooks/azure/pr-updated",
+                vec![
+                    draft_pr_updated_request("evt-1", true),
+                    draft_pr_updated_request("evt-2", false),
+                ],
+            ),
+        ];
+        for (path, requests) in requests {
+            let fake = FakeDokploy::default();
+            let dokploy_url = fake.serve().await;
+            let config = Config {
+                // Status updates and replies land on the fake and fail fast
+                azdo_base_url: dokploy_url.clone(),
+                dokploy_url,
+                ..test_config()
+            };
+            let app = Router::new()
+                .route("/webhooks/azure/pr-comment", post(azure_pr_comment_webhook))
+                .route("/webhooks/azure/pr-updated", post(azure_pr_updated_webhook))
+                .with_state(test_state(config));
+
+            for request in requests {
+                app.clone().oneshot(request).await.unwrap();
+            }
+
+            let branches = fake.git_branches.lock().unwrap().clone();
+            assert_eq!(branches, ["feature/foo"], "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn preview_reply_ignores_the_previous_deployments_status() {
+        let fake = FakeDokploy {
+            // The earlier deploy finished; the new one isn't recorded yet
+            finish_deploys_after_polls: Some(0),
+            queue_deploys: true,
+            ..FakeDokploy::with_composes(&["pr-7"])
+        };
+        fake.deploys
+            .lock()
+            .unwrap()
+            .push("compose-pr-7".to_string());
+        let dokploy_url = fake.serve().await;
+        let config = Config {
+            // Status updates and replies land on the fake and fail fast
+            azdo_base_url: dokploy_url.clone(),
+            dokploy_url,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-comment", post(azure_pr_comment_webhook))
+            .with_state(test_state(config));
+
+        let resp = app
+            .oneshot(pr_comment_request("evt-1", "/preview"))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], true);
+        assert_eq!(body["deploymentStatus"], "queued");
+    }
+
+    #[tokio::test]
+    async fn comment_edit_keeping_the_command_is_a_no_op() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let dokploy_url = fake.serve().await;
+        let config = Config {
+            // Status updates and replies land on the fake and fail fast
+            azdo_base_url: dokploy_url.clone(),
+            dokploy_url,
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-comment", post(azure_pr_comment_webhook))
+            .with_state(test_state(config));
+
+        let resp = app
+            .clone()
+            .oneshot(pr_comment_request("evt-1", "/preview"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Edited, but still just asking for a preview
+        let resp = app
+            .oneshot(pr_comment_request("evt-2", "  /PREVIEW\n"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(fake.deploys(), vec!["compose-pr-7"]);
+    }
+
+    #[tokio::test]
+    async fn commands_from_users_not_allowed_are_declined() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let dokploy_url = fake.serve().await;
+        let config = Config {
+            azdo_base_url: dokploy_url.clone(),
+            dokploy_url,
+            // The commenter's display name, which anyone could have picked
+            allowed_command_users: vec![
+                "release-manager@example.com".to_string(),
+                "Jamal Hartnett".to_string(),
+            ],
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/azure/pr-comment", post(azure_pr_comment_webhook))
+            .with_state(test_state(config));
+
+        let resp = app
+            .oneshot(pr_comment_request("evt-1", "/preview"))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("Jamal Hartnett"));
+        assert!(fake.deploys().is_empty());
+    }
+
+    async fn upsert_against(fake: &FakeDokploy) -> ComposeCreateUpdateResponse {
+        let state = test_state(Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        });
+
+        upsert_preview_internal(
+            &state.dokploy_client,
+            &state.config,
+            "dokploy-key",
+            &test_auditor(),
+            "feature/foo",
+            &None,
+            None,
+        )
+        .await
+        .unwrap()
+        .0
+        .resp
+    }
+
+    #[tokio::test]
+    async fn redeploys_build_until_dokploy_records_the_new_deployment() {
+        let fake = FakeDokploy {
+            // Only the deployment from before this redeploy is there, and it's done
+            finish_deploys_after_polls: Some(0),
+            queue_deploys: true,
+            ..FakeDokploy::with_composes(&["br-feature-foo"])
+        };
+        fake.deploys
+            .lock()
+            .unwrap()
+            .push("compose-br-feature-foo".to_string());
+
+        let resp = upsert_against(&fake).await;
+
+        assert_eq!(resp.status, PreviewStatus::Building);
+    }
+
+    #[tokio::test]
+    async fn failed_creates_of_a_concurrently_created_compose_configure_and_deploy_it() {
+        for error in [StatusCode::CONFLICT, StatusCode::INTERNAL_SERVER_ERROR] {
+            let fake = FakeDokploy {
+                create_error_after_storing: Some(error),
+                ..FakeDokploy::default()
+            };
+
+            let resp = upsert_against(&fake).await;
+
+            assert_eq!(resp.compose_id, "compose-br-feature-foo");
+            assert_eq!(fake.composes.lock().unwrap().len(), 1);
+            // Configured before it's deployed, in case its creator hadn't got to it yet
+            assert_eq!(fake.envs.lock().unwrap().len(), 1);
+            assert_eq!(fake.deploys(), ["compose-br-feature-foo"]);
+            assert!(!resp.domains.is_empty());
+            assert_eq!(resp.domains, fake.domain_hosts());
+        }
+    }
+
+    #[tokio::test]
+    async fn created_previews_record_their_source_repository() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        };
+        let state = test_state(config.clone());
+        let source = PreviewSource::Azure("repo".to_string());
+
+        upsert_preview_internal(
+            &state.dokploy_client,
+            &config,
+            "key",
+            &test_auditor(),
+            "feature/foo",
+            &None,
+            Some(&source),
+        )
+        .await
+        .unwrap();
+
+        let description = fake.composes.lock().unwrap()[0].description.clone();
+        assert_eq!(description.as_deref(), Some("spinploy:azure:repo"));
+        assert!(config.is_azure_repository_preview(description.as_deref()));
+        let github = PreviewSource::GitHub("repo".to_string()).description();
+        assert!(!config.is_azure_repository_preview(Some(&github)));
+        assert!(!config.is_azure_repository_preview(Some("spinploy:azure:other")));
+        assert!(!config.is_azure_repository_preview(None));
+    }
+
+    #[tokio::test]
+    async fn previews_without_a_source_get_it_on_their_next_deploy() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let state = test_state(Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        });
+        let source = PreviewSource::Azure("repo".to_string());
+
+        upsert_preview_internal(
+            &state.dokploy_client,
+            &state.config,
+            "key",
+            &test_auditor(),
+            "feature/foo",
+            &Some("7".to_string()),
+            Some(&source),
+        )
+        .await
+        .unwrap();
+
+        let description = fake.composes.lock().unwrap()[0].description.clone();
+        assert_eq!(description.as_deref(), Some("spinploy:azure:repo"));
+    }
+
+    #[tokio::test]
+    async fn previews_of_other_environments_and_bots_are_left_alone() {
+        let fake = FakeDokploy::with_composes(&["pr-3"]);
+        for (compose_id, app_name, environment_id) in [
+            ("compose-other-env", "preview-pr-3-def456", "env-b"),
+            ("compose-other-bot", "other-bot-pr-3-abc123", "env-b"),
+        ] {
+            fake.composes.lock().unwrap().push(Compose {
+                compose_id: compose_id.to_string(),
+                name: "pr-3".to_string(),
+                app_name: app_name.to_string(),
+                environment_id: environment_id.to_string(),
+                domains: vec![],
+                created_at: None,
+                description: None,
+            });
+        }
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            environment_id: "env-b".to_string(),
+            ..test_config()
+        };
+        let client = DokployClient::new(&config.dokploy_url, config.http_timeouts());
+        let pr_id = Some("3".to_string());
+
+        assert!(
+            redeploy_preview_if_exists(&client, &config, "key", &pr_id, "feature/x")
+                .await
+                .unwrap()
+        );
+        delete_preview_internal(
+            &client,
+            &config,
+            "key",
+            &test_auditor(),
+            &pr_id,
+            "feature/x",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(fake.deploys(), ["compose-other-env"]);
+        let remaining: Vec<_> = fake
+            .composes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.compose_id.clone())
+            .collect();
+        assert_eq!(remaining, ["compose-pr-3", "compose-other-bot"]);
+    }
+
+    #[tokio::test]
+    async fn upsert_compose_targets_the_given_environment() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            staging_environment_id: Some("staging".to_string()),
+            staging_base_domain: Some("staging.example.com".to_string()),
+            ..test_config()
+        };
+        let staging = config.staging_target().unwrap();
+        let client = DokployClient::new(&config.dokploy_url, config.http_timeouts());
+
+        let Upserted { resp, created, .. } = upsert_compose(
+            &client,
+            &config,
+            "dokploy-key",
+            &staging,
+            &staging_identifier("pr-7"),
+            "feature/foo",
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(created);
+        assert_eq!(
+            resp.domains,
+            [
+                "staging-pr-7.staging.example.com",
+                "api-staging-pr-7.staging.example.com"
+            ]
+        );
+        let composes = fake.composes.lock().unwrap().clone();
+        assert_eq!(composes.len(), 1);
+        assert_eq!(composes[0].name, "staging-pr-7");
+        assert_eq!(composes[0].environment_id, "staging");
+    }
+
+    #[tokio::test]
+    async fn closing_a_pr_deletes_its_preview_and_staging_deployment() {
+        let fake = FakeDokploy::default();
+        for (name, environment_id) in [("pr-7", "env"), ("staging-pr-7", "staging")] {
+            fake.composes.lock().unwrap().push(Compose {
+                compose_id: format!("compose-{name}"),
+                name: name.to_string(),
+                app_name: format!("preview-{name}-abc123"),
+                environment_id: environment_id.to_string(),
+                domains: vec![],
+                created_at: None,
+                description: None,
+            });
+        }
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            staging_environment_id: Some("staging".to_string()),
+            staging_base_domain: Some("staging.example.com".to_string()),
+            ..test_config()
+        };
+        let client = DokployClient::new(&config.dokploy_url, config.http_timeouts());
+        let coordinator = WebhookCoordinator::new(Duration::ZERO, Duration::ZERO, Duration::ZERO);
+
+        delete_closed_pr_deployments(
+            &client,
+            &config,
+            "key",
+            &test_auditor(),
+            &coordinator,
+            &Some("7".to_string()),
+            "feature/x",
+        )
+        .await
+        .unwrap();
+
+        assert!(fake.composes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn targets_shape_identifiers_hosts_and_env() {
+        let config = Config {
+            staging_environment_id: Some("staging".to_string()),
+            staging_base_domain: Some("staging.example.com".to_string()),
+            ..test_config()
+        };
+        let preview = config.preview_target();
+        let staging = config.staging_target().unwrap();
+        assert_eq!(preview.environment_id, "env");
+
+        let hosts = |target: &PreviewTarget, identifier: &str| -> Vec<String> {
+            plan_preview_domains(&config, target, identifier)
+                .unwrap()
+                .into_iter()
+                .map(|d| d.host)
+                .collect()
+        };
+        assert_eq!(
+            hosts(&preview, "pr-7"),
+            ["pr-7.preview.example.com", "api-pr-7.preview.example.com"]
+        );
+        let identifier = staging_identifier("pr-7");
+        assert_eq!(identifier, "staging-pr-7");
+        assert_eq!(
+            hosts(&staging, &identifier),
+            [
+                "staging-pr-7.staging.example.com",
+                "api-staging-pr-7.staging.example.com"
+            ]
+        );
+
+        let env = build_preview_env(&config, &staging, &identifier);
+        assert!(env.contains("staging-pr-7.staging.example.com"));
+        assert!(!env.contains("preview.example.com"));
+    }
+
+    #[tokio::test]
+    async fn prune_now_deletes_unprotected_previews_over_the_limit() {
+        let fake = FakeDokploy::with_composes(&["pr-1", "pr-2", "pr-3", "pr-4", "pr-5"]);
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            prune_protect: vec!["pr-1".to_string()],
+            ..test_config()
+        };
+
+        let Json(resp) = prune_now(State(test_state(config)), ApiKey("key".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.deleted, ["pr-2", "pr-3"]);
+        assert!(!resp.dry_run);
+        let remaining: Vec<_> = fake
+            .composes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|c| c.name.clone())
+            .collect();
+        assert_eq!(remaining, ["pr-1", "pr-4", "pr-5"]);
+    }
+
+    #[tokio::test]
+    async fn create_response_uses_returned_domains() {
+        let fake = FakeDokploy::default();
+
+        let resp = upsert_against(&fake).await;
+
+        assert_eq!(resp.domains, fake.domain_hosts());
+        assert_eq!(resp.domains.len(), 2);
+        assert_eq!(
+            fake.domain_lists.load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_and_pruning_only_consider_the_app_name_prefix() {
+        let fake = FakeDokploy::with_composes(&["pr-1", "pr-2"]);
+        fake.composes.lock().unwrap().push(Compose {
+            compose_id: "compose-other".to_string(),
+            name: "pr-3".to_string(),
+            app_name: "other-bot-pr-3-abc123".to_string(),
+            environment_id: "env".to_string(),
+            domains: vec![],
+            created_at: None,
+            description: None,
+        });
+        let dokploy_url = fake.serve().await;
+
+        for (prefix, expected) in [
+            ("preview-", vec!["pr-1", "pr-2"]),
+            ("other-bot-", vec!["pr-3"]),
+        ] {
+            let config = Config {
+                dokploy_url: dokploy_url.clone(),
+                app_name_prefix: prefix.to_string(),
+                ..test_config()
+            };
+            let state = test_state(config.clone());
+
+            let plan = plan_prune(&state.dokploy_client, &config, "key", None)
+                .await
+                .unwrap();
+            let mut planned: Vec<_> = plan
+                .candidates
+                .iter()
+                .map(|c| c.identifier.as_str())
+                .collect();
+            planned.sort();
+            assert_eq!(planned, expected);
+
+            let (listed, total) = list_active_previews(&state, "key").await.unwrap();
+            let listed: Vec<_> = listed
+                .iter()
+                .map(|preview| preview.identifier.as_str())
+                .collect();
+            assert_eq!(listed, expected);
+            assert_eq!(total, expected.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn new_previews_never_prune_staging_deployments() {
+        let fake = FakeDokploy::with_composes(&["pr-1", "pr-2", "pr-3"]);
+        for (name, environment_id) in [("staging-pr-1", "staging"), ("staging-pr-2", "env")] {
+            fake.composes.lock().unwrap().push(Compose {
+                compose_id: format!("compose-{name}"),
+                name: name.to_string(),
+                app_name: format!("preview-{name}-abc123"),
+                environment_id: environment_id.to_string(),
+                domains: vec![],
+                created_at: None,
+                description: None,
+            });
+        }
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            staging_environment_id: Some("staging".to_string()),
+            ..test_config()
+        };
+        let client = DokployClient::new(&config.dokploy_url, config.http_timeouts());
+
+        let plan = plan_prune(&client, &config, "key", Some("compose-pr-4"))
+            .await
+            .unwrap();
+
+        let identifiers: Vec<_> = plan
+            .candidates
+            .iter()
+            .map(|c| c.identifier.as_str())
+            .collect();
+        assert_eq!(identifiers.len(), 3, "{identifiers:?}");
+        assert!(
+            identifiers.iter().all(|i| i.starts_with("pr-")),
+            "{identifiers:?}"
+        );
+        assert_eq!(plan.candidates.iter().filter(|c| c.would_delete).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn prune_planning_bounds_concurrent_dokploy_calls() {
+        let fake = FakeDokploy {
+            // Long enough for concurrent requests to overlap
+            detail_delay: Some(Duration::from_millis(20)),
+            ..FakeDokploy::with_composes(&["pr-1", "pr-2", "pr-3", "pr-4", "pr-5", "pr-6"])
+        };
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            dokploy_concurrency: 2,
+            ..test_config()
+        };
+        let client = DokployClient::new(&config.dokploy_url, config.http_timeouts());
+
+        let plan = plan_prune(&client, &config, "key", None).await.unwrap();
+
+        assert_eq!(plan.candidates.len(), 6);
+        assert_eq!(
+            fake.max_details_in_flight
+                .load(std::sync::atomic::Ordering::SeqCst),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn create_response_lists_domains_when_dokploy_returns_none() {
+        let fake = FakeDokploy {
+            empty_domain_responses: true,
+            ..FakeDokploy::default()
+        };
+
+        let resp = upsert_against(&fake).await;
+
+        assert_eq!(resp.domains, fake.domain_hosts());
+        assert_eq!(resp.domains.len(), 2);
+        assert_eq!(
+            fake.domain_lists.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+    }
+
+    fn request_id_app() -> Router {
+        Router::new()
+            .route(
+                "/fail",
+                get(|| async { (StatusCode::BAD_REQUEST, "nope".to_string()) }),
+            )
+            .layer(middleware::from_fn(assign_request_id))
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_back() {
+        let response = request_id_app()
+            .oneshot(
+                Request::builder()
+                    .uri("/fail")
+                    .header("x-request-id", "abc-123")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+    }
+
+    #[tokio::test]
+    async fn request_id_is_generated_when_missing_or_invalid() {
+        for supplied in [None, Some("has spaces")] {
+            let mut request = Request::builder().uri("/fail");
+            if let Some(id) = supplied {
+                request = request.header("x-request-id", id);
+            }
+            let response = request_id_app()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            let id = response.headers()["x-request-id"].to_str().unwrap();
+            assert_eq!(id.len(), 32);
+            assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        }
+    }
+
+    #[tokio::test]
+    async fn webhooks_over_the_rate_limit_get_429() {
+        let app = Router::new()
+            .route("/webhooks/test", post(|| async { StatusCode::NO_CONTENT }))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::new(RateLimiter::new(2, Duration::from_secs(60))),
+                limit_webhook_rate,
+            ));
+        let deliver = |ip: [u8; 4]| {
+            let mut request = Request::post("/webhooks/test").body(Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 443))));
+            app.clone().oneshot(request)
+        };
+
+        assert_eq!(
+            deliver([10, 0, 0, 1]).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            deliver([10, 0, 0, 1]).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            deliver([10, 0, 0, 1]).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            deliver([10, 0, 0, 2]).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_get_413() {
+        let fake = FakeDokploy::default();
+        let state = test_state(Config {
+            dokploy_url: fake.serve().await,
+            ..test_config()
+        });
+        let limit = state.config.max_body_bytes;
+        let app = build_router(&state);
+        let deliver = |path: &str, body: String| {
+            let request = Request::post(path)
+                .header("x-api-key", "dokploy-key")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        for path in ["/webhooks/github/pr", "/api/previews"] {
+            // Read, though it's then refused for other reasons
+            let fits = format!("\"{}\"", "a".repeat(limit - 2));
+            assert_ne!(
+                deliver(path, fits).await.unwrap().status(),
+                StatusCode::PAYLOAD_TOO_LARGE
+            );
+            let oversized = format!("\"{}\"", "a".repeat(limit));
+            assert_eq!(
+                deliver(path, oversized).await.unwrap().status(),
+                StatusCode::PAYLOAD_TOO_LARGE
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_resolve_under_the_base_path() {
+        let config = Config {
+            base_path: Some("/spinploy/".to_string()),
+            ..test_config()
+        };
+        assert!(config.validate().is_ok());
+        let app = Router::new()
+            .route("/healthz", get(healthz))
+            .nest(
+                "/api",
+                Router::new().route("/ping", get(|| async { "pong" })),
+            )
+            .with_state(test_state(config.clone()));
+        let app = nest_under_base_path(app, config.base_path());
+
+        for (uri, status) in [
+            ("/spinploy/healthz", StatusCode::OK),
+            ("/spinploy/api/ping", StatusCode::OK),
+            ("/healthz", StatusCode::NOT_FOUND),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{uri}");
+        }
+
+        let config = Config {
+            base_path: Some("spinploy".to_string()),
+            ..test_config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn basic_auth_key_prefers_password_then_username() {
+        let header = |creds: &str| format!("Basic {}", BASE64.encode(creds));
+
+        assert_eq!(
+            basic_auth_key(&header("secret:")).as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            basic_auth_key(&header(":secret")).as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            basic_auth_key(&header("user:secret")).as_deref(),
+            Some("secret")
+        );
+        assert_eq!(basic_auth_key(&header("secret")).as_deref(), Some("secret"));
+        assert_eq!(basic_auth_key(&header(":")), None);
+        assert_eq!(basic_auth_key("Bearer token"), None);
+    }
+
+    #[tokio::test]
+    async fn in_flight_counts_running_requests() {
+        let state = test_state(test_config());
+        let observed = state.in_flight.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move || async move { observed.load(Ordering::SeqCst).to_string() }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                track_in_flight,
+            ))
+            .with_state(state.clone());
+
+        let response = app
+            .oneshot(Request::builder().uri("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"1");
+        assert_eq!(state.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn upsert_reports_the_triggered_deploy_as_building() {
+        let fake = FakeDokploy::default();
+
+        let created = upsert_against(&fake).await;
+        let updated = upsert_against(&fake).await;
+
+        assert_eq!(created.status, PreviewStatus::Building);
+        assert_eq!(updated.status, PreviewStatus::Building);
+        let json = serde_json::to_value(&created).unwrap();
+        assert_eq!(json["status"], "Building");
+    }
+
+    #[tokio::test]
+    async fn repeated_upserts_do_not_duplicate_domains() {
+        let fake = FakeDokploy::default();
+
+        let first = upsert_against(&fake).await;
+        let second = upsert_against(&fake).await;
+
+        assert_eq!(fake.composes.lock().unwrap().len(), 1);
+        assert_eq!(fake.domain_hosts().len(), 2);
+        assert_eq!(second.compose_id, first.compose_id);
+        assert_eq!(second.domains, first.domains);
+    }
+
+    #[tokio::test]
+    async fn upsert_adds_domains_missing_after_a_partial_create() {
+        let fake = FakeDokploy::with_composes(&["br-feature-foo"]);
+        fake.domains.lock().unwrap().push(Domain {
+            domain_id: "domain-1".to_string(),
+            host: "br-feature-foo.preview.example.com".to_string(),
+            service_name: "web".to_string(),
+            compose_id: "compose-br-feature-foo".to_string(),
+        });
+
+        let resp = upsert_against(&fake).await;
+
+        assert_eq!(
+            fake.domain_hosts(),
+            vec![
+                "br-feature-foo.preview.example.com",
+                "api-br-feature-foo.preview.example.com",
+            ]
+        );
+        assert_eq!(resp.domains, fake.domain_hosts());
+    }
+
+    #[test]
+    fn payload_key_ignores_envelope() {
+        let body = |id: &str, status: &str| {
+            serde_json::json!({ "id": id, "resource": { "status": status } }).to_string()
+        };
+        let key = |body: String| azure_payload_key("updated", 7, "refs/heads/a", body.as_bytes());
+
+        assert_eq!(key(body("evt-1", "active")), key(body("evt-2", "active")));
+        assert_ne!(
+            key(body("evt-1", "active")),
+            key(body("evt-1", "completed"))
+        );
+    }
+
+    fn github_pr_event(action: &str, base: &str, merged: bool) -> GithubPullRequestEvent {
+        serde_json::from_value(serde_json::json!({
+            "action": action,
+            "number": 7,
+            "pull_request": {
+                "head": { "ref": "feature/foo" },
+                "base": { "ref": base },
+                "merged": merged,
+            },
+            "repository": { "full_name": "acme/app", "default_branch": "main" },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn github_webhooks_record_their_sender_in_the_audit_log() {
+        let fake = FakeDokploy::default();
+        let path = std::env::temp_dir().join(format!("spinploy-audit-{}.log", std::process::id()));
+        let state = AppState {
+            audit_log: AuditLog::open(Some(&path)).await.unwrap(),
+            ..test_state(Config {
+                dokploy_url: fake.serve().await,
+                ..test_config()
+            })
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "pull_request".parse().unwrap());
+        let payload = serde_json::json!({
+            "action": "opened",
+            "number": 7,
+            "pull_request": { "head": { "ref": "feature/foo" }, "base": { "ref": "main" } },
+            "repository": { "full_name": "acme/app" },
+            "sender": { "login": "octocat" },
+        });
+
+        let result = github_pr_webhook(
+            State(state),
+            ApiKey("key".to_string()),
+            headers,
+            Json(payload),
+        )
+        .await;
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let event: spinploy::audit::AuditEvent = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(event.actor, "github:octocat");
+        assert_eq!(event.identifier, "pr-7");
+        assert_eq!(event.action, AuditAction::Deploy);
+        assert_eq!(event.outcome, spinploy::audit::AuditOutcome::Success);
+    }
+
+    #[tokio::test]
+    async fn github_commands_from_users_not_allowed_are_declined() {
+        let fake = FakeDokploy::with_composes(&["pr-7"]);
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            allowed_command_users: vec!["release-manager".to_string()],
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/github/pr", post(github_pr_webhook))
+            .with_state(test_state(config));
+        let body = serde_json::json!({
+            "action": "created",
+            "issue": { "number": 7, "pull_request": {} },
+            "comment": { "body": "/delete" },
+            "repository": { "full_name": "acme/app" },
+            "sender": { "login": "octocat" },
+        });
+        let request = Request::post("/webhooks/github/pr")
+            .header("x-api-key", "dokploy-key")
+            .header("x-github-event", "issue_comment")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        let resp = app.oneshot(request).await.unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert!(body["message"].as_str().unwrap().contains("octocat"));
+        assert_eq!(fake.composes.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn merged_github_prs_delete_their_staging_deployment() {
+        let fake = FakeDokploy::default();
+        for (name, environment_id) in [("pr-7", "env"), ("staging-pr-7", "staging")] {
+            fake.composes.lock().unwrap().push(Compose {
+                compose_id: format!("compose-{name}"),
+                name: name.to_string(),
+                app_name: format!("preview-{name}-abc123"),
+                environment_id: environment_id.to_string(),
+                domains: vec![],
+                created_at: None,
+                description: None,
+            });
+        }
+        let state = test_state(Config {
+            dokploy_url: fake.serve().await,
+            staging_environment_id: Some("staging".to_string()),
+            ..test_config()
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "pull_request".parse().unwrap());
+        let payload = serde_json::json!({
+            "action": "closed",
+            "number": 7,
+            "pull_request": {
+                "head": { "ref": "feature/foo" },
+                "base": { "ref": "main" },
+                "merged": true,
+            },
+            "repository": { "full_name": "acme/app", "default_branch": "main" },
+        });
+
+        let result = github_pr_webhook(
+            State(state),
+            ApiKey("key".to_string()),
+            headers,
+            Json(payload),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert!(fake.composes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn github_pr_opened_or_pushed_upserts() {
+        assert_eq!(
+            github_pr_action(&github_pr_event("opened", "main", false)),
+            Some(GithubPrAction::Upsert)
+        );
+        assert_eq!(
+            github_pr_action(&github_pr_event("synchronize", "main", false)),
+            Some(GithubPrAction::Upsert)
+        );
+    }
+
+    #[test]
+    fn github_pr_merged_into_default_branch_deletes() {
+        assert_eq!(
+            github_pr_action(&github_pr_event("closed", "main", true)),
+            Some(GithubPrAction::Delete)
+        );
+        assert_eq!(
+            github_pr_action(&github_pr_event("closed", "release", true)),
+            None
+        );
+        assert_eq!(
+            github_pr_action(&github_pr_event("closed", "main", false)),
+            None
+        );
+        assert_eq!(
+            github_pr_action(&github_pr_event("labeled", "main", false)),
+            None
+        );
+    }
+
+    fn gitlab_mr_event(action: &str, target_branch: &str) -> GitlabMergeRequestEvent {
+        serde_json::from_value(serde_json::json!({
+            "object_kind": "merge_request",
+            "event_type": "merge_request",
+            "user": { "username": "jdoe" },
+            "project": {
+                "id": 15,
+                "path_with_namespace": "acme/webapp",
+                "default_branch": "main"
+            },
+            "object_attributes": {
+                "id": 99,
+                "iid": 42,
+                "action": action,
+                "source_branch": "feature/login",
+                "target_branch": target_branch,
+                "state": "opened",
+                "title": "Add login"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn gitlab_mr_open_reopen_update_upserts() {
+        for action in ["open", "reopen", "update"] {
+            assert_eq!(
+                gitlab_mr_action(&gitlab_mr_event(action, "main")),
+                Some(GitlabMrAction::Upsert)
+            );
+        }
+        assert_eq!(gitlab_mr_action(&gitlab_mr_event("approved", "main")), None);
+    }
+
+    #[test]
+    fn gitlab_mr_merged_into_default_branch_deletes() {
+        assert_eq!(
+            gitlab_mr_action(&gitlab_mr_event("merge", "main")),
+            Some(GitlabMrAction::Delete)
+        );
+        assert_eq!(gitlab_mr_action(&gitlab_mr_event("merge", "release")), None);
+    }
+
+    fn bitbucket_pr_event(event_key: &str, target_branch: &str) -> BitbucketPullRequestEvent {
+        serde_json::from_value(serde_json::json!({
+            "eventKey": event_key,
+            "date": "2024-01-01T10:00:00+0000",
+            "actor": { "name": "jdoe" },
+            "pullRequest": {
+                "id": 12,
+                "title": "Add login",
+                "state": "OPEN",
+                "fromRef": {
+                    "id": "refs/heads/feature/login",
+                    "displayId": "feature/login",
+                    "repository": { "slug": "webapp", "project": { "key": "ACME" } }
+                },
+                "toRef": {
+                    "id": format!("refs/heads/{}", target_branch),
+                    "displayId": target_branch,
+                    "repository": { "slug": "webapp", "project": { "key": "ACME" } }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn bitbucket_pr_actions() {
+        let config = test_config();
+        let action = |event_key, target| {
+            bitbucket_pr_action(&config, &bitbucket_pr_event(event_key, target))
+        };
+
+        assert_eq!(action("pr:opened", "main"), Some(BitbucketPrAction::Upsert));
+        assert_eq!(action("pr:merged", "main"), Some(BitbucketPrAction::Delete));
+        assert_eq!(action("pr:merged", "release"), None);
+        assert_eq!(
+            action("pr:declined", "release"),
+            Some(BitbucketPrAction::Delete)
+        );
+        assert_eq!(action("pr:comment:added", "main"), None);
+    }
+
+    #[tokio::test]
+    async fn bitbucket_webhook_rejects_bad_signatures() {
+        let fake = FakeDokploy::default();
+        let config = Config {
+            dokploy_url: fake.serve().await,
+            bitbucket_webhook_secret: Some("s3cret".to_string()),
+            ..test_config()
+        };
+        let app = Router::new()
+            .route("/webhooks/bitbucket/pr", post(bitbucket_pr_webhook))
+            .with_state(test_state(config));
+        let body = r#"{ "eventKey": "diagnostics:ping" }"#;
+        let request = |signature: &str| {
+            Request::post("/webhooks/bitbucket/pr")
+                .header("x-api-key", "dokploy-key")
+                .header(BITBUCKET_SIGNATURE_HEADER, signature)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let resp = app.clone().oneshot(request("sha256=00")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+        mac.update(body.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        let resp = app
+            .oneshot(request(&format!("sha256={signature}")))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[test]
+    fn gitlab_mr_identifier_uses_iid() {
+        let event = gitlab_mr_event("open", "main");
+        let pr_id = Some(event.object_attributes.iid.to_string());
+
+        assert_eq!(
+            spinploy::compute_identifier(&pr_id, &event.object_attributes.source_branch),
+            "pr-42"
+        );
+    }
+
+    #[test]
+    fn plans_frontend_and_backend_domains() {
+        let planned =
+            plan_preview_domains(&test_config(), &test_config().preview_target(), "pr-42").unwrap();
+        let hosts: Vec<_> = planned.iter().map(|d| d.host.as_str()).collect();
+
+        assert_eq!(
+            hosts,
+            ["pr-42.preview.example.com", "api-pr-42.preview.example.com"]
+        );
+    }
+
+    #[test]
+    fn plans_a_domain_per_extra_service() {
+        let mut json = test_config_json();
+        json["services"] =
+            r#"[{"name": "worker", "port": 9000}, {"name": "admin", "port": 4000, "subdomain_prefix": "adm-"}]"#
+                .into();
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        let planned = plan_preview_domains(&config, &config.preview_target(), "pr-42").unwrap();
+        let planned: Vec<_> = planned
+            .iter()
+            .map(|d| (d.service_name.as_str(), d.host.as_str(), d.port))
+            .collect();
+
+        assert_eq!(
+            planned,
+            [
+                ("web", "pr-42.preview.example.com", 3000),
+                ("api", "api-pr-42.preview.example.com", 8080),
+                ("worker", "worker-pr-42.preview.example.com", 9000),
+                ("admin", "adm-pr-42.preview.example.com", 4000),
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_domains_use_the_configured_certificate_type() {
+        let request = |config: &Config| {
+            let planned = plan_preview_domains(config, &config.preview_target(), "pr-42").unwrap();
+            planned
+                .into_iter()
+                .next()
+                .unwrap()
+                .into_request("compose-1")
+        };
+        assert_eq!(request(&test_config()).certificate_type, "none");
+
+        let mut json = test_config_json();
+        json["certificate_type"] = "letsencrypt".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+        let request = request(&config);
+        assert_eq!(request.certificate_type, "letsencrypt");
+        assert!(request.https);
+    }
+
+    #[test]
+    fn subdomain_templates_shape_preview_hosts() {
+        let config = Config {
+            frontend_subdomain_template: "{identifier}-web.{base_domain}".to_string(),
+            backend_subdomain_template: "backend.{identifier}.{base_domain}".to_string(),
+            ..test_config()
+        };
+
+        assert_eq!(
+            config.preview_hosts("pr-42"),
+            (
+                "pr-42-web.preview.example.com".to_string(),
+                "backend.pr-42.preview.example.com".to_string()
+            )
+        );
+        let hosts: Vec<_> = plan_preview_domains(&config, &config.preview_target(), "pr-42")
+            .unwrap()
+            .into_iter()
+            .map(|d| d.host)
+            .collect();
+        assert_eq!(
+            hosts,
+            [
+                "pr-42-web.preview.example.com",
+                "backend.pr-42.preview.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn domain_plan_over_cap_fails_before_creation() {
+        let config = Config {
+            max_domains_per_preview: 1,
+            ..test_config()
+        };
+
+        let (status, message) =
+            plan_preview_domains(&config, &config.preview_target(), "pr-42").unwrap_err();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(message.contains("MAX_DOMAINS_PER_PREVIEW"));
+    }
+
+    fn preview_result(deployment_status: Option<&str>) -> CommandResult {
+        CommandResult {
+            frontend_url: Some("https://pr-42.preview.example.com".to_string()),
+            backend_url: Some("https://api-pr-42.preview.example.com".to_string()),
+            deployment_status: deployment_status.map(str::to_string),
+            ..CommandResult::succeeded(
+                SlashCommand::Preview,
+                "👷 Preview building, should be available soon",
+            )
+        }
+    }
+
+    #[test]
+    fn preview_reply_lists_urls_and_status() {
+        let message = command_reply(&test_config(), &preview_result(Some("running")));
+
+        assert_eq!(
+            message,
+            "👷 Preview building, should be available soon:\n\n\
+             - 🖥️ Frontend: https://pr-42.preview.example.com\n\
+             - 🔌 Backend: https://api-pr-42.preview.example.com\n\
+             - 📦 Deployment status: running\n\n\
+             💻 View the status of all previews here: https://spinploy.example.com"
+        );
+    }
+
+    #[test]
+    fn finished_deploys_edit_the_preview_reply() {
+        let started = preview_result(Some("running"));
+
+        let message = command_reply(
+            &test_config(),
+            &finished_command_result(started.clone(), PreviewStatus::Running),
+        );
+        assert!(message.starts_with("✅ Preview deployed:\n\n"), "{message}");
+        assert!(message.contains("- 🖥️ Frontend: https://pr-42.preview.example.com"));
+        assert!(message.contains("- 📦 Deployment status: done"));
+
+        let failed = finished_command_result(started, PreviewStatus::Failed);
+        let message = command_reply(&test_config(), &failed);
+        assert!(
+            message.starts_with("❌ Preview deploy failed:"),
+            "{message}"
+        );
+        assert!(message.contains("- 📦 Deployment status: error"));
+        assert!(!failed.success);
+    }
+
+    #[test]
+    fn preview_reply_omits_unknown_status() {
+        let message = command_reply(&test_config(), &preview_result(None));
+
+        assert!(!message.contains("Deployment status"));
+    }
+
+    #[test]
+    fn failed_command_reply_is_just_the_message() {
+        let result = CommandResult::failed(SlashCommand::Preview, "❌ Preview deploy failed: boom");
+
+        assert_eq!(
+            command_reply(&test_config(), &result),
+            "❌ Preview deploy failed: boom"
+        );
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["command"], "preview");
+        assert_eq!(json["success"], false);
+    }
+
+    #[test]
+    fn errored_command_reply_points_at_the_logs_instead_of_the_error() {
+        let request_id = RequestId {
+            id: "req-123".to_string(),
+            span: tracing::Span::none(),
+        };
+        let error = "POST https://dokploy.internal/api/compose.deploy: 500";
+
+        let result = CommandResult::errored(
+            SlashCommand::Preview,
+            "Preview deploy failed",
+            error,
+            Some(&request_id),
+        );
+
+        assert!(!result.success);
+        assert_eq!(
+            result.message,
+            "❌ Preview deploy failed. Details are in the spinploy logs under request id `req-123`"
+        );
+        let without_id = CommandResult::errored(
+            SlashCommand::Delete,
+            "Failed to delete preview",
+            error,
+            None,
+        );
+        assert!(!without_id.message.contains("dokploy.internal"));
+    }
+
+    #[test]
+    fn failed_refresh_replies_say_whether_the_preview_is_gone() {
+        let replies = [
+            (
+                RefreshError::Delete(StatusCode::BAD_GATEWAY, "compose.delete: 502".to_string()),
+                "❌ Deleting the preview failed, it is unchanged. \
+                 Details are in the spinploy logs",
+            ),
+            (
+                RefreshError::Recreate(StatusCode::BAD_GATEWAY, "compose.create: 502".to_string()),
+                "❌ Preview was deleted but recreating it failed, comment /preview to try again. \
+                 Details are in the spinploy logs",
+            ),
+        ];
+        for (error, reply) in replies {
+            let what = error.reply();
+            let (_, error) = error.into_error();
+            let result = CommandResult::errored(SlashCommand::Refresh, what, error, None);
+            assert_eq!(result.message, reply);
+        }
+    }
+
+    #[test]
+    fn preview_list_message_notes_truncation() {
+        let summary = |identifier: &str, frontend_url: Option<&str>| PreviewSummary {
+            identifier: identifier.to_string(),
+            compose_id: format!("compose-{identifier}"),
+            pr_id: None,
+            pr_title: None,
+            pr_author: None,
+            branch: identifier.to_string(),
+            status: PreviewStatus::Running,
+            created_at: None,
+            last_deployed_at: None,
+            frontend_url: frontend_url.map(str::to_string),
+            backend_url: None,
+            pr_url: None,
+            containers: vec![],
+        };
+        let previews = vec![
+            summary("pr-1", Some("https://pr-1.preview.example.com")),
+            summary("pr-2", None),
+        ];
+
+        assert_eq!(
+            preview_list_message(&previews, 2),
+            "📋 Active previews (2):\n\n- `pr-1`: https://pr-1.preview.example.com\n- `pr-2`"
+        );
+        assert!(preview_list_message(&previews, 5).ends_with("\n\n…and 3 more"));
+        assert_eq!(preview_list_message(&[], 0), "📋 No active previews");
+    }
+
+    #[test]
+    fn promote_reply_links_the_staging_frontend() {
+        let result = CommandResult {
+            frontend_url: Some("https://staging-pr-42.staging.example.com".to_string()),
+            ..CommandResult::succeeded(
+                SlashCommand::Promote,
+                "🚀 Promoted `feature/foo` to staging",
+            )
+        };
+
+        assert_eq!(
+            command_reply(&test_config(), &result),
+            "🚀 Promoted `feature/foo` to staging:\n\n\
+             - 🖥️ Frontend: https://staging-pr-42.staging.example.com"
+        );
+    }
+
+    #[test]
+    fn detects_abandoned_pr_update() {
+        let payload: AzurePrUpdatedEvent = serde_json::from_value(serde_json::json!({
+            "id": "2ab4e3d3-b7a6-425e-92b1-5a9982c1269e",
+            "eventType": "git.pullrequest.updated",
+            "message": { "text": "Jamal Hartnett abandoned pull request 1 (Dependency update)" },
+            "resource": {
+                "pullRequestId": 1,
+                "status": "abandoned",
+                "sourceRefName": "refs/heads/feature/deps",
+                "targetRefName": "refs/heads/main",
+            },
+        }))
+        .unwrap();
+
+        assert!(has_pr_status(&payload.resource, "abandoned"));
+        assert!(!has_pr_status(&payload.resource, "completed"));
+    }
+
+    #[tokio::test]
+    async fn status_tracker_reports_only_changes() {
+        let tracker = StatusTracker::new();
+
+        assert_eq!(tracker.record("pr-1", PreviewStatus::Building).await, None);
+        assert_eq!(tracker.record("pr-1", PreviewStatus::Building).await, None);
+        assert_eq!(
+            tracker.record("pr-1", PreviewStatus::Failed).await,
+            Some(StatusTransition {
+                identifier: "pr-1".to_string(),
+                from: PreviewStatus::Building,
+                to: PreviewStatus::Failed,
+            })
+        );
+        assert_eq!(tracker.record("pr-2", PreviewStatus::Failed).await, None);
+    }
+
+    fn config_with_api_keys(passthrough: bool) -> Config {
+        Config {
+            dokploy_api_key: Some("dokploy-key".to_string()),
+            api_keys: vec!["old-key".to_string(), "new-key".to_string()],
+            api_key_passthrough: passthrough,
+            ..test_config()
+        }
+    }
+
+    #[test]
+    fn any_configured_management_key_is_accepted() {
+        let config = config_with_api_keys(false);
+        for key in ["old-key", "new-key"] {
+            assert_eq!(
+                check_api_key(&config, key),
+                KeyCheck::Management("dokploy-key".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected_unless_passthrough_is_enabled() {
+        assert_eq!(
+            check_api_key(&config_with_api_keys(false), "new-key-2"),
+            KeyCheck::Rejected
+        );
+        assert_eq!(
+            check_api_key(&config_with_api_keys(true), "some-dokploy-key"),
+            KeyCheck::Passthrough
+        );
+    }
+
+    #[test]
+    fn keys_pass_through_without_management_keys() {
+        assert_eq!(
+            check_api_key(&test_config(), "some-dokploy-key"),
+            KeyCheck::Passthrough
+        );
+    }
+
+    #[test]
+    fn repository_git_source_overrides_the_global_one() {
+        let mut json = test_config_json();
+        json["repositories"] = serde_json::json!({
+            "webapp": {
+                "custom_git_url": "ssh://git@example.com/webapp.git",
+                "custom_git_ssh_key_id": "webapp-key"
+            }
+        });
+        let config: Config = serde_json::from_value(json).unwrap();
+        let repository = AzureRepositoryRef {
+            id: "5c7a9e1e-0000-4000-8000-000000000000".to_string(),
+            name: Some("webapp".to_string()),
+        };
+
+        let resolved = config.for_repository(&azure_repository_keys(Some(&repository)));
+        assert_eq!(
+            resolved.compose_source("feature/foo"),
+            spinploy::ComposeSource::Git {
+                custom_git_url: "ssh://git@example.com/webapp.git".to_string(),
+                custom_git_branch: "feature/foo".to_string(),
+                custom_git_ssh_key_id: "webapp-key".to_string(),
+                compose_path: config.compose_path.clone(),
+            }
+        );
+
+        // Repositories without overrides, or payloads without one, use the global source
+        let other = AzureRepositoryRef {
+            id: "other-id".to_string(),
+            name: Some("other".to_string()),
+        };
+        for resolved in [
+            config.for_repository(&azure_repository_keys(Some(&other))),
+            config.for_repository(&azure_repository_keys(None)),
+        ] {
+            assert_eq!(resolved.custom_git_url, config.custom_git_url);
+            assert_eq!(resolved.custom_git_ssh_key_id, config.custom_git_ssh_key_id);
+        }
+    }
+
+    fn prune_entry(name: &str, finished_at: Option<&str>) -> (Compose, Option<ComposeDetail>) {
+        let compose: Compose = serde_json::from_value(serde_json::json!({
+            "composeId": format!("id-{name}"),
+            "name": name,
+            "appName": format!("preview-{name}-abc123"),
+            "environmentId": "env",
+        }))
+        .unwrap();
+        let detail: ComposeDetail = serde_json::from_value(serde_json::json!({
+            "composeId": format!("id-{name}"),
+            "deployments": finished_at
+                .map(|ts| vec![serde_json::json!({ "deploymentId": "d", "finishedAt": ts })])
+                .unwrap_or_default(),
+        }))
+        .unwrap();
+        (compose, Some(detail))
+    }
+
+    #[test]
+    fn pruned_pr_id_only_for_pr_previews() {
+        assert_eq!(pruned_pr_id("pr-42"), Some(42));
+        assert_eq!(pruned_pr_id("br-feature-x"), None);
+        assert_eq!(pruned_pr_id("pr-abc"), None);
+        assert_eq!(pruned_pr_id("staging-pr-4"), None);
+        assert!(pruned_preview_message("pr-42").contains("/preview"));
+    }
+
+    #[tokio::test]
+    async fn prune_comments_only_on_prs_of_the_azure_repository() {
+        type Threads = Arc<std::sync::Mutex<Vec<String>>>;
+        async fn create_thread(State(threads): State<Threads>, uri: axum::http::Uri) {
+            threads.lock().unwrap().push(uri.path().to_string());
+        }
+        let threads = Threads::default();
+        let app = Router::new()
+            .route(
+                "/org/project/_apis/git/repositories/{repo}/pullRequests/{pr}/threads",
+                post(create_thread),
+            )
+            .with_state(threads.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let azdo_base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let state = test_state(Config {
+            azdo_base_url,
+            prune_pr_comments: true,
+            ..test_config()
+        });
+        let pruned = |identifier: &str, source: Option<PreviewSource>| PruneCandidate {
+            identifier: identifier.to_string(),
+            compose_id: format!("compose-{}", identifier),
+            description: source.as_ref().map(PreviewSource::description),
+            last_activity_at: None,
+            protected: false,
+            would_delete: true,
+        };
+        let pruned = [
+            pruned("pr-1", Some(PreviewSource::Azure("repo".to_string()))),
+            pruned("pr-2", Some(PreviewSource::Azure("other".to_string()))),
+            pruned("pr-3", Some(PreviewSource::GitHub("repo".to_string()))),
+            pruned("pr-4", None),
+        ];
+
+        comment_on_pruned_prs(&state.azure_client, &state.config, &pruned).await;
+
+        assert_eq!(
+            *threads.lock().unwrap(),
+            ["/org/project/_apis/git/repositories/repo/pullRequests/1/threads"]
+        );
+    }
+
+    #[tokio::test]
+    async fn push_redeploys_reuse_one_thread_per_pr() {
+        #[derive(Clone, Default)]
+        struct Threads {
+            created: Arc<std::sync::Mutex<Vec<String>>>,
+            edited: Arc<std::sync::Mutex<Vec<String>>>,
+        }
+        async fn create_thread(
+            State(threads): State<Threads>,
+            Path((_, pr)): Path<(String, u64)>,
+        ) -> Json<serde_json::Value> {
+            threads.created.lock().unwrap().push(format!("pr {pr}"));
+            Json(serde_json::json!({ "id": pr * 10, "comments": [{ "id": 1 }] }))
+        }
+        async fn edit_comment(
+            State(threads): State<Threads>,
+            Path((_, pr, thread, comment)): Path<(String, u64, u64, u64)>,
+        ) -> Json<serde_json::Value> {
+            let edit = format!("pr {pr} thread {thread} comment {comment}");
+            threads.edited.lock().unwrap().push(edit);
+            Json(serde_json::json!({ "id": comment }))
+        }
+        let threads = Threads::default();
+        let prs = "/org/project/_apis/git/repositories/{repo}/pullRequests/{pr}";
+        let app = Router::new()
+            .route(&format!("{prs}/threads"), post(create_thread))
+            .route(
+                &format!("{prs}/threads/{{thread}}/comments/{{comment}}"),
+                axum::routing::patch(edit_comment),
+            )
+            .with_state(threads.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let azdo_base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let state = test_state(Config {
+            azdo_base_url,
+            ..test_config()
+        });
+        let announce = |pr_id: u64| {
+            let identifier = format!("pr-{pr_id}");
+            let state = state.clone();
+            async move {
+                let (azure_client, coordinator) = (&state.azure_client, &state.coordinator);
+                let config = &state.config;
+                announce_redeploy(
+                    azure_client,
+                    coordinator,
+                    config,
+                    "repo",
+                    pr_id,
+                    &identifier,
+                )
+                .await;
+            }
+        };
+
+        announce(7).await;
+        announce(7).await;
+        announce(8).await;
+        announce(7).await;
+
+        assert_eq!(*threads.created.lock().unwrap(), ["pr 7", "pr 8"]);
+        assert_eq!(
+            *threads.edited.lock().unwrap(),
+            ["pr 7 thread 70 comment 1", "pr 7 thread 70 comment 1"]
+        );
+    }
+
+    #[test]
+    fn prune_plan_deletes_oldest_over_limit() {
+        let plan = build_prune_plan(
+            &test_config(),
+            vec![
+                prune_entry("pr-3", Some("2024-05-03T00:00:00Z")),
+                prune_entry("pr-1", Some("2024-05-01T00:00:00Z")),
+                prune_entry("pr-2", Some("2024-05-02T00:00:00Z")),
+            ],
+            1,
+            3,
+        );
+
+        let order: Vec<_> = plan
+            .candidates
+            .iter()
+            .map(|c| (c.identifier.as_str(), c.would_delete))
+            .collect();
+        assert_eq!(order, [("pr-1", true), ("pr-2", false), ("pr-3", false)]);
+    }
+
+    #[test]
+    fn prune_plan_skips_protected_previews() {
+        let config = Config {
+            prune_protect: vec!["pr-1".to_string(), "br-release-*".to_string()],
+            ..test_config()
+        };
+        let plan = build_prune_plan(
+            &config,
+            vec![
+                prune_entry("pr-1", Some("2024-05-01T00:00:00Z")),
+                prune_entry("br-release-2024", Some("2024-05-02T00:00:00Z")),
+                prune_entry("pr-3", Some("2024-05-03T00:00:00Z")),
+                prune_entry("pr-4", Some("2024-05-04T00:00:00Z")),
+            ],
+            1,
+            3,
+        );
+
+        let deleted: Vec<_> = plan
+            .candidates
+            .iter()
+            .filter(|c| c.would_delete)
+            .map(|c| c.identifier.as_str())
+            .collect();
+        // Protected previews still count toward the limit
+        assert_eq!(deleted, ["pr-3", "pr-4"]);
+        assert!(plan.candidates[0].protected && plan.candidates[1].protected);
+    }
+
+    #[test]
+    fn prune_plan_within_limit_deletes_nothing() {
+        let plan = build_prune_plan(
+            &test_config(),
+            vec![
+                prune_entry("pr-1", Some("2024-05-01T00:00:00Z")),
+                prune_entry("pr-2", None),
+            ],
+            0,
+            3,
+        );
+
+        assert!(plan.candidates.iter().all(|c| !c.would_delete));
+        // No deployment activity sorts first
+        assert_eq!(plan.candidates[0].identifier, "pr-2");
+    }
+
+    #[test]
+    fn preview_env_uses_identifier_domains() {
+        let env = build_preview_env(&test_config(), &test_config().preview_target(), "pr-42");
+
+        assert!(env.starts_with(
+            "APP_URL=https://pr-42.preview.example.com\nBACKEND_API_URL=https://api-pr-42.preview.example.com\n"
+        ));
+        assert!(env.contains("EMAIL_ENVIRONMENT_PREFIX=\"[pr-42] \""));
+        assert!(env.contains("STORAGE_TOKEN=${{project.STORAGE_TOKEN}}"));
+    }
+
+    #[test]
+    fn env_template_substitutes_placeholders() {
+        let config = Config {
+            env_template: "URL=https://{frontend_domain}\nAPI={backend_domain}\nBASE={base_domain}\nID={identifier}-{identifier}\n".to_string(),
+            ..test_config()
+        };
+
+        assert_eq!(
+            build_preview_env(&config, &config.preview_target(), "pr-42"),
+            "URL=https://pr-42.preview.example.com\nAPI=api-pr-42.preview.example.com\nBASE=preview.example.com\nID=pr-42-pr-42\n"
+        );
+    }
+
+    #[test]
+    fn extra_env_is_appended_after_the_template_in_order() {
+        let mut json = test_config_json();
+        json["env_template"] = "APP_URL=https://{frontend_domain}".into();
+        json["extra_env"] = "FEATURE_X=on\n# comment\n\nAPI_KEY=abc=123\n".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            build_preview_env(&config, &config.preview_target(), "pr-42"),
+            "APP_URL=https://pr-42.preview.example.com\nFEATURE_X=on\nAPI_KEY=abc=123\n"
+        );
+
+        let mut json = test_config_json();
+        json["extra_env"] = "NOT A PAIR".into();
+        assert!(serde_json::from_value::<Config>(json).is_err());
+    }
+
+    #[test]
+    fn basic_auth_users_are_written_to_the_env_as_is() {
+        let mut json = test_config_json();
+        json["env_template"] = "APP_URL=https://{frontend_domain}".into();
+        json["extra_env"] = "FEATURE_X=on".into();
+        json["preview_basic_auth"] = "qa:$apr1$H6uskkkW$IgXLP6ewTrSuBkTrqE8wj/".into();
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            build_preview_env(&config, &config.preview_target(), "pr-42"),
+            "APP_URL=https://pr-42.preview.example.com\n\
+             PREVIEW_BASIC_AUTH='qa:$apr1$H6uskkkW$IgXLP6ewTrSuBkTrqE8wj/'\n\
+             FEATURE_X=on\n"
+        );
+    }
+
+    #[test]
+    fn env_template_leaves_unknown_placeholders_alone() {
+        let rendered = render_env_template(
+            "A={identifier}\nB={unknown}\nC=${{project.SECRET}}",
+            &[("identifier", "pr-1")],
+        );
+
+        assert_eq!(rendered, "A=pr-1\nB={unknown}\nC=${{project.SECRET}}");
+    }
 
     fn timeline_record(name: &str, result: Option<&str>) -> AzureTimelineRecord {
         AzureTimelineRecord {
@@ -1,7 +1,10 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
 pub struct AzurePrCommentEvent {
+    /// Event id; redeliveries of the same event reuse it
+    #[serde(default)]
+    pub id: Option<String>,
     #[serde(rename = "eventType")]
     pub event_type: String,
     pub resource: AzureResource,
@@ -17,14 +20,46 @@ pub struct AzureResource {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AzureComment {
+    /// Comment id, unique within its thread
+    #[serde(default)]
+    pub id: Option<u64>,
     #[serde(default)]
     pub content: Option<String>,
     #[serde(default)]
     pub is_deleted: bool,
+    /// Who wrote the comment (`author` in comment events, `createdBy` in some payloads)
+    #[serde(default, alias = "createdBy")]
+    pub author: Option<AzureIdentityRef>,
     #[serde(rename = "_links")]
     pub links: AzureCommentLinks,
 }
 
+/// A PR thread comment as the REST API returns it when creating or updating one
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureThreadComment {
+    /// Comment id, unique within its thread
+    pub id: u64,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+/// A PR thread as the REST API returns it when creating one
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureThread {
+    pub id: u64,
+    #[serde(default)]
+    pub comments: Vec<AzureThreadComment>,
+}
+
+/// Where a posted comment is, to edit it later
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AzureCommentRef {
+    pub thread_id: u64,
+    pub comment_id: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AzureCommentLinks {
     #[serde(rename = "self")]
@@ -44,11 +79,25 @@ pub struct AzureHref {
 pub struct AzurePullRequest {
     pub pull_request_id: u64,
     pub source_ref_name: String,
+    #[serde(default)]
+    pub is_draft: bool,
+    #[serde(default)]
+    pub repository: Option<AzureRepositoryRef>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AzureRepositoryRef {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 // Azure DevOps git.pullrequest.updated (PushNotification filtered) minimal payload
 #[derive(Debug, Deserialize)]
 pub struct AzurePrUpdatedEvent {
+    /// Event id; redeliveries of the same event reuse it
+    #[serde(default)]
+    pub id: Option<String>,
     #[serde(rename = "eventType")]
     pub event_type: String,
     pub resource: AzurePrUpdatedResource,
@@ -63,6 +112,43 @@ pub struct AzurePrUpdatedResource {
     pub target_ref_name: Option<String>,
     #[serde(default)]
     pub status: Option<String>,
+    #[serde(default)]
+    pub is_draft: bool,
+    #[serde(default)]
+    pub merge_status: MergeStatus,
+    #[serde(default)]
+    pub repository: Option<AzureRepositoryRef>,
+}
+
+/// Outcome of Azure's trial merge of a PR into its target, matched case-insensitively.
+/// An explicit `null` is `NotSet`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "Option<String>")]
+pub enum MergeStatus {
+    Succeeded,
+    Conflicts,
+    Failure,
+    Queued,
+    #[default]
+    NotSet,
+    /// A status not listed here, e.g. `rejectedByPolicy`
+    Other(String),
+}
+
+impl From<Option<String>> for MergeStatus {
+    fn from(status: Option<String>) -> Self {
+        let Some(status) = status else {
+            return MergeStatus::NotSet;
+        };
+        match status.to_ascii_lowercase().as_str() {
+            "succeeded" => MergeStatus::Succeeded,
+            "conflicts" => MergeStatus::Conflicts,
+            "failure" => MergeStatus::Failure,
+            "queued" => MergeStatus::Queued,
+            "notset" => MergeStatus::NotSet,
+            _ => MergeStatus::Other(status),
+        }
+    }
 }
 
 // Azure DevOps build.completed webhook payload
@@ -165,8 +251,157 @@ pub struct AzureBuildListItem {
     pub id: u64,
 }
 
+// Azure DevOps REST: pull request list
+#[derive(Debug, Deserialize)]
+pub struct AzurePullRequestListResponse {
+    #[serde(default)]
+    pub value: Vec<AzurePullRequestListItem>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzurePullRequestListItem {
+    pub pull_request_id: u64,
+    pub source_ref_name: String,
+    #[serde(default)]
+    pub target_ref_name: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// `active`, `completed` or `abandoned`
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub is_draft: bool,
+}
+
 // Azure DevOps REST: pull request detail
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct AzurePullRequestDetail {
     pub title: String,
+    pub created_by: Option<AzureIdentityRef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzureIdentityRef {
+    pub display_name: String,
+    /// Usually the user's email (`DOMAIN\user` on Azure DevOps Server)
+    #[serde(default)]
+    pub unique_name: Option<String>,
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+impl AzureIdentityRef {
+    /// Unique name when known, display name otherwise, for logs
+    pub fn login(&self) -> &str {
+        self.unique_name.as_deref().unwrap_or(&self.display_name)
+    }
+}
+
+// Azure DevOps REST: pull request status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AzurePrStatusState {
+    Pending,
+    Succeeded,
+    Failed,
+    /// The outcome couldn't be determined
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AzurePrStatusRequest {
+    pub state: AzurePrStatusState,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_url: Option<String>,
+    pub context: AzurePrStatusContext,
+}
+
+/// Statuses with the same genre/name replace each other in the PR UI
+#[derive(Debug, Serialize)]
+pub struct AzurePrStatusContext {
+    pub genre: String,
+    pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_status_parses_known_values_in_any_case() {
+        for (json, expected) in [
+            ("succeeded", MergeStatus::Succeeded),
+            ("conflicts", MergeStatus::Conflicts),
+            ("failure", MergeStatus::Failure),
+            ("queued", MergeStatus::Queued),
+            ("notSet", MergeStatus::NotSet),
+            ("Succeeded", MergeStatus::Succeeded),
+            ("CONFLICTS", MergeStatus::Conflicts),
+            (
+                "rejectedByPolicy",
+                MergeStatus::Other("rejectedByPolicy".to_string()),
+            ),
+        ] {
+            let parsed: MergeStatus = serde_json::from_value(json.into()).unwrap();
+            assert_eq!(parsed, expected, "{json}");
+        }
+    }
+
+    #[test]
+    fn merge_status_defaults_to_not_set() {
+        let resource: AzurePrUpdatedResource = serde_json::from_value(serde_json::json!({
+            "pullRequestId": 7,
+            "sourceRefName": "refs/heads/feature/foo",
+        }))
+        .unwrap();
+
+        assert_eq!(resource.merge_status, MergeStatus::NotSet);
+    }
+
+    #[test]
+    fn null_merge_status_is_not_set() {
+        let resource: AzurePrUpdatedResource = serde_json::from_value(serde_json::json!({
+            "pullRequestId": 7,
+            "sourceRefName": "refs/heads/feature/foo",
+            "mergeStatus": null,
+        }))
+        .unwrap();
+
+        assert_eq!(resource.merge_status, MergeStatus::NotSet);
+    }
+
+    #[test]
+    fn parses_comment_author() {
+        let comment: AzureComment = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "content": "/preview",
+            "author": {
+                "displayName": "Jamal Hartnett",
+                "uniqueName": "fabrikamfiber4@hotmail.com",
+                "id": "54d125f7-69f7-4191-904f-c5b96b6261c8",
+            },
+            "_links": { "threads": { "href": "https://dev.azure.com/org/_apis/git/repositories/repo/pullRequests/1/threads/3" } },
+        }))
+        .unwrap();
+        let author = comment.author.unwrap();
+        assert_eq!(author.display_name, "Jamal Hartnett");
+        assert_eq!(
+            author.id.as_deref(),
+            Some("54d125f7-69f7-4191-904f-c5b96b6261c8")
+        );
+        assert_eq!(author.login(), "fabrikamfiber4@hotmail.com");
+
+        // Older payloads name it `createdBy`, sometimes without a unique name
+        let comment: AzureComment = serde_json::from_value(serde_json::json!({
+            "createdBy": { "displayName": "Normal Paulk" },
+            "_links": { "threads": { "href": "https://dev.azure.com/org/_apis/git/repositories/repo/pullRequests/1/threads/3" } },
+        }))
+        .unwrap();
+        assert_eq!(comment.author.unwrap().login(), "Normal Paulk");
+    }
 }
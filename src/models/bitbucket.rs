@@ -0,0 +1,41 @@
+use serde::Deserialize;
+
+// Bitbucket Server pull request webhook payload (minimal)
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketPullRequestEvent {
+    /// e.g. `pr:opened`, `pr:merged`, `pr:declined`
+    pub event_key: String,
+    pub pull_request: BitbucketPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketPullRequest {
+    pub id: u64,
+    /// `OPEN`, `MERGED` or `DECLINED`
+    pub state: String,
+    pub from_ref: BitbucketRef,
+    pub to_ref: BitbucketRef,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitbucketRef {
+    /// Branch name without `refs/heads/`
+    pub display_id: String,
+    #[serde(default)]
+    pub repository: Option<BitbucketRepository>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketRepository {
+    pub slug: String,
+    #[serde(default)]
+    pub project: Option<BitbucketProject>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BitbucketProject {
+    pub key: String,
+}
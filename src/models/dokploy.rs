@@ -31,6 +31,9 @@ pub struct Compose {
     pub domains: Vec<Domain>,
     #[serde(default)]
     pub created_at: Option<String>,
+    /// Where spinploy records the preview's source repository; see `PreviewSource`
+    #[serde(default)]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,13 +45,50 @@ pub struct Domain {
     pub compose_id: String,
 }
 
+/// How Dokploy runs a compose: plain `docker compose` or a Swarm `stack`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ComposeType {
+    #[default]
+    DockerCompose,
+    Stack,
+}
+
+/// Where Dokploy reads a compose file from, as configured in `SOURCE_TYPE`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceType {
+    #[default]
+    Git,
+    Raw,
+}
+
+/// A compose's source in `compose.update`: a git repository checked out at a branch,
+/// or the compose file itself stored in Dokploy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "sourceType", rename_all = "lowercase")]
+pub enum ComposeSource {
+    #[serde(rename_all = "camelCase")]
+    Git {
+        custom_git_url: String,
+        custom_git_branch: String,
+        #[serde(rename = "customGitSSHKeyId")]
+        custom_git_ssh_key_id: String,
+        compose_path: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Raw { compose_file: String },
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateComposeRequest {
     pub name: String,
     pub environment_id: String,
-    pub compose_type: String,
+    pub compose_type: ComposeType,
     pub app_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,18 +105,31 @@ pub struct UpdateComposeRequest {
     pub name: String,
     pub app_name: String,
     pub env: String,
-    pub source_type: String,
-    pub compose_type: String,
-    pub custom_git_url: String,
-    pub custom_git_branch: String,
-    #[serde(rename = "customGitSSHKeyId")]
-    pub custom_git_ssh_key_id: String,
-    pub compose_path: String,
+    #[serde(flatten)]
+    pub source: ComposeSource,
+    pub compose_type: ComposeType,
     pub environment_id: String,
     pub auto_deploy: bool,
     pub isolated_deployment: bool,
 }
 
+/// `compose.update` replacing only a compose's source.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateComposeSourceRequest {
+    pub compose_id: String,
+    #[serde(flatten)]
+    pub source: ComposeSource,
+}
+
+/// `compose.update` replacing only a compose's description.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateComposeDescriptionRequest {
+    pub compose_id: String,
+    pub description: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DomainCreateRequest {
@@ -112,12 +165,97 @@ pub struct Deployment {
     pub log_path: Option<String>,
 }
 
+impl Deployment {
+    /// Whether Dokploy is done with this deployment, successfully or not
+    pub fn is_finished(&self) -> bool {
+        match self.status.as_deref().map(str::to_lowercase).as_deref() {
+            Some("done" | "error" | "cancelled") => true,
+            Some(_) => false,
+            None => self.finished_at.is_some(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ComposeDetail {
     pub compose_id: String,
     #[serde(default)]
     pub created_at: Option<String>,
+    /// Raw `KEY=value` lines configured on the compose
+    #[serde(default)]
+    pub env: Option<String>,
+    /// Git branch a git-sourced compose deploys
+    #[serde(default)]
+    pub custom_git_branch: Option<String>,
     #[serde(default)]
     pub deployments: Vec<Deployment>,
 }
+
+impl ComposeDetail {
+    /// Latest deployment by timestamp (Dokploy doesn't guarantee order)
+    pub fn latest_deployment(&self) -> Option<&Deployment> {
+        self.deployments.iter().max_by_key(|d| {
+            d.finished_at
+                .as_ref()
+                .or(d.started_at.as_ref())
+                .or(d.created_at.as_ref())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compose_type_uses_dokploy_names() {
+        assert_eq!(
+            serde_json::to_string(&ComposeType::DockerCompose).unwrap(),
+            "\"docker-compose\""
+        );
+        assert_eq!(
+            serde_json::from_str::<ComposeType>("\"stack\"").unwrap(),
+            ComposeType::Stack
+        );
+        assert!(serde_json::from_str::<ComposeType>("\"swarm\"").is_err());
+    }
+
+    #[test]
+    fn raw_source_serializes_inline_compose_file() {
+        let req = UpdateComposeRequest {
+            compose_id: "compose-1".to_string(),
+            name: "pr-7".to_string(),
+            app_name: "preview-pr-7".to_string(),
+            env: String::new(),
+            source: ComposeSource::Raw {
+                compose_file: "services:\n  web:\n    image: nginx\n".to_string(),
+            },
+            compose_type: ComposeType::DockerCompose,
+            environment_id: "env".to_string(),
+            auto_deploy: false,
+            isolated_deployment: true,
+        };
+
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json["sourceType"], "raw");
+        assert_eq!(json["composeFile"], "services:\n  web:\n    image: nginx\n");
+        assert!(json.get("customGitUrl").is_none());
+        assert!(json.get("composePath").is_none());
+    }
+
+    #[test]
+    fn git_source_keeps_dokploy_field_names() {
+        let source = ComposeSource::Git {
+            custom_git_url: "ssh://git@example.com/repo.git".to_string(),
+            custom_git_branch: "feature/foo".to_string(),
+            custom_git_ssh_key_id: "key".to_string(),
+            compose_path: "docker-compose.yml".to_string(),
+        };
+
+        let json = serde_json::to_value(&source).unwrap();
+        assert_eq!(json["sourceType"], "git");
+        assert_eq!(json["customGitSSHKeyId"], "key");
+        assert_eq!(json["composePath"], "docker-compose.yml");
+    }
+}
@@ -0,0 +1,64 @@
+use serde::Deserialize;
+
+// GitHub `pull_request` webhook payload (minimal)
+#[derive(Debug, Deserialize)]
+pub struct GithubPullRequestEvent {
+    pub action: String,
+    pub number: u64,
+    pub pull_request: GithubPullRequest,
+    pub repository: GithubRepository,
+    #[serde(default)]
+    pub sender: Option<GithubUser>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubPullRequest {
+    pub head: GithubRef,
+    pub base: GithubRef,
+    #[serde(default)]
+    pub merged: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubRef {
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubRepository {
+    pub full_name: String,
+    #[serde(default)]
+    pub default_branch: Option<String>,
+}
+
+// GitHub `issue_comment` webhook payload (minimal); PR comments arrive as issue comments
+#[derive(Debug, Deserialize)]
+pub struct GithubIssueCommentEvent {
+    pub action: String,
+    pub issue: GithubIssue,
+    pub comment: GithubComment,
+    pub repository: GithubRepository,
+    #[serde(default)]
+    pub sender: Option<GithubUser>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubIssue {
+    pub number: u64,
+    /// Present only when the issue is a pull request
+    #[serde(default)]
+    pub pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GithubComment {
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+/// The account that triggered a webhook
+#[derive(Debug, Deserialize)]
+pub struct GithubUser {
+    pub login: String,
+}
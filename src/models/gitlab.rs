@@ -0,0 +1,28 @@
+use serde::Deserialize;
+
+// GitLab Merge Request Hook payload (minimal)
+#[derive(Debug, Deserialize)]
+pub struct GitlabMergeRequestEvent {
+    pub object_kind: String,
+    pub project: GitlabProject,
+    pub object_attributes: GitlabMergeRequestAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GitlabProject {
+    pub path_with_namespace: String,
+    #[serde(default)]
+    pub default_branch: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GitlabMergeRequestAttributes {
+    /// Project-scoped MR number, as shown in the UI (`!42`)
+    pub iid: u64,
+    /// Missing on some system-generated events
+    #[serde(default)]
+    pub action: Option<String>,
+    pub source_branch: String,
+    pub target_branch: String,
+    pub state: String,
+}
@@ -1,2 +1,5 @@
 pub mod azure;
+pub mod bitbucket;
 pub mod dokploy;
+pub mod github;
+pub mod gitlab;
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before full (idle) ones are dropped, so spoofed or rotating sources
+/// can't grow the map without bound.
+const MAX_BUCKETS: usize = 1024;
+
+/// Token bucket rate limiter keyed by request source.
+///
+/// Each source may send `burst` requests at once, then gets one more every
+/// `per_request` (i.e. `burst` per `window`).
+pub struct RateLimiter {
+    burst: u32,
+    per_request: Duration,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// Allows `limit` requests per `window` and source. A `limit` of 0 disables limiting.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            burst: limit,
+            per_request: window / limit.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for `key`. Returns `false` if its bucket is empty.
+    pub fn try_acquire(&self, key: &str) -> bool {
+        if self.burst == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().expect("rate limiter mutex poisoned");
+
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < burst);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at);
+        let earned = elapsed.as_secs_f64() / self.per_request.as_secs_f64();
+        (bucket.tokens + earned).min(f64::from(self.burst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_requests_over_the_limit_per_source() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+
+        assert!(limiter.try_acquire("10.0.0.1"));
+        assert!(limiter.try_acquire("10.0.0.1"));
+        assert!(limiter.try_acquire("10.0.0.1"));
+        assert!(!limiter.try_acquire("10.0.0.1"));
+        // Other sources have their own bucket
+        assert!(limiter.try_acquire("10.0.0.2"));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(20));
+
+        assert!(limiter.try_acquire("a"));
+        assert!(!limiter.try_acquire("a"));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(limiter.try_acquire("a"));
+    }
+
+    #[test]
+    fn zero_limit_disables_limiting() {
+        let limiter = RateLimiter::new(0, Duration::from_secs(60));
+
+        assert!((0..100).all(|_| limiter.try_acquire("a")));
+    }
+}
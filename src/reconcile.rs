@@ -0,0 +1,335 @@
+//! One-shot reconcile of PR previews against the open Azure DevOps pull requests, for
+//! running from cron to catch drift left by missed webhooks.
+
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result, bail};
+use spinploy::audit::{AuditAction, AuditLog};
+use spinploy::azure_client::AzureDevOpsClient;
+use spinploy::config::PreviewSource;
+use spinploy::models::azure::AzurePullRequestListItem;
+use spinploy::telemetry;
+use spinploy::{Compose, Config, DokployClient};
+
+/// Audit log actor for changes made by a reconcile pass
+const RECONCILE_ACTOR: &str = "reconcile";
+
+/// What one reconcile pass should change.
+#[derive(Debug, Default)]
+pub(crate) struct ReconcilePlan {
+    /// Open PRs missing a preview, newest first: (PR id, source branch)
+    pub create: Vec<(String, String)>,
+    /// Open PRs missing a preview that don't fit under the preview limit
+    pub skipped: Vec<String>,
+    /// PR previews of `AZDO_REPOSITORY_ID`, and staging deployments promoted from them,
+    /// whose PR is no longer open
+    pub delete: Vec<Compose>,
+    /// `pr-*` previews without a recorded source that aren't an open PR's, e.g. created
+    /// before sources were recorded and not deployed since; left for an operator
+    pub unrecorded: Vec<String>,
+}
+
+/// Creates previews for open PRs that lack one and deletes `pr-*` previews whose PR
+/// is gone, along with the `staging-pr-*` deployments in `staged` promoted from them.
+/// Only composes recorded as created for a PR of `AZDO_REPOSITORY_ID` are
+/// deleted: other providers and repositories have their own `pr-<n>` previews, and
+/// previews without a recorded source can't be told apart from them, so they're only
+/// reported. Branch previews and `PRUNE_PROTECT` matches are left alone, and drafts get
+/// no preview with `SKIP_DRAFT_PREVIEWS`. Creations are
+/// capped so the result stays within `limit`, since creating past it would prune
+/// previews of other open PRs and the next pass would just recreate them.
+pub(crate) fn plan_reconcile(
+    config: &Config,
+    open_prs: &[AzurePullRequestListItem],
+    previews: Vec<Compose>,
+    staged: Vec<Compose>,
+    limit: usize,
+) -> ReconcilePlan {
+    let open: BTreeSet<String> = open_prs
+        .iter()
+        .map(|pr| spinploy::compute_identifier(&Some(pr.pull_request_id.to_string()), ""))
+        .collect();
+    let is_other_pr = |c: &Compose, identifier: &str| {
+        identifier.starts_with("pr-")
+            && !open.contains(identifier)
+            && !config.is_prune_protected(&c.name)
+    };
+    let is_closed_pr = |c: &Compose, identifier: &str| {
+        is_other_pr(c, identifier) && config.is_azure_repository_preview(c.description.as_deref())
+    };
+
+    let (mut delete, kept): (Vec<Compose>, Vec<Compose>) =
+        previews.into_iter().partition(|c| is_closed_pr(c, &c.name));
+    delete.extend(staged.into_iter().filter(|c| {
+        c.name
+            .strip_prefix("staging-")
+            .is_some_and(|identifier| is_closed_pr(c, identifier))
+    }));
+    let unrecorded = kept
+        .iter()
+        .filter(|c| c.description.is_none() && is_other_pr(c, &c.name))
+        .map(|c| c.name.clone())
+        .collect();
+    let existing: BTreeSet<&str> = kept.iter().map(|c| c.name.as_str()).collect();
+
+    let mut missing: Vec<&AzurePullRequestListItem> = open_prs
+        .iter()
+        .filter(|pr| !(pr.is_draft && config.skip_draft_previews))
+        .filter(|pr| {
+            let identifier =
+                spinploy::compute_identifier(&Some(pr.pull_request_id.to_string()), "");
+            !existing.contains(identifier.as_str())
+        })
+        .collect();
+    missing.sort_by_key(|pr| std::cmp::Reverse(pr.pull_request_id));
+
+    let capacity = limit.saturating_sub(kept.len());
+    let skipped = missing
+        .iter()
+        .skip(capacity)
+        .map(|pr| pr.pull_request_id.to_string())
+        .collect();
+    let create = missing
+        .into_iter()
+        .take(capacity)
+        .map(|pr| {
+            (
+                pr.pull_request_id.to_string(),
+                spinploy::strip_refs_heads(&pr.source_ref_name),
+            )
+        })
+        .collect();
+
+    ReconcilePlan {
+        create,
+        skipped,
+        delete,
+        unrecorded,
+    }
+}
+
+/// Run one reconcile pass, logging what changed. Fails if any create or delete did.
+pub(crate) async fn run_once(
+    dokploy_client: &DokployClient,
+    azure_client: &AzureDevOpsClient,
+    audit_log: &AuditLog,
+    config: &Config,
+) -> Result<()> {
+    let api_key = config
+        .dokploy_api_key
+        .as_deref()
+        .context("DOKPLOY_API_KEY must be set to reconcile")?;
+    let audit = audit_log.actor(RECONCILE_ACTOR);
+    // Record the previews created here as this repository's, for later passes
+    let source = PreviewSource::Azure(config.azdo_repository_id.clone());
+
+    let open_prs = azure_client
+        .list_pull_requests(&config.azdo_repository_id, "active")
+        .await
+        .context("Failed to list open pull requests")?;
+    let previews = dokploy_client
+        .list_composes_with_prefix(api_key, &config.environment_id, &config.app_name_prefix)
+        .await
+        .context("Failed to list previews")?;
+    // Staging deployments sharing the previews' environment are listed with them already
+    let staged = match config.staging_target() {
+        Some(staging) if staging.environment_id != config.environment_id => dokploy_client
+            .list_composes_with_prefix(api_key, &staging.environment_id, &config.app_name_prefix)
+            .await
+            .context("Failed to list staging deployments")?,
+        _ => vec![],
+    };
+
+    let plan = plan_reconcile(config, &open_prs, previews, staged, crate::PREVIEW_LIMIT);
+    tracing::info!(
+        open_prs = open_prs.len(),
+        to_create = plan.create.len(),
+        to_delete = plan.delete.len(),
+        "Reconciling previews"
+    );
+    if !plan.skipped.is_empty() {
+        tracing::warn!(
+            skipped = ?plan.skipped,
+            "Open PRs without a preview skipped: preview limit reached"
+        );
+    }
+    if !plan.unrecorded.is_empty() {
+        tracing::warn!(
+            unrecorded = ?plan.unrecorded,
+            "PR previews without a recorded source left alone; delete them if their PR is closed"
+        );
+    }
+
+    let mut created = Vec::new();
+    let mut deleted = Vec::new();
+    let mut failed = 0;
+
+    for compose in plan.delete {
+        let result = dokploy_client
+            .delete_compose(api_key, &compose.compose_id, true)
+            .await;
+        audit
+            .record(&compose.name, AuditAction::Delete, &result)
+            .await;
+        match result {
+            Ok(()) => {
+                telemetry::record_delete();
+                deleted.push(compose.name);
+            }
+            Err(e) => {
+                tracing::error!(identifier = compose.name, error = %e, "Failed to delete stale preview");
+                failed += 1;
+            }
+        }
+    }
+
+    for (pr_id, branch) in plan.create {
+        let pr_id = Some(pr_id);
+        let identifier = spinploy::compute_identifier(&pr_id, &branch);
+        match crate::upsert_preview_internal(
+            dokploy_client,
+            config,
+            api_key,
+            &audit,
+            &branch,
+            &pr_id,
+            Some(&source),
+        )
+        .await
+        {
+            Ok((_, pruned)) => {
+                // Posted before returning: this process exits once the pass is done
+                crate::comment_on_pruned_prs(azure_client, config, &pruned).await;
+                created.push(identifier);
+            }
+            Err((_, e)) => {
+                tracing::error!(identifier, error = %e, "Failed to create missing preview");
+                failed += 1;
+            }
+        }
+    }
+
+    tracing::info!(?created, ?deleted, failed, "Reconcile finished");
+    if failed > 0 {
+        bail!("{} reconcile operation(s) failed", failed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr(id: u64) -> AzurePullRequestListItem {
+        AzurePullRequestListItem {
+            pull_request_id: id,
+            source_ref_name: format!("refs/heads/feature/{id}"),
+            target_ref_name: Some("refs/heads/main".to_string()),
+            title: None,
+            status: Some("active".to_string()),
+            is_draft: false,
+        }
+    }
+
+    /// A preview created for a PR of the configured repository
+    fn preview(name: &str) -> Compose {
+        Compose {
+            compose_id: format!("compose-{name}"),
+            name: name.to_string(),
+            app_name: format!("preview-{name}-abc123"),
+            environment_id: "env".to_string(),
+            domains: vec![],
+            created_at: None,
+            description: Some(PreviewSource::Azure(config().azdo_repository_id).description()),
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            prune_protect: vec!["pr-4".to_string()],
+            ..crate::test_support::test_config()
+        }
+    }
+
+    fn names(composes: &[Compose]) -> Vec<&str> {
+        composes.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn deletes_closed_pr_previews_and_creates_missing_ones() {
+        let previews = ["pr-1", "pr-3", "pr-4", "br-main"].map(preview).to_vec();
+
+        let plan = plan_reconcile(&config(), &[pr(1), pr(2), pr(5)], previews, vec![], 10);
+
+        // pr-4 is protected and br-main isn't tied to a PR
+        assert_eq!(names(&plan.delete), ["pr-3"]);
+        assert_eq!(
+            plan.create,
+            [
+                ("5".to_string(), "feature/5".to_string()),
+                ("2".to_string(), "feature/2".to_string())
+            ]
+        );
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn deletes_staging_deployments_of_closed_prs() {
+        let staged = ["staging-pr-1", "staging-pr-3", "staging-br-main"]
+            .map(preview)
+            .to_vec();
+
+        let plan = plan_reconcile(&config(), &[pr(1)], vec![preview("pr-1")], staged, 10);
+
+        assert_eq!(names(&plan.delete), ["staging-pr-3"]);
+        assert!(plan.create.is_empty());
+    }
+
+    #[test]
+    fn only_deletes_previews_of_the_configured_repository() {
+        let from = |source: Option<PreviewSource>| Compose {
+            description: source.map(|source| source.description()),
+            ..preview("pr-7")
+        };
+        let previews = vec![
+            from(Some(PreviewSource::GitHub("acme/webapp".to_string()))),
+            from(Some(PreviewSource::Azure("other-repo".to_string()))),
+            from(None),
+        ];
+
+        let plan = plan_reconcile(&config(), &[pr(1)], previews, vec![], 10);
+
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.unrecorded, ["pr-7"]);
+        // The PR's own preview is still missing, whoever else uses its name
+        assert_eq!(plan.create, [("1".to_string(), "feature/1".to_string())]);
+    }
+
+    #[test]
+    fn skips_drafts_when_draft_previews_are_off() {
+        let draft = AzurePullRequestListItem {
+            is_draft: true,
+            ..pr(2)
+        };
+        let config = Config {
+            skip_draft_previews: true,
+            ..config()
+        };
+
+        let plan = plan_reconcile(&config, &[pr(1), draft], vec![], vec![], 10);
+
+        assert_eq!(plan.create, [("1".to_string(), "feature/1".to_string())]);
+        assert!(plan.skipped.is_empty());
+    }
+
+    #[test]
+    fn creations_stop_at_the_preview_limit() {
+        let previews = ["pr-1", "br-main"].map(preview).to_vec();
+
+        let plan = plan_reconcile(&config(), &[pr(1), pr(2), pr(5)], previews, vec![], 3);
+
+        assert_eq!(plan.create, [("5".to_string(), "feature/5".to_string())]);
+        assert_eq!(plan.skipped, ["2"]);
+        assert!(plan.delete.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use std::future::Future;
+use std::time::Duration;
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+/// Attempts made per request, the first one included
+pub const MAX_ATTEMPTS: u32 = 4;
+
+/// Wait before the first retry of a 5xx; doubled for every further one
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest wait between attempts, also for a server asking for a longer `Retry-After`
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Sends `request`, retrying responses that say the server is overloaded: 429 after
+/// its `Retry-After` (or the backoff, without one) and 5xx with exponential backoff.
+/// Gives up after `MAX_ATTEMPTS` and returns the last response, so callers handle its
+/// status as usual. Connection errors aren't retried.
+pub async fn send_with_retry(request: RequestBuilder) -> reqwest::Result<Response> {
+    let mut delay = BASE_DELAY;
+    let mut attempt = 1;
+    loop {
+        // The last attempt, and bodies that can't be cloned (streams), go out as is
+        let Some(next) = request.try_clone().filter(|_| attempt < MAX_ATTEMPTS) else {
+            return request.send().await;
+        };
+        let response = next.send().await?;
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+            return Ok(response);
+        }
+
+        let wait = retry_after(&response).unwrap_or(delay).min(MAX_DELAY);
+        tracing::debug!(
+            url = %response.url(),
+            %status,
+            attempt,
+            wait_ms = wait.as_millis() as u64,
+            "Retrying request"
+        );
+        tokio::time::sleep(wait).await;
+        delay = (delay * 2).min(MAX_DELAY);
+        attempt += 1;
+    }
+}
+
+/// [`send_with_retry`] as a method, to end a request chain with
+pub trait SendWithRetry {
+    fn send_with_retry(self) -> impl Future<Output = reqwest::Result<Response>> + Send;
+}
+
+impl SendWithRetry for RequestBuilder {
+    fn send_with_retry(self) -> impl Future<Output = reqwest::Result<Response>> + Send {
+        send_with_retry(self)
+    }
+}
+
+/// Wait a 429 asks for in whole seconds; the HTTP-date form isn't supported.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let secs = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    secs.trim().parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Serves `/` answering the first `failures` requests with `status` (and
+    /// `retry_after`), then 200. Returns its URL and the request count.
+    async fn flaky_server(
+        failures: u32,
+        status: StatusCode,
+        retry_after: Option<&'static str>,
+    ) -> (String, Arc<AtomicU32>) {
+        use axum::response::IntoResponse;
+
+        let requests = Arc::new(AtomicU32::new(0));
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let requests = requests.clone();
+                move || async move {
+                    if requests.fetch_add(1, Ordering::SeqCst) >= failures {
+                        return "ok".into_response();
+                    }
+                    match retry_after {
+                        Some(secs) => (status, [(RETRY_AFTER, secs)]).into_response(),
+                        None => status.into_response(),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/"), requests)
+    }
+
+    #[tokio::test]
+    async fn retries_after_too_many_requests() {
+        let (url, requests) = flaky_server(2, StatusCode::TOO_MANY_REQUESTS, Some("0")).await;
+
+        let resp = send_with_retry(reqwest::Client::new().post(&url))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn returns_the_last_response_after_max_attempts() {
+        let (url, requests) =
+            flaky_server(u32::MAX, StatusCode::TOO_MANY_REQUESTS, Some("0")).await;
+
+        let resp = send_with_retry(reqwest::Client::new().post(&url))
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_but_not_client_errors() {
+        let (url, requests) = flaky_server(1, StatusCode::SERVICE_UNAVAILABLE, None).await;
+        let resp = send_with_retry(reqwest::Client::new().post(&url))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (url, requests) = flaky_server(1, StatusCode::NOT_FOUND, None).await;
+        let resp = send_with_retry(reqwest::Client::new().post(&url))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
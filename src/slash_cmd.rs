@@ -1,9 +1,19 @@
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use anyhow::Context;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SlashCommand {
     Preview,
     Delete,
+    /// Deploy the PR's branch to the staging environment too
+    Promote,
+    /// Reply with all active previews
+    List,
+    /// Delete the preview and create it from scratch
+    Refresh,
 }
 
 impl FromStr for SlashCommand {
@@ -13,33 +23,166 @@ impl FromStr for SlashCommand {
         match s.trim().to_ascii_lowercase().as_str() {
             "/preview" => Ok(SlashCommand::Preview),
             "/delete" => Ok(SlashCommand::Delete),
+            "/promote" => Ok(SlashCommand::Promote),
+            "/list" => Ok(SlashCommand::List),
+            "/refresh" => Ok(SlashCommand::Refresh),
             _ => Err(anyhow::anyhow!("Invalid slash command: {}", s)),
         }
     }
 }
 
+/// A slash command with its arguments, e.g. `/preview --compose-path infra/docker-compose.yml`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlashCommandLine {
+    pub command: SlashCommand,
+    /// Compose file to create the preview from instead of the configured one
+    pub compose_path: Option<String>,
+}
+
+impl FromStr for SlashCommandLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let command: SlashCommand = args.next().unwrap_or_default().parse()?;
+        let mut compose_path = None;
+        while let Some(arg) = args.next() {
+            let value = match arg.split_once('=') {
+                Some(("--compose-path", value)) => Some(value),
+                None if arg == "--compose-path" => args.next(),
+                _ => anyhow::bail!("Unknown argument for {:?}: {}", command, arg),
+            };
+            let value = value.filter(|v| !v.is_empty());
+            compose_path = Some(value.context("--compose-path needs a path")?.to_string());
+        }
+        if compose_path.is_some()
+            && !matches!(command, SlashCommand::Preview | SlashCommand::Refresh)
+        {
+            anyhow::bail!("--compose-path only applies to /preview and /refresh");
+        }
+        Ok(SlashCommandLine {
+            command,
+            compose_path,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn parse_preview_command() {
-        assert_eq!(SlashCommand::from_str("/preview").unwrap(), SlashCommand::Preview);
-        assert_eq!(SlashCommand::from_str("/PREVIEW").unwrap(), SlashCommand::Preview);
+        assert_eq!(
+            SlashCommand::from_str("/preview").unwrap(),
+            SlashCommand::Preview
+        );
+        assert_eq!(
+            SlashCommand::from_str("/PREVIEW").unwrap(),
+            SlashCommand::Preview
+        );
     }
 
     #[test]
     fn parse_delete_command() {
-        assert_eq!(SlashCommand::from_str("/delete").unwrap(), SlashCommand::Delete);
-        assert_eq!(SlashCommand::from_str("/DELETE").unwrap(), SlashCommand::Delete);
+        assert_eq!(
+            SlashCommand::from_str("/delete").unwrap(),
+            SlashCommand::Delete
+        );
+        assert_eq!(
+            SlashCommand::from_str("/DELETE").unwrap(),
+            SlashCommand::Delete
+        );
+    }
+
+    #[test]
+    fn parse_promote_command() {
+        assert_eq!(
+            SlashCommand::from_str("/promote").unwrap(),
+            SlashCommand::Promote
+        );
+        assert_eq!(
+            SlashCommand::from_str("/Promote\n").unwrap(),
+            SlashCommand::Promote
+        );
+    }
+
+    #[test]
+    fn parse_list_command() {
+        assert_eq!(SlashCommand::from_str("/list").unwrap(), SlashCommand::List);
+        assert_eq!(
+            SlashCommand::from_str(" /LIST ").unwrap(),
+            SlashCommand::List
+        );
+    }
+
+    #[test]
+    fn parse_refresh_command() {
+        assert_eq!(
+            SlashCommand::from_str("/refresh").unwrap(),
+            SlashCommand::Refresh
+        );
+        assert_eq!(
+            SlashCommand::from_str("/Refresh\n").unwrap(),
+            SlashCommand::Refresh
+        );
     }
 
     #[test]
     fn parse_command_with_whitespace() {
-        assert_eq!(SlashCommand::from_str("/preview\n").unwrap(), SlashCommand::Preview);
-        assert_eq!(SlashCommand::from_str("/preview  ").unwrap(), SlashCommand::Preview);
-        assert_eq!(SlashCommand::from_str("  /preview").unwrap(), SlashCommand::Preview);
-        assert_eq!(SlashCommand::from_str("\n/delete\n").unwrap(), SlashCommand::Delete);
+        assert_eq!(
+            SlashCommand::from_str("/preview\n").unwrap(),
+            SlashCommand::Preview
+        );
+        assert_eq!(
+            SlashCommand::from_str("/preview  ").unwrap(),
+            SlashCommand::Preview
+        );
+        assert_eq!(
+            SlashCommand::from_str("  /preview").unwrap(),
+            SlashCommand::Preview
+        );
+        assert_eq!(
+            SlashCommand::from_str("\n/delete\n").unwrap(),
+            SlashCommand::Delete
+        );
+    }
+
+    #[test]
+    fn parse_compose_path_argument() {
+        let line: SlashCommandLine = "/preview --compose-path infra/Compose.yml".parse().unwrap();
+        assert_eq!(line.command, SlashCommand::Preview);
+        assert_eq!(line.compose_path.as_deref(), Some("infra/Compose.yml"));
+
+        let line: SlashCommandLine = " /REFRESH --compose-path=infra/compose.yml\n"
+            .parse()
+            .unwrap();
+        assert_eq!(line.command, SlashCommand::Refresh);
+        assert_eq!(line.compose_path.as_deref(), Some("infra/compose.yml"));
+
+        let line: SlashCommandLine = "/delete".parse().unwrap();
+        assert_eq!(line.compose_path, None);
+    }
+
+    #[test]
+    fn invalid_command_arguments() {
+        assert!(
+            "/preview --compose-path"
+                .parse::<SlashCommandLine>()
+                .is_err()
+        );
+        assert!(
+            "/preview --compose-path="
+                .parse::<SlashCommandLine>()
+                .is_err()
+        );
+        assert!("/preview --force".parse::<SlashCommandLine>().is_err());
+        assert!(
+            "/delete --compose-path x.yml"
+                .parse::<SlashCommandLine>()
+                .is_err()
+        );
+        assert!("".parse::<SlashCommandLine>().is_err());
     }
 
     #[test]
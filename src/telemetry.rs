@@ -0,0 +1,149 @@
+use std::time::Instant;
+
+use metrics::{counter, describe_counter, describe_histogram, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::coordinator::CoordinatorSnapshot;
+
+pub const PREVIEWS_DEPLOYED: &str = "spinploy_previews_deployed_total";
+pub const PREVIEWS_DELETED: &str = "spinploy_previews_deleted_total";
+pub const PREVIEWS_PRUNED: &str = "spinploy_previews_pruned_total";
+pub const WEBHOOK_EVENTS: &str = "spinploy_webhook_events_total";
+pub const WEBHOOKS_DEDUPED: &str = "spinploy_webhooks_deduped_total";
+pub const REDEPLOYS_COALESCED: &str = "spinploy_redeploys_coalesced_total";
+pub const LOG_STREAM_STALLS: &str = "spinploy_log_stream_stalls_total";
+pub const DOKPLOY_REQUEST_DURATION: &str = "spinploy_dokploy_request_duration_seconds";
+
+/// Buckets for Dokploy calls: mostly sub-second, but deploys and project listings can be slow
+const DOKPLOY_LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0];
+
+/// How a preview deploy was triggered.
+#[derive(Debug, Clone, Copy)]
+pub enum DeployKind {
+    Created,
+    Updated,
+    Redeployed,
+}
+
+impl DeployKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Redeployed => "redeployed",
+        }
+    }
+}
+
+/// Builds the Prometheus recorder with our buckets and metric descriptions.
+pub fn prometheus_builder() -> PrometheusBuilder {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(DOKPLOY_REQUEST_DURATION.to_string()),
+            &DOKPLOY_LATENCY_BUCKETS,
+        )
+        .expect("latency buckets are not empty")
+}
+
+/// Installs the global Prometheus recorder. Call once at startup; the handle renders `/metrics`.
+pub fn install_recorder() -> anyhow::Result<PrometheusHandle> {
+    let handle = prometheus_builder().install_recorder()?;
+    describe_metrics();
+    Ok(handle)
+}
+
+fn describe_metrics() {
+    describe_counter!(PREVIEWS_DEPLOYED, "Preview deploys triggered, by kind");
+    describe_counter!(
+        PREVIEWS_DELETED,
+        "Previews deleted on request or by webhook"
+    );
+    describe_counter!(
+        PREVIEWS_PRUNED,
+        "Previews deleted to stay under the preview limit"
+    );
+    describe_counter!(
+        WEBHOOK_EVENTS,
+        "Webhook deliveries, by route and response status"
+    );
+    describe_counter!(
+        WEBHOOKS_DEDUPED,
+        "Redelivered webhook events that were skipped"
+    );
+    describe_counter!(
+        REDEPLOYS_COALESCED,
+        "Push redeploys skipped as part of a burst"
+    );
+    describe_counter!(
+        LOG_STREAM_STALLS,
+        "Times a log stream waited on a client that fell a full buffer behind"
+    );
+    describe_histogram!(
+        DOKPLOY_REQUEST_DURATION,
+        metrics::Unit::Seconds,
+        "Dokploy API request latency, by endpoint and outcome"
+    );
+}
+
+pub fn record_deploy(kind: DeployKind) {
+    counter!(PREVIEWS_DEPLOYED, "kind" => kind.as_str()).increment(1);
+}
+
+pub fn record_delete() {
+    counter!(PREVIEWS_DELETED).increment(1);
+}
+
+pub fn record_prune() {
+    counter!(PREVIEWS_PRUNED).increment(1);
+}
+
+pub fn record_webhook(route: &str, status: u16) {
+    counter!(WEBHOOK_EVENTS, "route" => route.to_string(), "status" => status.to_string())
+        .increment(1);
+}
+
+/// Mirrors the coordinator's own dedupe/coalesce counters.
+pub fn record_coordinator(snapshot: &CoordinatorSnapshot) {
+    counter!(WEBHOOKS_DEDUPED).absolute(snapshot.deduped_total);
+    counter!(REDEPLOYS_COALESCED).absolute(snapshot.coalesced_total);
+}
+
+pub fn record_log_stream_stall() {
+    counter!(LOG_STREAM_STALLS).increment(1);
+}
+
+/// Records how long a Dokploy call to `endpoint` (e.g. `compose.one`) took since `started`.
+pub fn record_dokploy_request(endpoint: &str, started: Instant, ok: bool) {
+    // Drop query strings so ids don't blow up label cardinality
+    let endpoint = endpoint.split('?').next().unwrap_or(endpoint).to_string();
+    let outcome = if ok { "ok" } else { "error" };
+    histogram!(DOKPLOY_REQUEST_DURATION, "endpoint" => endpoint, "outcome" => outcome)
+        .record(started.elapsed().as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let recorder = prometheus_builder().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            record_deploy(DeployKind::Created);
+            record_deploy(DeployKind::Created);
+            record_webhook("/webhooks/github/pr", 204);
+            record_dokploy_request("compose.one?composeId=abc", Instant::now(), true);
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains(r#"spinploy_previews_deployed_total{kind="created"} 2"#));
+        assert!(rendered.contains(
+            r#"spinploy_webhook_events_total{route="/webhooks/github/pr",status="204"} 1"#
+        ));
+        assert!(rendered.contains(
+            r#"spinploy_dokploy_request_duration_seconds_count{endpoint="compose.one",outcome="ok"} 1"#
+        ));
+    }
+}
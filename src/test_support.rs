@@ -0,0 +1,314 @@
+//! Fixtures shared by the binary's tests: a valid configuration, an in-process fake
+//! of the Dokploy API and the app state handlers run against.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use spinploy::audit::{AuditLog, Auditor};
+use spinploy::azure_client::AzureDevOpsClient;
+use spinploy::coordinator::WebhookCoordinator;
+use spinploy::github_client::GitHubClient;
+use spinploy::models::dokploy::{Compose, Domain, DomainCreateRequest};
+use spinploy::slack_client::SlackWebhookClient;
+use spinploy::{Config, DokployClient, telemetry};
+
+use crate::deploy_watch::DeployWatcher;
+use crate::{AppState, AuthCache, PrDetailsCache, StatusTracker};
+
+pub(crate) fn test_config() -> Config {
+    serde_json::from_value(test_config_json()).unwrap()
+}
+
+pub(crate) fn test_config_json() -> serde_json::Value {
+    serde_json::json!({
+        "dokploy_url": "https://dokploy.example.com/api",
+        "project_id": "project",
+        "environment_id": "env",
+        "custom_git_url": "ssh://git@example.com/repo.git",
+        "custom_git_ssh_key_id": "ssh-key",
+        "compose_path": "./docker-compose.yml",
+        "base_domain": "preview.example.com",
+        "frontend_service_name": "web",
+        "frontend_port": 3000,
+        "backend_service_name": "api",
+        "backend_port": 8080,
+        "azdo_org": "org",
+        "azdo_project": "project",
+        "azdo_repository_id": "repo",
+        "azdo_pat": "pat",
+        "slack_webhook_url": "https://hooks.slack.com/services/x",
+        "deployed_preview_api_path": "https://spinploy.example.com",
+    })
+}
+
+/// In-process stand-in for the Dokploy API that records the composes and domains
+/// created and the deploys it receives.
+#[derive(Clone, Default)]
+pub(crate) struct FakeDokploy {
+    pub(crate) composes: Arc<std::sync::Mutex<Vec<Compose>>>,
+    pub(crate) domains: Arc<std::sync::Mutex<Vec<Domain>>>,
+    pub(crate) deploys: Arc<std::sync::Mutex<Vec<String>>>,
+    /// `customGitBranch` of every `compose.update` received
+    pub(crate) git_branches: Arc<std::sync::Mutex<Vec<String>>>,
+    /// `composePath` of every `compose.update` received
+    pub(crate) compose_paths: Arc<std::sync::Mutex<Vec<String>>>,
+    /// `env` of every `compose.update` received
+    pub(crate) envs: Arc<std::sync::Mutex<Vec<String>>>,
+    pub(crate) domain_lists: Arc<std::sync::atomic::AtomicUsize>,
+    pub(crate) detail_polls: Arc<std::sync::atomic::AtomicUsize>,
+    /// `compose.one` requests being answered now, and the most there ever were at once
+    pub(crate) details_in_flight: Arc<std::sync::atomic::AtomicUsize>,
+    pub(crate) max_details_in_flight: Arc<std::sync::atomic::AtomicUsize>,
+    /// Answer `domain.create` with an empty body, as some Dokploy versions do
+    pub(crate) empty_domain_responses: bool,
+    /// Report deploys as done once `compose.one` was polled more than this many times
+    pub(crate) finish_deploys_after_polls: Option<usize>,
+    /// Answer `compose.create` with this error after storing the compose, as if a
+    /// concurrent request created it first
+    pub(crate) create_error_after_storing: Option<StatusCode>,
+    /// Leave deploys out of `compose.one`, as if Dokploy's queue hadn't picked them up yet
+    pub(crate) queue_deploys: bool,
+    /// Hold every `compose.one` answer this long, so concurrent requests overlap
+    pub(crate) detail_delay: Option<Duration>,
+}
+
+impl FakeDokploy {
+    pub(crate) fn with_composes(names: &[&str]) -> Self {
+        let fake = Self::default();
+        fake.composes
+            .lock()
+            .unwrap()
+            .extend(names.iter().map(|name| Compose {
+                compose_id: format!("compose-{name}"),
+                name: name.to_string(),
+                app_name: format!("preview-{name}-abc123"),
+                environment_id: "env".to_string(),
+                domains: vec![],
+                created_at: None,
+                description: None,
+            }));
+        fake
+    }
+
+    pub(crate) fn deploys(&self) -> Vec<String> {
+        self.deploys.lock().unwrap().clone()
+    }
+
+    pub(crate) fn domain_hosts(&self) -> Vec<String> {
+        let domains = self.domains.lock().unwrap();
+        domains.iter().map(|d| d.host.clone()).collect()
+    }
+
+    /// Serves the fake on a local port and returns its API base URL.
+    pub(crate) async fn serve(&self) -> String {
+        async fn projects(State(fake): State<FakeDokploy>) -> Json<serde_json::Value> {
+            let composes = fake.composes.lock().unwrap().clone();
+            let mut environment_ids = vec!["env".to_string()];
+            for compose in &composes {
+                if !environment_ids.contains(&compose.environment_id) {
+                    environment_ids.push(compose.environment_id.clone());
+                }
+            }
+            let environments: Vec<_> = environment_ids
+                .iter()
+                .map(|environment_id| {
+                    let composes: Vec<_> = composes
+                        .iter()
+                        .filter(|c| &c.environment_id == environment_id)
+                        .collect();
+                    serde_json::json!({
+                        "environmentId": environment_id,
+                        "name": environment_id,
+                        "projectId": "project",
+                        "compose": composes,
+                    })
+                })
+                .collect();
+            Json(serde_json::json!([{
+                "projectId": "project",
+                "name": "project",
+                "organizationId": "org",
+                "environments": environments,
+            }]))
+        }
+
+        async fn create(
+            State(fake): State<FakeDokploy>,
+            Json(req): Json<spinploy::models::dokploy::CreateComposeRequest>,
+        ) -> Result<Json<Compose>, StatusCode> {
+            // Widen the window in which a concurrent upsert could miss this compose
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let compose = Compose {
+                compose_id: format!("compose-{}", req.name),
+                name: req.name,
+                app_name: req.app_name,
+                environment_id: req.environment_id,
+                domains: vec![],
+                created_at: None,
+                description: req.description,
+            };
+            fake.composes.lock().unwrap().push(compose.clone());
+            if let Some(error) = fake.create_error_after_storing {
+                return Err(error);
+            }
+            Ok(Json(compose))
+        }
+
+        async fn update(State(fake): State<FakeDokploy>, Json(req): Json<serde_json::Value>) {
+            if let Some(branch) = req["customGitBranch"].as_str() {
+                fake.git_branches.lock().unwrap().push(branch.to_string());
+            }
+            if let Some(path) = req["composePath"].as_str() {
+                fake.compose_paths.lock().unwrap().push(path.to_string());
+            }
+            if let Some(env) = req["env"].as_str() {
+                fake.envs.lock().unwrap().push(env.to_string());
+            }
+            if let Some(description) = req["description"].as_str() {
+                let mut composes = fake.composes.lock().unwrap();
+                for compose in composes.iter_mut() {
+                    if req["composeId"].as_str() == Some(&compose.compose_id) {
+                        compose.description = Some(description.to_string());
+                    }
+                }
+            }
+        }
+
+        async fn create_domain(
+            State(fake): State<FakeDokploy>,
+            Json(req): Json<DomainCreateRequest>,
+        ) -> String {
+            let domain = Domain {
+                domain_id: format!("domain-{}", req.host),
+                host: req.host,
+                service_name: req.service_name,
+                compose_id: req.compose_id,
+            };
+            fake.domains.lock().unwrap().push(domain.clone());
+            if fake.empty_domain_responses {
+                String::new()
+            } else {
+                serde_json::to_string(&domain).unwrap()
+            }
+        }
+
+        async fn list_domains(State(fake): State<FakeDokploy>) -> Json<Vec<Domain>> {
+            fake.domain_lists
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Json(fake.domains.lock().unwrap().clone())
+        }
+
+        // Every deploy received is reported as still running, unless it's time to finish
+        async fn compose_detail(
+            State(fake): State<FakeDokploy>,
+            Query(query): Query<HashMap<String, String>>,
+        ) -> Json<serde_json::Value> {
+            let polls = fake
+                .detail_polls
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                + 1;
+            let in_flight = fake
+                .details_in_flight
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+                + 1;
+            fake.max_details_in_flight
+                .fetch_max(in_flight, std::sync::atomic::Ordering::SeqCst);
+            if let Some(delay) = fake.detail_delay {
+                tokio::time::sleep(delay).await;
+            }
+            fake.details_in_flight
+                .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+
+            let status = match fake.finish_deploys_after_polls {
+                Some(limit) if polls > limit => "done",
+                _ => "running",
+            };
+            let compose_id = query.get("composeId").cloned().unwrap_or_default();
+            let deployments: Vec<_> = fake
+                .deploys()
+                .iter()
+                .filter(|id| **id == compose_id)
+                .enumerate()
+                .map(|(i, _)| {
+                    serde_json::json!({ "deploymentId": format!("deployment-{i}"), "status": status })
+                })
+                .collect();
+            Json(serde_json::json!({ "composeId": compose_id, "deployments": deployments }))
+        }
+
+        async fn delete(
+            State(fake): State<FakeDokploy>,
+            Json(req): Json<spinploy::models::dokploy::DeleteComposeRequest>,
+        ) {
+            let mut composes = fake.composes.lock().unwrap();
+            composes.retain(|c| c.compose_id != req.compose_id);
+        }
+
+        async fn deploy(
+            State(fake): State<FakeDokploy>,
+            Json(req): Json<spinploy::models::dokploy::ComposeDeployRequest>,
+        ) {
+            if !fake.queue_deploys {
+                fake.deploys.lock().unwrap().push(req.compose_id);
+            }
+        }
+
+        let app = Router::new()
+            .route("/api/project.all", get(projects))
+            .route("/api/compose.create", post(create))
+            .route("/api/compose.update", post(update))
+            .route("/api/compose.deploy", post(deploy))
+            .route("/api/compose.delete", post(delete))
+            .route("/api/compose.one", get(compose_detail))
+            .route("/api/domain.create", post(create_domain))
+            .route("/api/domain.byComposeId", get(list_domains))
+            .with_state(self.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/api")
+    }
+}
+
+pub(crate) fn test_state(config: Config) -> AppState {
+    AppState {
+        dokploy_client: Arc::new(DokployClient::new(
+            &config.dokploy_url,
+            config.http_timeouts(),
+        )),
+        azure_client: Arc::new(AzureDevOpsClient::new(
+            &config.azdo_base_url,
+            "org",
+            "project",
+            "pat",
+            config.http_timeouts(),
+        )),
+        github_client: Arc::new(GitHubClient::new(None::<String>, config.http_timeouts())),
+        docker_client: None,
+        audit_log: AuditLog::default(),
+        slack_client: Arc::new(SlackWebhookClient::new(&config.slack_webhook_url).unwrap()),
+        deploy_watcher: Arc::new(DeployWatcher::new(Duration::from_secs(60))),
+        auth_cache: Arc::new(AuthCache::new(60, 10, 16)),
+        pr_details_cache: Arc::new(PrDetailsCache::new(600, 16)),
+        coordinator: Arc::new(WebhookCoordinator::new(
+            Duration::from_secs(config.webhook_dedupe_ttl_secs),
+            Duration::from_secs(config.webhook_duplicate_window_secs),
+            Duration::from_secs(config.redeploy_coalesce_secs),
+        )),
+        status_tracker: Arc::new(StatusTracker::new()),
+        metrics: telemetry::prometheus_builder().build_recorder().handle(),
+        in_flight: Arc::new(AtomicUsize::new(0)),
+        config,
+    }
+}
+
+/// Auditor for calls made directly from tests, recording nowhere
+pub(crate) fn test_auditor() -> Auditor {
+    AuditLog::default().actor("test")
+}